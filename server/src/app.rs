@@ -1,6 +1,17 @@
+pub mod address;
 mod agent;
-mod agent_line_user;
+pub mod api_token;
 pub mod coordinates;
 pub mod core;
+pub mod geocoding_queue;
+pub mod history;
 pub mod jar;
+mod jar_agent;
+pub mod link;
+pub mod manage;
+pub mod membership;
+pub mod place_url;
+pub mod response;
+pub mod seed;
+pub mod session;
 pub mod user_action;
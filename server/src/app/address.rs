@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+/// Postal address of a place, split as far as the geocoder knows it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Address {
+    /// Street and number, such as 宇田川町13-7
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<String>,
+    /// Ward or city, such as 渋谷区
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub district: Option<String>,
+    /// Prefecture or state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub postal_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+}
+
+impl Address {
+    pub fn is_empty(&self) -> bool {
+        self.line.is_none() && self.district.is_none() && self.region.is_none()
+    }
+
+    /// Region, district and street, as shown next to the places
+    pub fn short(&self) -> Option<String> {
+        let parts: Vec<&str> = [&self.region, &self.district, &self.line]
+            .into_iter()
+            .filter_map(|part| part.as_deref())
+            .collect();
+        (!parts.is_empty()).then(|| parts.join(" "))
+    }
+
+    /// Whether the place lies in the named ward, city or region; "渋谷" is in 渋谷区
+    pub fn is_in(&self, area: &str) -> bool {
+        let area = area.trim();
+        !area.is_empty()
+            && [&self.district, &self.region]
+                .into_iter()
+                .flatten()
+                .any(|name| name.contains(area))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::address::Address;
+
+    #[test]
+    fn it_matches_the_district_or_region() {
+        let address = Address {
+            line: Some("宇田川町13-7".to_string()),
+            district: Some("渋谷区".to_string()),
+            region: Some("東京都".to_string()),
+            ..Address::default()
+        };
+        assert!(address.is_in("渋谷区"));
+        assert!(address.is_in(" 渋谷 "));
+        assert!(address.is_in("東京都"));
+        assert!(!address.is_in("新宿区"));
+        assert!(!address.is_in(""));
+        // Streets are not areas
+        assert!(!address.is_in("宇田川町"));
+        assert_eq!(
+            address.short().as_deref(),
+            Some("東京都 渋谷区 宇田川町13-7")
+        );
+        assert_eq!(Address::default().short(), None);
+    }
+}
@@ -1,68 +1,160 @@
 use crate::app::coordinates::Coordinates;
 use async_trait::async_trait;
 
-use crate::app::core::{Client, Meal, Place};
-use crate::bing::http::BingClient;
+use crate::app::core::{Client, DrawResult, Meal, Place};
+use crate::app::link::LinkSigner;
+use crate::app::response::Response;
 use crate::gcp::api::FirebaseApi;
+use crate::geocoding::api::Candidate;
 use crate::http::HttpResult;
 
+/// Jar actions; each returns the response to render on the platform the action came from
 #[async_trait]
 pub trait Agent {
-    async fn whoami(&self, client: &Client);
-    async fn refresh<T: FirebaseApi + Sync>(
+    async fn whoami(&self, client: &Client) -> Response;
+    /// Send the requesting user a signed link to the management page of the jar
+    async fn manage_link(&self, client: &Client, host: &str, signer: &LinkSigner) -> Response;
+    /// Send the requesting user a new token of the jar REST API
+    async fn issue_api_token<T: FirebaseApi + Sync>(
         &self,
         client: &Client,
         firebase_client: &T,
         host: &str,
-    );
+    ) -> Response;
+    async fn revoke_api_tokens<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response;
+    async fn refresh<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response;
     async fn try_draw<T: FirebaseApi + Sync>(
         &self,
         meal: Meal,
         client: &Client,
         firebase_client: &T,
-        host: &str,
         coordinates: &Option<Coordinates>,
-    );
+        area: Option<&str>,
+    ) -> (DrawResult, Response);
     async fn postpone<T: FirebaseApi + Sync>(
         &self,
         client: &Client,
         firebase_client: &T,
-        host: &str,
-        coordinates: Option<Coordinates>,
-    );
+    ) -> Response;
     async fn delete_current<T: FirebaseApi + Sync>(
         &self,
         client: &Client,
         firebase_client: &T,
-        host: &str,
-        coordinates: Option<Coordinates>,
-    );
+    ) -> Response;
     async fn archive_current<T: FirebaseApi + Sync>(
         &self,
         client: &Client,
         firebase_client: &T,
-        host: &str,
-        coordinates: Option<Coordinates>,
-    );
+    ) -> Response;
     async fn add_place<T: FirebaseApi + Sync>(
         &self,
         client: &Client,
         firebase_client: &T,
         place_name: &str,
         meals: Vec<Meal>,
-        host: &str,
-    ) -> HttpResult<Place>;
+    ) -> (HttpResult<Place>, Response);
 
-    async fn add_place_coordinates<T: FirebaseApi + Sync>(
+    /// Locate an added place from its geocoding candidates; several are offered to pick from,
+    /// none asks for a shared location
+    async fn locate_place<T: FirebaseApi + Sync>(
         &self,
         client: &Client,
         firebase_client: &T,
-        place_name: &Place,
-        host: &str,
-        bing_client: BingClient,
-    );
+        place: &Place,
+        candidates: Vec<Candidate>,
+    ) -> Response;
+
+    async fn choose_candidate<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        index: usize,
+    ) -> Response;
+
+    /// None of the candidates fits; wait for a shared location of the place instead
+    async fn reject_candidates<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response;
+
+    /// Add a place at known coordinates, such as those of a shared location or a map link
+    async fn add_place_at<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        place_name: &str,
+        coordinates: &Coordinates,
+        link: Option<&str>,
+    ) -> Response;
+
+    /// Handle a shared location; a titled one is offered to be added as a place
+    async fn update_location<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        latitude: f32,
+        longitude: f32,
+        title: Option<&str>,
+    ) -> Response;
+
+    async fn clear_location<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response;
+
+    /// Greeting of a new group or user explaining how to use the bot
+    async fn welcome(&self) -> Response;
+
+    /// Add the template places to the jar
+    async fn seed<T: FirebaseApi + Sync>(&self, client: &Client, firebase_client: &T) -> Response;
+
+    /// Show where the places of the jar are looked up, or change it from the command argument
+    async fn geocoding_region<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        argument: &str,
+    ) -> Response;
+
+    /// Draw around the saved location whose name has the id a button was sent with
+    async fn draw_from_saved<T: FirebaseApi + Sync>(
+        &self,
+        meal: Meal,
+        client: &Client,
+        firebase_client: &T,
+        location_id: &str,
+    ) -> Response;
+
+    /// List the saved locations of the jar, save the current origin under the argument name or
+    /// remove one with `削除 オフィス`
+    async fn saved_location<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        argument: &str,
+    ) -> Response;
 
-    async fn update_location(&self, client: &Client, host: &str, latitude: f32, longitude: f32);
+    async fn request_place_name<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response;
 
-    async fn clear_location(&self, client: &Client, host: &str);
+    /// Handle a free text message; returns the name of the place to add when the jar was waiting for it
+    async fn receive_input<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        text: &str,
+    ) -> Option<String>;
 }
@@ -0,0 +1,50 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+/// A token giving access to the REST API of one jar; only its hash is stored
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiToken {
+    pub created_at: u64,
+    pub created_by: Option<String>,
+}
+
+pub fn generate() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Key a token is stored under; url safe so it can be used in a Firebase path
+pub fn hash(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+/// Token of an `Authorization: Bearer <token>` header
+pub fn from_authorization(header: &str) -> Option<&str> {
+    let (scheme, token) = header.trim().split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::api_token::{from_authorization, generate, hash};
+
+    #[test]
+    fn it_hashes_bearer_tokens() {
+        let token = generate();
+        assert_eq!(token.len(), 43);
+        assert_ne!(generate(), token);
+        assert_eq!(hash(&token), hash(&token));
+        assert!(!hash(&token).contains(['/', '.', '+']));
+        assert_eq!(
+            from_authorization(&format!("Bearer {token}")),
+            Some(token.as_str())
+        );
+        assert_eq!(from_authorization("Basic abc"), None);
+        assert_eq!(from_authorization("Bearer "), None);
+    }
+}
@@ -1,6 +1,6 @@
 const EARTH_RADIUS: f32 = 6371000_f32;
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
 pub struct Coordinates {
     pub latitude: f32,
     pub longitude: f32,
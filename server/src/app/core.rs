@@ -1,100 +1,505 @@
+use tokio::sync::oneshot;
+
 use crate::app::agent::Agent;
 use crate::app::coordinates::Coordinates;
-use crate::bing::http::BingClient;
+use crate::app::geocoding_queue::{FirstAttempt, GeocodingQueue, GeocodingStore};
+use crate::app::jar::Jar;
+use crate::app::jar_agent::JarAgent;
+use crate::app::link::LinkSigner;
+use crate::app::membership;
+use crate::app::place_url::PlaceUrl;
+use crate::app::response::{Choices, Messenger, Reply, Response};
+use crate::app::session;
 use crate::gcp::api::FirebaseApi;
-use crate::line::http::{LineChannel, LineClient};
+use crate::line::http::LineChannel;
+use crate::slack::http::SlackChannel;
+use crate::telegram::http::TelegramChat;
+use crate::web::hub::WebRoom;
+
+// Line shows at most 13 quick replies, two of them per saved location
+const MAX_SAVED_LOCATION_CHOICES: usize = 4;
 
 #[derive(Debug)]
 pub enum Client {
     Line(LineChannel),
+    Slack(SlackChannel),
+    Telegram(TelegramChat),
+    Web(WebRoom),
+}
+
+impl Client {
+    pub(crate) fn user_id(&self) -> Option<&str> {
+        match self {
+            Client::Line(channel) => match channel {
+                LineChannel::User(id) => Some(id),
+                LineChannel::Room { user_id, .. } => user_id.as_deref(),
+                LineChannel::Group { user_id, .. } => user_id.as_deref(),
+            },
+            Client::Slack(channel) => channel.user_id.as_deref(),
+            Client::Telegram(chat) => chat.user_id.as_deref(),
+            Client::Web(room) => room.user_id.as_deref(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Action {
-    Add(Client, String, Vec<Meal>),
-    Draw(Client, Meal, Option<Coordinates>),
-    PostponeCurrent(Client, Option<Coordinates>),
-    ArchiveCurrent(Client, Option<Coordinates>),
-    RemoveCurrent(Client, Option<Coordinates>),
+    Add(Client, String, Vec<Meal>, Option<AddResponder>),
+    Draw(Client, Meal, Option<Coordinates>, Option<DrawResponder>),
+    /// Draw around a location saved in the jar, such as オフィス, or else among the places of a
+    /// ward, city or region, such as 渋谷区
+    DrawIn(Client, Meal, String),
+    /// Draw around the saved location a button was sent for, by the id of its name
+    DrawFromSaved(Client, Meal, String),
+    PostponeCurrent(Client),
+    ArchiveCurrent(Client),
+    RemoveCurrent(Client),
     Refresh(Client),
     WhoAmI(Client),
-    Location(Client, f32, f32),
+    Manage(Client),
+    IssueApiToken(Client),
+    RevokeApiTokens(Client),
+    /// A shared location, with the name of the spot when it has one
+    Location(Client, f32, f32, Option<String>),
+    /// Add a place at known coordinates, without geocoding its name
+    AddAt(Client, String, Coordinates),
     ClearLocation(Client),
+    RequestPlaceName(Client),
+    ChooseCandidate(Client, usize),
+    NoCandidate(Client),
+    Input(Client, String),
+    Join(Client),
+    Leave(Client),
+    MembersJoined(Client, Vec<String>),
+    MembersLeft(Client, Vec<String>),
+    Seed(Client),
+    /// Show or set the geocoding context of the jar, from the text after the command
+    Region(Client, String),
+    /// List, save or remove the named locations of the jar, from the text after the command
+    SavedLocation(Client, String),
 }
 
-#[derive(Debug, Clone)]
+/// What became of a place added from a web form
+#[derive(Debug)]
+pub enum AddOutcome {
+    /// Added and queued to be located, the chat hears about its location later; the first
+    /// attempt at locating it is heard here too, unless it could not be queued
+    Added(Place, Option<oneshot::Receiver<FirstAttempt>>),
+    Failed(String),
+}
+
+pub type AddResponder = oneshot::Sender<AddOutcome>;
+
+/// What a draw requested from the API gave
+#[derive(Debug)]
+pub enum DrawResult {
+    Drawn(Place),
+    AlreadyDrawn(Place),
+    NothingToDraw,
+    Failed(String),
+}
+
+pub type DrawResponder = oneshot::Sender<DrawResult>;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Meal {
     Lunch,
     Dinner,
 }
 
-#[derive(Debug, Clone)]
+/// Argument of a `地域 US en` text, empty when only asking for the current region
+pub fn parse_region(text: &str) -> Option<String> {
+    let text = text.trim();
+    let (command, argument) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    matches!(command.to_lowercase().as_str(), "地域" | "region")
+        .then(|| argument.trim().to_string())
+}
+
+/// Argument of a `場所 オフィス` text, empty when only listing the saved locations
+pub fn parse_saved_location(text: &str) -> Option<String> {
+    let text = text.trim();
+    let (command, argument) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    matches!(command.to_lowercase().as_str(), "場所" | "origin")
+        .then(|| argument.trim().to_string())
+}
+
+/// Meal and area of a text such as `引く 渋谷区`, lunch unless `引く(夜)` is written
+pub fn parse_area_draw(text: &str) -> Option<(Meal, String)> {
+    let text = text.trim();
+    let (command, area) = text.split_once(char::is_whitespace)?;
+    let meal = match command {
+        "引く" | "引く(昼)" | "引く（昼）" => Meal::Lunch,
+        "引く(夜)" | "引く（夜）" => Meal::Dinner,
+        _ => return None,
+    };
+    let area = area.trim();
+    (!area.is_empty()).then(|| (meal, area.to_string()))
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Place {
     pub key: String,
     pub name: String,
 }
 
-pub async fn handle_action<T: FirebaseApi + Sync>(
+pub async fn handle_action<T: FirebaseApi + Sync, M: Messenger + Sync, S: GeocodingStore + Sync>(
     action: (String, Action),
-    line_client: &LineClient,
+    messenger: &M,
     firebase_client: &T,
+    geocoding_queue: &GeocodingQueue<S>,
+    link_signer: &LinkSigner,
 ) {
     let (host, action) = action;
-    match action {
-        Action::Draw(source, meal, coordinates) => {
-            line_client
-                .try_draw(meal, &source, firebase_client, &host, &coordinates)
+    let agent = JarAgent;
+    let (source, response) = match action {
+        Action::Draw(source, meal, coordinates, responder) => {
+            let (result, response) = agent
+                .try_draw(meal, &source, firebase_client, &coordinates, None)
                 .await;
+            if let Some(responder) = responder {
+                let _ = responder.send(result);
+            }
+            (source, response)
         }
-        Action::PostponeCurrent(source, coordinates) => {
-            line_client
-                .postpone(&source, firebase_client, &host, coordinates)
+        Action::DrawIn(source, meal, area) => {
+            let (_, response) = agent
+                .try_draw(meal, &source, firebase_client, &None, Some(&area))
                 .await;
+            (source, response)
         }
-        Action::RemoveCurrent(source, coordinates) => {
-            line_client
-                .delete_current(&source, firebase_client, &host, coordinates)
+        Action::DrawFromSaved(source, meal, location_id) => {
+            let response = agent
+                .draw_from_saved(meal, &source, firebase_client, &location_id)
                 .await;
+            (source, response)
         }
-        Action::ArchiveCurrent(source, coordinates) => {
-            line_client
-                .archive_current(&source, firebase_client, &host, coordinates)
-                .await;
+        Action::PostponeCurrent(source) => {
+            let response = agent.postpone(&source, firebase_client).await;
+            (source, response)
+        }
+        Action::RemoveCurrent(source) => {
+            let response = agent.delete_current(&source, firebase_client).await;
+            (source, response)
+        }
+        Action::ArchiveCurrent(source) => {
+            let response = agent.archive_current(&source, firebase_client).await;
+            (source, response)
         }
         Action::Refresh(source) => {
-            line_client.refresh(&source, firebase_client, &host).await;
+            let response = agent.refresh(&source, firebase_client).await;
+            (source, response)
         }
         Action::WhoAmI(source) => {
-            line_client.whoami(&source).await;
+            let response = agent.whoami(&source).await;
+            (source, response)
+        }
+        Action::Manage(source) => {
+            let response = agent.manage_link(&source, &host, link_signer).await;
+            (source, response)
+        }
+        Action::IssueApiToken(source) => {
+            let response = agent.issue_api_token(&source, firebase_client, &host).await;
+            (source, response)
         }
-        Action::Add(source, place_name, meals) => {
-            let place = line_client
-                .add_place(&source, firebase_client, &place_name, meals, &host)
+        Action::RevokeApiTokens(source) => {
+            let response = agent.revoke_api_tokens(&source, firebase_client).await;
+            (source, response)
+        }
+        Action::Add(source, place_name, meals, responder) => {
+            let outcome = add(
+                &source,
+                &place_name,
+                meals,
+                &host,
+                messenger,
+                firebase_client,
+                geocoding_queue,
+            )
+            .await;
+            if let Some(responder) = responder {
+                let _ = responder.send(outcome);
+            }
+            return;
+        }
+        Action::Location(source, latitude, longitude, title) => {
+            let response = agent
+                .update_location(
+                    &source,
+                    firebase_client,
+                    latitude,
+                    longitude,
+                    title.as_deref(),
+                )
                 .await;
-            match place {
-                Ok(place) => {
-                    line_client
-                        .add_place_coordinates(
-                            &source,
-                            firebase_client,
-                            &place,
-                            &host,
-                            BingClient::default(),
-                        )
-                        .await;
-                }
+            (source, response)
+        }
+        Action::AddAt(source, place_name, coordinates) => {
+            let response = agent
+                .add_place_at(&source, firebase_client, &place_name, &coordinates, None)
+                .await;
+            (source, response)
+        }
+        Action::ClearLocation(source) => {
+            let response = agent.clear_location(&source, firebase_client).await;
+            (source, response)
+        }
+        Action::RequestPlaceName(source) => {
+            let response = agent.request_place_name(&source, firebase_client).await;
+            (source, response)
+        }
+        Action::ChooseCandidate(source, index) => {
+            let response = agent
+                .choose_candidate(&source, firebase_client, index)
+                .await;
+            (source, response)
+        }
+        Action::NoCandidate(source) => {
+            let response = agent.reject_candidates(&source, firebase_client).await;
+            (source, response)
+        }
+        Action::Join(source) => {
+            let jar: Jar = (&source).into();
+            // Web rooms join again each time their first browser connects
+            let welcome = match membership::activate(&jar, firebase_client).await {
+                Ok(welcome) => welcome,
                 Err(e) => {
-                    println!("{e:?}");
+                    println!("Could not activate {jar:?}: {e:?}");
+                    true
+                }
+            };
+            if let Client::Line(LineChannel::User(id)) = &source {
+                let _ = membership::members_joined(&jar, firebase_client, std::slice::from_ref(id))
+                    .await;
+            }
+            match messenger.chat_name(&source).await {
+                Ok(label) => {
+                    let _ = firebase_client.add_label(&jar, &label).await;
                 }
+                Err(e) => println!("Could not get a label for {jar:?}: {e:?}"),
             }
+            if !welcome {
+                return;
+            }
+            (source, agent.welcome().await)
+        }
+        Action::Leave(source) => {
+            let jar: Jar = (&source).into();
+            if let Err(e) = membership::deactivate(&jar, firebase_client, session::now()).await {
+                println!("Could not deactivate {jar:?}: {e:?}");
+            }
+            return;
         }
-        Action::Location(source, latitude, longitude) => {
-            line_client
-                .update_location(&source, &host, latitude, longitude)
+        Action::MembersJoined(source, user_ids) => {
+            let jar: Jar = (&source).into();
+            if let Err(e) = membership::members_joined(&jar, firebase_client, &user_ids).await {
+                println!("Could not add members to {jar:?}: {e:?}");
+            }
+            return;
+        }
+        Action::MembersLeft(source, user_ids) => {
+            let jar: Jar = (&source).into();
+            if let Err(e) = membership::members_left(&jar, firebase_client, &user_ids).await {
+                println!("Could not remove members from {jar:?}: {e:?}");
+            }
+            return;
+        }
+        Action::Seed(source) => {
+            let response = agent.seed(&source, firebase_client).await;
+            (source, response)
+        }
+        Action::Region(source, argument) => {
+            let response = agent
+                .geocoding_region(&source, firebase_client, &argument)
                 .await;
+            (source, response)
         }
-        Action::ClearLocation(source) => {
-            line_client.clear_location(&source, &host).await;
+        Action::SavedLocation(source, argument) => {
+            let response = agent
+                .saved_location(&source, firebase_client, &argument)
+                .await;
+            (source, response)
+        }
+        Action::Input(source, text) => {
+            let place_name = agent.receive_input(&source, firebase_client, &text).await;
+            if let Some(place_url) = PlaceUrl::parse(&text) {
+                add_from_url(
+                    &source,
+                    &place_url,
+                    &host,
+                    messenger,
+                    firebase_client,
+                    geocoding_queue,
+                )
+                .await;
+            } else if let Some(place_name) = place_name {
+                let meals = vec![Meal::Lunch, Meal::Dinner];
+                add(
+                    &source,
+                    &place_name,
+                    meals,
+                    &host,
+                    messenger,
+                    firebase_client,
+                    geocoding_queue,
+                )
+                .await;
+            }
+            return;
+        }
+    };
+    let response = with_saved_locations(&source, firebase_client, response).await;
+    messenger.respond(&source, &host, response).await;
+}
+
+// Offer to draw around the saved locations along the idle choices, so nobody has to share a
+// location every time
+async fn with_saved_locations<T: FirebaseApi + Sync>(
+    source: &Client,
+    firebase_client: &T,
+    mut response: Response,
+) -> Response {
+    let idle = |reply: &Reply| matches!(reply, Reply::Choices(_, Choices::Idle(None)));
+    if !response.replies.iter().any(idle) {
+        return response;
+    }
+    let jar: Jar = source.into();
+    let names: Vec<String> = match firebase_client.get_saved_locations(&jar).await {
+        Ok(locations) => locations
+            .into_keys()
+            .take(MAX_SAVED_LOCATION_CHOICES)
+            .collect(),
+        Err(e) => {
+            println!("Could not get the saved locations of {jar:?}: {e:?}");
+            return response;
+        }
+    };
+    if names.is_empty() {
+        return response;
+    }
+    for reply in response.replies.iter_mut() {
+        if let Reply::Choices(_, choices @ Choices::Idle(None)) = reply {
+            *choices = Choices::SavedLocations(names.clone());
         }
     }
+    response
+}
+
+async fn add<T: FirebaseApi + Sync, M: Messenger + Sync, S: GeocodingStore + Sync>(
+    source: &Client,
+    place_name: &str,
+    meals: Vec<Meal>,
+    host: &str,
+    messenger: &M,
+    firebase_client: &T,
+    geocoding_queue: &GeocodingQueue<S>,
+) -> AddOutcome {
+    let agent = JarAgent;
+    let (place, response) = agent
+        .add_place(source, firebase_client, place_name, meals)
+        .await;
+    messenger.respond(source, host, response).await;
+    match place {
+        Ok(place) => {
+            let jar: Jar = source.into();
+            let first_attempt = match geocoding_queue.enqueue_watched(&jar, &place, host).await {
+                Ok(first_attempt) => Some(first_attempt),
+                Err(e) => {
+                    println!("Could not queue {place:?} to be located: {e:?}");
+                    None
+                }
+            };
+            AddOutcome::Added(place, first_attempt)
+        }
+        Err(e) => {
+            println!("{e:?}");
+            AddOutcome::Failed(e.to_string())
+        }
+    }
+}
+
+// Add the place a pasted map or guide link points to, geocoding it only when the link has no
+// coordinates
+async fn add_from_url<T: FirebaseApi + Sync, M: Messenger + Sync, S: GeocodingStore + Sync>(
+    source: &Client,
+    place_url: &PlaceUrl,
+    host: &str,
+    messenger: &M,
+    firebase_client: &T,
+    geocoding_queue: &GeocodingQueue<S>,
+) {
+    let agent = JarAgent;
+    let Some(place_name) = &place_url.name else {
+        let response =
+            Response::text("リンクから店名が読めませんでした。「+ 加」から店名で追加してください");
+        messenger.respond(source, host, response.private()).await;
+        return;
+    };
+    if let Some(coordinates) = &place_url.coordinates {
+        let response = agent
+            .add_place_at(
+                source,
+                firebase_client,
+                place_name,
+                coordinates,
+                Some(&place_url.url),
+            )
+            .await;
+        messenger.respond(source, host, response).await;
+        return;
+    }
+    let meals = vec![Meal::Lunch, Meal::Dinner];
+    let outcome = add(
+        source,
+        place_name,
+        meals,
+        host,
+        messenger,
+        firebase_client,
+        geocoding_queue,
+    )
+    .await;
+    if let AddOutcome::Added(place, _) = outcome {
+        let jar: Jar = source.into();
+        let _ = firebase_client
+            .set_place_link(&jar, &place, &place_url.url)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::core::{handle_action, Action, Client};
+    use crate::app::geocoding_queue::fixtures::MemoryGeocodingStore;
+    use crate::app::geocoding_queue::GeocodingQueue;
+    use crate::app::jar::Jar;
+    use crate::app::link::LinkSigner;
+    use crate::app::response::fixtures::RecordingMessenger;
+    use crate::gcp::api::fixtures::MemoryFirebase;
+    use crate::gcp::api::FirebaseApi;
+    use crate::line::http::LineChannel;
+
+    async fn send_text(firebase_client: &MemoryFirebase, text: &str) -> usize {
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let jar = Jar::from(&client);
+        handle_action(
+            ("host".to_string(), Action::Input(client, text.to_string())),
+            &RecordingMessenger::default(),
+            firebase_client,
+            &GeocodingQueue::new(MemoryGeocodingStore::default()),
+            &LinkSigner::new(b"secret"),
+        )
+        .await;
+        firebase_client.get_all_places(&jar).await.unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn it_adds_places_only_from_messages_made_of_a_link() {
+        let firebase_client = MemoryFirebase::default();
+        let url = "https://www.google.com/maps/place/%E4%B8%80%E8%98%AD/@35.6611,139.6982,17z";
+        assert_eq!(
+            send_text(&firebase_client, &format!("ここ行かない？ {url}")).await,
+            0
+        );
+        assert_eq!(send_text(&firebase_client, &format!("{url}\n")).await, 1);
+    }
 }
@@ -0,0 +1,609 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, Notify};
+
+use crate::app::agent::Agent;
+use crate::app::core::{Client, Place};
+use crate::app::jar::Jar;
+use crate::app::jar_agent::{save_candidate, JarAgent};
+use crate::app::response::{Messenger, Response};
+use crate::app::session;
+use crate::gcp::api::FirebaseApi;
+use crate::geocoding::api::{Candidate, Geocoder, GeocodingError};
+use crate::geocoding::context::GeocodingContext;
+use crate::http::{ApiError, HttpResult};
+
+// Addresses offered when the name of an added place is ambiguous
+const MAX_CANDIDATES: usize = 4;
+// Delay before the first retry, doubled after each failure
+const RETRY_DELAY_SECONDS: u64 = 60;
+const MAX_ATTEMPTS: u32 = 5;
+// The queue is also read on a timer, for the retries and the jobs left by a restart
+const POLL_INTERVAL_SECONDS: u64 = 60;
+const CACHE_TTL_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+/// A place waiting for its coordinates
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GeocodingJob {
+    pub jar: String,
+    pub place: Place,
+    /// Host the links of the notification point to
+    pub host: String,
+    /// Backfills take the best match without telling the chat
+    pub notify: bool,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+}
+
+impl GeocodingJob {
+    fn id(&self) -> String {
+        format!("{}_{}", self.jar, self.place.key)
+    }
+}
+
+/// How the first attempt at locating a place went, for the page that added it
+#[derive(Debug, Clone, PartialEq)]
+pub enum FirstAttempt {
+    Located(Candidate),
+    /// The chat is asked to pick among that many candidates
+    Ambiguous(usize),
+    NotFound,
+    Retrying,
+}
+
+/// Candidates found for a query, shared by every jar
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CachedCandidates {
+    pub candidates: Vec<Candidate>,
+    pub cached_at: u64,
+}
+
+/// Persistent geocoding queue and cache
+#[async_trait]
+pub trait GeocodingStore {
+    async fn get_jobs(&self) -> HttpResult<Vec<GeocodingJob>>;
+
+    async fn put_job(&self, id: &str, job: &GeocodingJob) -> HttpResult<()>;
+
+    async fn remove_job(&self, id: &str) -> HttpResult<()>;
+
+    async fn get_cached_candidates(&self, key: &str) -> HttpResult<Option<CachedCandidates>>;
+
+    async fn cache_candidates(&self, key: &str, cached: &CachedCandidates) -> HttpResult<()>;
+}
+
+// Hashed as queries may be long and hold characters the datastore refuses in keys; the same
+// name is cached per context
+fn cache_key(query: &str, context: &GeocodingContext) -> String {
+    let context = serde_json::to_string(context).unwrap_or_default();
+    let key = format!("{}|{context}", query.trim().to_lowercase());
+    URL_SAFE_NO_PAD.encode(digest::digest(&digest::SHA256, key.as_bytes()))
+}
+
+// Backfill jobs never notify, so nothing reaches it
+struct Unnotified;
+
+#[async_trait]
+impl Messenger for Unnotified {
+    async fn respond(&self, client: &Client, _host: &str, _response: Response) {
+        println!("Backfills do not answer {client:?}");
+    }
+
+    async fn chat_name(&self, client: &Client) -> HttpResult<String> {
+        Err(ApiError::Unknown {
+            message: format!("Backfills do not name {client:?}"),
+        })
+    }
+}
+
+/// Locates the added places out of the action loop, so a slow geocoder only delays its own
+/// notifications
+pub struct GeocodingQueue<S: GeocodingStore> {
+    store: S,
+    wake: Notify,
+    // Waiting for the first attempt at a job, by job id
+    watchers: Mutex<HashMap<String, oneshot::Sender<FirstAttempt>>>,
+}
+
+impl<S: GeocodingStore + Sync> GeocodingQueue<S> {
+    pub fn new(store: S) -> Self {
+        GeocodingQueue {
+            store,
+            wake: Notify::new(),
+            watchers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn enqueue(
+        &self,
+        jar: &Jar,
+        place: &Place,
+        host: &str,
+        notify: bool,
+    ) -> HttpResult<()> {
+        let job = GeocodingJob {
+            jar: jar.to_string(),
+            place: place.clone(),
+            host: host.to_string(),
+            notify,
+            attempts: 0,
+            next_attempt_at: session::now(),
+        };
+        self.store.put_job(&job.id(), &job).await?;
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// Queue a place to be located, and hear how the first attempt went; nothing is heard
+    /// when the job is left to another instance or a restart
+    pub async fn enqueue_watched(
+        &self,
+        jar: &Jar,
+        place: &Place,
+        host: &str,
+    ) -> HttpResult<oneshot::Receiver<FirstAttempt>> {
+        let id = format!("{jar}_{}", place.key);
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut watchers = self.watchers.lock().unwrap();
+            // Nobody waits on jobs run elsewhere once their page gave up
+            watchers.retain(|_, watcher| !watcher.is_closed());
+            // Watched before the job is put, as the worker may run it right away
+            watchers.insert(id.clone(), sender);
+        }
+        if let Err(e) = self.enqueue(jar, place, host, true).await {
+            self.watchers.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        Ok(receiver)
+    }
+
+    fn report(&self, job: &GeocodingJob, attempt: FirstAttempt) {
+        if let Some(watcher) = self.watchers.lock().unwrap().remove(&job.id()) {
+            let _ = watcher.send(attempt);
+        }
+    }
+
+    async fn candidates<G: Geocoder + Sync>(
+        &self,
+        geocoder: &G,
+        query: &str,
+        context: &GeocodingContext,
+    ) -> Result<Vec<Candidate>, GeocodingError> {
+        let key = cache_key(query, context);
+        let now = session::now();
+        match self.store.get_cached_candidates(&key).await {
+            Ok(Some(cached)) if cached.cached_at + CACHE_TTL_SECONDS > now => {
+                return Ok(cached.candidates)
+            }
+            Ok(_) => {}
+            Err(e) => println!("Could not read the geocoding cache for {query}: {e:?}"),
+        }
+        let candidates = geocoder.candidates(query, context, MAX_CANDIDATES).await?;
+        // Misses are not cached, the place may be known to the geocoder later on
+        if !candidates.is_empty() {
+            let cached = CachedCandidates {
+                candidates: candidates.clone(),
+                cached_at: now,
+            };
+            if let Err(e) = self.store.cache_candidates(&key, &cached).await {
+                println!("Could not cache the candidates of {query}: {e:?}");
+            }
+        }
+        Ok(candidates)
+    }
+
+    async fn resolve<T: FirebaseApi + Sync, M: Messenger + Sync>(
+        &self,
+        job: &GeocodingJob,
+        candidates: Vec<Candidate>,
+        firebase_client: &T,
+        messenger: &M,
+    ) {
+        let jar = Jar::new(&job.jar);
+        if !job.notify {
+            if let Some(candidate) = candidates.first() {
+                save_candidate(&jar, firebase_client, &job.place, candidate).await;
+            }
+            return;
+        }
+        match jar.client() {
+            Ok(client) => {
+                let response = JarAgent
+                    .locate_place(&client, firebase_client, &job.place, candidates)
+                    .await;
+                messenger.respond(&client, &job.host, response).await;
+            }
+            Err(_) => println!("No chat to notify for {jar:?}"),
+        }
+    }
+
+    /// Geocode the jobs due now; returns how many jobs are left for later
+    pub async fn run_due_jobs<T: FirebaseApi + Sync, M: Messenger + Sync, G: Geocoder + Sync>(
+        &self,
+        firebase_client: &T,
+        messenger: &M,
+        geocoder: &G,
+    ) -> HttpResult<usize> {
+        self.run_jobs(|_| true, firebase_client, messenger, geocoder)
+            .await
+    }
+
+    /// Geocode the due backfill jobs of the jar, leaving every other job to the server; returns
+    /// how many of them are left for later
+    pub async fn run_due_backfills<T: FirebaseApi + Sync, G: Geocoder + Sync>(
+        &self,
+        jar: &Jar,
+        firebase_client: &T,
+        geocoder: &G,
+    ) -> HttpResult<usize> {
+        let jar = jar.to_string();
+        self.run_jobs(
+            |job| job.jar == jar && !job.notify,
+            firebase_client,
+            &Unnotified,
+            geocoder,
+        )
+        .await
+    }
+
+    async fn run_jobs<
+        F: Fn(&GeocodingJob) -> bool + Sync,
+        T: FirebaseApi + Sync,
+        M: Messenger + Sync,
+        G: Geocoder + Sync,
+    >(
+        &self,
+        selected: F,
+        firebase_client: &T,
+        messenger: &M,
+        geocoder: &G,
+    ) -> HttpResult<usize> {
+        let now = session::now();
+        let mut left = 0;
+        for job in self.store.get_jobs().await? {
+            if !selected(&job) {
+                continue;
+            }
+            if job.next_attempt_at > now {
+                left += 1;
+                continue;
+            }
+            let context = firebase_client
+                .get_geocoding_context(&Jar::new(&job.jar))
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or_default();
+            match self.candidates(geocoder, &job.place.name, &context).await {
+                Ok(candidates) => {
+                    self.store.remove_job(&job.id()).await?;
+                    let attempt = match &candidates[..] {
+                        [] => FirstAttempt::NotFound,
+                        [candidate] => FirstAttempt::Located(candidate.clone()),
+                        candidates => FirstAttempt::Ambiguous(candidates.len()),
+                    };
+                    self.resolve(&job, candidates, firebase_client, messenger)
+                        .await;
+                    self.report(&job, attempt);
+                }
+                Err(e) if job.attempts + 1 >= MAX_ATTEMPTS => {
+                    println!("Gave up geocoding {:?}: {e}", job.place);
+                    self.store.remove_job(&job.id()).await?;
+                    self.resolve(&job, vec![], firebase_client, messenger).await;
+                    self.report(&job, FirstAttempt::NotFound);
+                }
+                Err(e) => {
+                    println!("Could not geocode {:?}, will retry: {e}", job.place);
+                    let retry = GeocodingJob {
+                        attempts: job.attempts + 1,
+                        next_attempt_at: now + (RETRY_DELAY_SECONDS << job.attempts),
+                        ..job
+                    };
+                    self.store.put_job(&retry.id(), &retry).await?;
+                    self.report(&retry, FirstAttempt::Retrying);
+                    left += 1;
+                }
+            }
+        }
+        Ok(left)
+    }
+
+    /// Process the queue whenever a job is added, and on a timer for the retries
+    pub async fn run<T: FirebaseApi + Sync, M: Messenger + Sync, G: Geocoder + Sync>(
+        &self,
+        firebase_client: &T,
+        messenger: &M,
+        geocoder: &G,
+    ) {
+        loop {
+            if let Err(e) = self
+                .run_due_jobs(firebase_client, messenger, geocoder)
+                .await
+            {
+                println!("Could not run the geocoding jobs: {e:?}");
+            }
+            let _ = tokio::time::timeout(
+                Duration::from_secs(POLL_INTERVAL_SECONDS),
+                self.wake.notified(),
+            )
+            .await;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod fixtures {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use crate::app::geocoding_queue::{CachedCandidates, GeocodingJob, GeocodingStore};
+    use crate::http::HttpResult;
+
+    #[derive(Default)]
+    pub struct MemoryGeocodingStore {
+        pub jobs: Mutex<HashMap<String, GeocodingJob>>,
+        pub cache: Mutex<HashMap<String, CachedCandidates>>,
+    }
+
+    #[async_trait]
+    impl GeocodingStore for MemoryGeocodingStore {
+        async fn get_jobs(&self) -> HttpResult<Vec<GeocodingJob>> {
+            Ok(self.jobs.lock().unwrap().values().cloned().collect())
+        }
+
+        async fn put_job(&self, id: &str, job: &GeocodingJob) -> HttpResult<()> {
+            self.jobs
+                .lock()
+                .unwrap()
+                .insert(id.to_string(), job.clone());
+            Ok(())
+        }
+
+        async fn remove_job(&self, id: &str) -> HttpResult<()> {
+            self.jobs.lock().unwrap().remove(id);
+            Ok(())
+        }
+
+        async fn get_cached_candidates(&self, key: &str) -> HttpResult<Option<CachedCandidates>> {
+            Ok(self.cache.lock().unwrap().get(key).cloned())
+        }
+
+        async fn cache_candidates(&self, key: &str, cached: &CachedCandidates) -> HttpResult<()> {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), cached.clone());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use crate::app::address::Address;
+    use crate::app::agent::Agent;
+    use crate::app::coordinates::Coordinates;
+    use crate::app::core::{Client, Meal};
+    use crate::app::geocoding_queue::fixtures::MemoryGeocodingStore;
+    use crate::app::geocoding_queue::{cache_key, FirstAttempt, GeocodingQueue};
+    use crate::app::jar::Jar;
+    use crate::app::jar_agent::JarAgent;
+    use crate::app::response::fixtures::RecordingMessenger;
+    use crate::app::session;
+    use crate::gcp::api::fixtures::MemoryFirebase;
+    use crate::gcp::api::FirebaseApi;
+    use crate::geocoding::api::{Candidate, Geocoder, GeocodingError};
+    use crate::geocoding::context::GeocodingContext;
+    use crate::line::http::LineChannel;
+
+    // Fails the first calls, then finds a single address
+    struct FlakyGeocoder {
+        failures: usize,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Geocoder for FlakyGeocoder {
+        async fn candidates(
+            &self,
+            _query: &str,
+            _context: &GeocodingContext,
+            _limit: usize,
+        ) -> Result<Vec<Candidate>, GeocodingError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(GeocodingError("timeout".to_string()));
+            }
+            Ok(vec![Candidate {
+                address: "宇田川町13-7".to_string(),
+                details: Address {
+                    district: Some("渋谷区".to_string()),
+                    ..Address::default()
+                },
+                coordinates: Coordinates {
+                    latitude: 35.661,
+                    longitude: 139.698,
+                },
+            }])
+        }
+    }
+
+    #[tokio::test]
+    async fn it_retries_then_notifies_and_caches() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let jar = Jar::from(&client);
+        let (place, _) = JarAgent
+            .add_place(&client, &firebase_client, "一蘭", vec![Meal::Lunch])
+            .await;
+        let place = place.unwrap();
+        let queue = GeocodingQueue::new(MemoryGeocodingStore::default());
+        let geocoder = FlakyGeocoder {
+            failures: 1,
+            calls: AtomicUsize::new(0),
+        };
+        let recorder = RecordingMessenger::default();
+
+        queue.enqueue(&jar, &place, "host", true).await.unwrap();
+        let left = queue
+            .run_due_jobs(&firebase_client, &recorder, &geocoder)
+            .await
+            .unwrap();
+        assert_eq!(left, 1);
+        assert!(recorder.0.lock().unwrap().is_empty());
+        let job = queue.store.jobs.lock().unwrap().values().next().cloned();
+        let job = job.unwrap();
+        assert_eq!(job.attempts, 1);
+        assert!(job.next_attempt_at > session::now());
+
+        // Not due yet
+        queue
+            .run_due_jobs(&firebase_client, &recorder, &geocoder)
+            .await
+            .unwrap();
+        assert_eq!(geocoder.calls.load(Ordering::SeqCst), 1);
+
+        queue
+            .store
+            .jobs
+            .lock()
+            .unwrap()
+            .values_mut()
+            .for_each(|job| job.next_attempt_at = 0);
+        let left = queue
+            .run_due_jobs(&firebase_client, &recorder, &geocoder)
+            .await
+            .unwrap();
+        assert_eq!(left, 0);
+        let texts: Vec<String> = recorder
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|response| format!("{:?}", response.replies))
+            .collect();
+        assert_eq!(texts.len(), 1);
+        assert!(texts[0].contains("「一蘭」の位置が見つかりました"));
+        firebase_client.with_jar(&jar, |jar| {
+            let coordinates = jar.places[&place.key].coordinates.clone();
+            assert_eq!(coordinates.map(|c| c.latitude), Some(35.661));
+            let district = jar.places[&place.key]
+                .address
+                .clone()
+                .and_then(|a| a.district);
+            assert_eq!(district.as_deref(), Some("渋谷区"));
+        });
+
+        // The same name elsewhere is served from the cache, silently for backfills
+        let other = Jar::from(&Client::Line(LineChannel::User("U2".to_string())));
+        queue.enqueue(&other, &place, "", false).await.unwrap();
+        queue
+            .run_due_jobs(&firebase_client, &recorder, &geocoder)
+            .await
+            .unwrap();
+        assert_eq!(geocoder.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(recorder.0.lock().unwrap().len(), 1);
+
+        // But not for a jar looking its places up in another country
+        let abroad = Jar::from(&Client::Line(LineChannel::User("U3".to_string())));
+        let france = GeocodingContext::parse("FR fr").unwrap();
+        firebase_client
+            .set_geocoding_context(&abroad, &france)
+            .await
+            .unwrap();
+        queue.enqueue(&abroad, &place, "", false).await.unwrap();
+        queue
+            .run_due_jobs(&firebase_client, &recorder, &geocoder)
+            .await
+            .unwrap();
+        assert_eq!(geocoder.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn it_runs_only_the_backfill_of_a_jar() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let jar = Jar::from(&client);
+        let other = Jar::from(&Client::Line(LineChannel::User("U2".to_string())));
+        let (place, _) = JarAgent
+            .add_place(&client, &firebase_client, "一蘭", vec![Meal::Lunch])
+            .await;
+        let place = place.unwrap();
+        let queue = GeocodingQueue::new(MemoryGeocodingStore::default());
+        let geocoder = FlakyGeocoder {
+            failures: 0,
+            calls: AtomicUsize::new(0),
+        };
+
+        queue.enqueue(&jar, &place, "", false).await.unwrap();
+        queue.enqueue(&other, &place, "host", true).await.unwrap();
+        let left = queue
+            .run_due_backfills(&jar, &firebase_client, &geocoder)
+            .await
+            .unwrap();
+        assert_eq!(left, 0);
+        let jobs: Vec<String> = queue.store.jobs.lock().unwrap().keys().cloned().collect();
+        assert_eq!(jobs, vec![format!("{other}_{}", place.key)]);
+    }
+
+    #[tokio::test]
+    async fn it_reports_the_first_attempt_to_its_watcher() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let jar = Jar::from(&client);
+        let (place, _) = JarAgent
+            .add_place(&client, &firebase_client, "一蘭", vec![Meal::Lunch])
+            .await;
+        let place = place.unwrap();
+        let queue = GeocodingQueue::new(MemoryGeocodingStore::default());
+        let geocoder = FlakyGeocoder {
+            failures: 1,
+            calls: AtomicUsize::new(0),
+        };
+        let recorder = RecordingMessenger::default();
+
+        let attempt = queue.enqueue_watched(&jar, &place, "host").await.unwrap();
+        queue
+            .run_due_jobs(&firebase_client, &recorder, &geocoder)
+            .await
+            .unwrap();
+        assert_eq!(attempt.await.unwrap(), FirstAttempt::Retrying);
+
+        queue.store.jobs.lock().unwrap().clear();
+        let attempt = queue.enqueue_watched(&jar, &place, "host").await.unwrap();
+        queue
+            .run_due_jobs(&firebase_client, &recorder, &geocoder)
+            .await
+            .unwrap();
+        let attempt = attempt.await.unwrap();
+        assert!(matches!(attempt, FirstAttempt::Located(c) if c.address == "宇田川町13-7"));
+        assert!(queue.watchers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_hashes_the_cache_keys() {
+        let context = GeocodingContext::default();
+        let key = cache_key(&"一蘭 渋谷店 ".repeat(100), &context);
+        assert_eq!(key.len(), 43);
+        assert!(key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(
+            key,
+            cache_key(&"一蘭 渋谷店 ".repeat(100).to_uppercase(), &context)
+        );
+    }
+}
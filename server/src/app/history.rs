@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::app::core::Place;
+
+/// What happened to a drawn place
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DrawOutcome {
+    Drawn,
+    Postponed,
+    Archived,
+    Deleted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub place_key: String,
+    pub place_name: String,
+    pub outcome: DrawOutcome,
+    pub at: u64,
+}
+
+impl HistoryEntry {
+    pub fn new(place: &Place, outcome: DrawOutcome, at: u64) -> Self {
+        HistoryEntry {
+            place_key: place.key.clone(),
+            place_name: place.name.clone(),
+            outcome,
+            at,
+        }
+    }
+}
+
+/// Last visit of each place name: its latest draw that was not postponed.
+/// Names are used rather than keys so a place added again after being archived keeps its visits.
+pub fn last_visits(entries: &[HistoryEntry]) -> HashMap<String, u64> {
+    let mut entries: Vec<&HistoryEntry> = entries.iter().collect();
+    entries.sort_by_key(|entry| entry.at);
+    // Visit each pending draw replaced, restored if the draw is postponed
+    let mut replaced: HashMap<&str, Option<u64>> = HashMap::new();
+    let mut visits = HashMap::new();
+    for entry in entries {
+        let name = entry.place_name.as_str();
+        match entry.outcome {
+            DrawOutcome::Drawn => {
+                replaced.insert(name, visits.insert(name.to_string(), entry.at));
+            }
+            DrawOutcome::Postponed => match replaced.remove(name) {
+                Some(Some(previous)) => {
+                    visits.insert(name.to_string(), previous);
+                }
+                Some(None) => {
+                    visits.remove(name);
+                }
+                None => {}
+            },
+            DrawOutcome::Archived | DrawOutcome::Deleted => {
+                replaced.remove(name);
+            }
+        }
+    }
+    visits
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::core::Place;
+    use crate::app::history::{last_visits, DrawOutcome, HistoryEntry};
+
+    fn entry(name: &str, outcome: DrawOutcome, at: u64) -> HistoryEntry {
+        let place = Place {
+            key: format!("key_{at}"),
+            name: name.to_string(),
+        };
+        HistoryEntry::new(&place, outcome, at)
+    }
+
+    #[test]
+    fn it_does_not_count_postponed_draws_as_visits() {
+        let visits = last_visits(&[
+            entry("一蘭", DrawOutcome::Drawn, 10),
+            entry("一蘭", DrawOutcome::Archived, 11),
+            entry("松屋", DrawOutcome::Drawn, 20),
+            entry("一蘭", DrawOutcome::Drawn, 30),
+            entry("一蘭", DrawOutcome::Postponed, 31),
+            entry("松屋", DrawOutcome::Drawn, 5),
+        ]);
+        assert_eq!(visits.get("一蘭"), Some(&10));
+        assert_eq!(visits.get("松屋"), Some(&20));
+    }
+}
@@ -1,6 +1,9 @@
 use crate::app::core::Client;
-use crate::app::core::Client::Line;
+use crate::app::core::Client::{Line, Slack, Telegram, Web};
 use crate::line::http::LineChannel;
+use crate::slack::http::SlackChannel;
+use crate::telegram::http::TelegramChat;
+use crate::web::hub::WebRoom;
 use std::fmt::{Debug, Display, Formatter};
 
 pub struct Jar(String);
@@ -39,6 +42,9 @@ impl From<&Client> for Jar {
                 LineChannel::Room { id, .. } => format!("room_{id}"),
                 LineChannel::Group { id, .. } => format!("group_{id}"),
             },
+            Slack(channel) => format!("slack_{}_{}", channel.team_id, channel.channel_id),
+            Telegram(chat) => format!("telegram_{}", chat.chat_id),
+            Web(room) => format!("web_{}", room.room_id),
         };
         Jar::new(&jar_key)
     }
@@ -49,6 +55,29 @@ impl Jar {
         Jar(name.to_string())
     }
 
+    /// Chat of the jar, without any requesting user
+    pub fn client(&self) -> Result<Client, JarError> {
+        match self.0.split_once('_') {
+            Some(("slack", ids)) => {
+                let (team_id, channel_id) = ids.split_once('_').ok_or(JarError)?;
+                Ok(Slack(SlackChannel {
+                    team_id: team_id.to_string(),
+                    channel_id: channel_id.to_string(),
+                    user_id: None,
+                }))
+            }
+            Some(("telegram", chat_id)) => Ok(Telegram(TelegramChat {
+                chat_id: chat_id.to_string(),
+                user_id: None,
+            })),
+            Some(("web", room_id)) => Ok(Web(WebRoom {
+                room_id: room_id.to_string(),
+                user_id: None,
+            })),
+            _ => self.line_channel().map(Line),
+        }
+    }
+
     pub fn line_channel(&self) -> Result<LineChannel, JarError> {
         let parts = self.0.split_once('_');
         parts.ok_or(JarError).and_then(|(prefix, id)| {
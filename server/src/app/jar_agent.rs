@@ -0,0 +1,1304 @@
+use async_trait::async_trait;
+
+use crate::app::agent::Agent;
+use crate::app::api_token;
+use crate::app::api_token::ApiToken;
+use crate::app::coordinates::Coordinates;
+use crate::app::core::{Client, DrawResult, Meal, Place};
+use crate::app::history::{DrawOutcome, HistoryEntry};
+use crate::app::jar::Jar;
+use crate::app::link::{page_url, LinkPurpose, LinkSigner};
+use crate::app::response::{Choices, Response};
+use crate::app::seed::load_seed_places;
+use crate::app::session;
+use crate::app::session::{PendingInput, Session, SessionEvent};
+use crate::app::user_action::saved_location_id;
+use crate::gcp::api::FirebaseApi;
+use crate::geocoding::api::Candidate;
+use crate::geocoding::context::GeocodingContext;
+use crate::http::HttpResult;
+
+// Short enough to fit the quick reply labels, without the characters Firebase keys reject nor
+// those of the url syntax
+const MAX_LOCATION_NAME_LENGTH: usize = 12;
+const FORBIDDEN_KEY_CHARACTERS: [char; 8] = ['.', '$', '#', '[', ']', '/', '?', '%'];
+
+const WELCOME_MESSAGE: &str = "よろしくお願いします！
+みんなで行きたい店を登録して、ランダムに行き先を決めます。
+
++ 加 (追加): 店を追加
+🎲 昼 / 🎲 夜 (引く): 昼・夜の店を引く
+📍: 位置を送ると近くの店から引く
+「引く 渋谷区」: 区や市の店から引く（夜は「引く(夜) 渋谷区」）
+「場所 オフィス」: 送った位置を保存して、次から🎲 昼📍オフィスで近くの店から引く
+
+店が出たら:
+✓ 完 (完食): 行ってきた店を外す
+📅 延 (延期): 今回は見送る
+❌ 削 (削除): 店を削除する
+
+🌱 例: サンプルの店を追加
+「管理」と送ると店の一覧・編集ページのリンクが届きます
+「api」と送るとAPIトークンが届きます（「api revoke」で無効化）
+「場所」と送ると保存した場所の一覧が届きます（「場所 削除 オフィス」で削除）
+「地域」と送ると店を探す国・言語を確認・変更できます";
+
+async fn get_current_draw<T: FirebaseApi + Sync>(
+    client: &Client,
+    firebase_client: &T,
+) -> (Jar, HttpResult<Option<Place>>) {
+    let jar: Jar = client.into();
+    let draw = firebase_client.get_current_draw(&jar).await;
+    (jar, draw)
+}
+
+async fn get_session<T: FirebaseApi + Sync>(jar: &Jar, firebase_client: &T) -> Session {
+    match firebase_client.get_session(jar).await {
+        Ok(session) => session.unwrap_or_default().at(session::now()),
+        Err(e) => {
+            println!("Could not get session for {jar:?}: {e:?}");
+            Session::default()
+        }
+    }
+}
+
+async fn save_session<T: FirebaseApi + Sync>(jar: &Jar, firebase_client: &T, session: &Session) {
+    if let Err(e) = firebase_client.set_session(jar, session).await {
+        println!("Could not save session for {jar:?}: {e:?}");
+    }
+}
+
+// Apply the event to the session; an invalid transition means the session drifted from the
+// stored draw so it is reconciled instead
+async fn transition<T: FirebaseApi + Sync>(
+    jar: &Jar,
+    firebase_client: &T,
+    session: Session,
+    event: SessionEvent,
+) -> Session {
+    let now = session::now();
+    let session = match session.clone().apply(event, now) {
+        Ok(session) => session,
+        Err(e) => {
+            println!("Invalid session transition for {jar:?}: {e}");
+            let draw = firebase_client.get_current_draw(jar).await.ok().flatten();
+            session.reconcile(draw, now)
+        }
+    };
+    save_session(jar, firebase_client, &session).await;
+    session
+}
+
+async fn record_history<T: FirebaseApi + Sync>(
+    jar: &Jar,
+    firebase_client: &T,
+    place: &Place,
+    outcome: DrawOutcome,
+) {
+    let entry = HistoryEntry::new(place, outcome, session::now());
+    if let Err(e) = firebase_client.append_history(jar, &entry).await {
+        println!("Could not record {entry:?} for {jar:?}: {e:?}");
+    }
+}
+
+// Follow a draw announcement with the address of the place and the link it was added from
+async fn with_details<T: FirebaseApi + Sync>(
+    jar: &Jar,
+    firebase_client: &T,
+    place: &Place,
+    mut message: String,
+) -> String {
+    if let Ok(Some(address)) = firebase_client.get_place_address(jar, place).await {
+        if let Some(address) = address.short() {
+            message = format!("{message}\n{address}");
+        }
+    }
+    match firebase_client.get_place_link(jar, place).await {
+        Ok(Some(link)) => format!("{message}\n{link}"),
+        _ => message,
+    }
+}
+
+/// Save the coordinates of a geocoding candidate, with its address when known
+pub(crate) async fn save_candidate<T: FirebaseApi + Sync>(
+    jar: &Jar,
+    firebase_client: &T,
+    place: &Place,
+    candidate: &Candidate,
+) {
+    let _ = firebase_client
+        .set_place_coordinates(jar, place, &candidate.coordinates)
+        .await;
+    if !candidate.details.is_empty() {
+        let _ = firebase_client
+            .set_place_address(jar, place, &candidate.details)
+            .await;
+    }
+}
+
+// Wait for a shared location to attach to the place, instead of a draw origin
+async fn request_place_location<T: FirebaseApi + Sync>(
+    client: &Client,
+    firebase_client: &T,
+    place: &Place,
+    message: &str,
+) -> Response {
+    let jar: Jar = client.into();
+    let session = get_session(&jar, firebase_client).await;
+    let event = SessionEvent::InputRequested(PendingInput::PlaceLocation(place.clone()));
+    let session = transition(&jar, firebase_client, session, event).await;
+    Response::choices(message, (&session).into())
+}
+
+/// Runs the jar actions, whatever the chat platform the replies are rendered on
+pub struct JarAgent;
+
+async fn delete_current<F: FnOnce(String) -> String, T: FirebaseApi + Sync>(
+    client: &Client,
+    firebase_client: &T,
+    outcome: DrawOutcome,
+    message_formatter: F,
+) -> Response {
+    let (jar, draw) = get_current_draw(client, firebase_client).await;
+    match draw {
+        Ok(draw) => match draw {
+            None => {
+                println!(
+                    "Something is wrong here; tried to postpone the current shop but got no data"
+                );
+                Response::none()
+            }
+            Some(draw) => {
+                record_history(&jar, firebase_client, &draw, outcome).await;
+                let drawn_place_name = draw.name;
+                let _ = firebase_client
+                    .delete_place(
+                        &jar,
+                        &Place {
+                            name: drawn_place_name.clone(),
+                            key: draw.key,
+                        },
+                    )
+                    .await;
+                let session = get_session(&jar, firebase_client).await;
+                let session =
+                    transition(&jar, firebase_client, session, SessionEvent::DrawResolved).await;
+                Response::choices(&message_formatter(drawn_place_name), (&session).into())
+            }
+        },
+        Err(e) => Response::error(&e),
+    }
+}
+
+async fn refresh<F: FnOnce(&Option<String>) -> String, T: FirebaseApi + Sync>(
+    client: &Client,
+    firebase_client: &T,
+    message: F,
+) -> Response {
+    // Add count
+    let (jar, draw) = get_current_draw(client, firebase_client).await;
+    match draw {
+        Ok(draw) => {
+            let text_message = message(&draw.as_ref().map(|p| p.name.clone()));
+            let session = get_session(&jar, firebase_client)
+                .await
+                .reconcile(draw, session::now());
+            save_session(&jar, firebase_client, &session).await;
+            Response::choices(&text_message, (&session).into())
+        }
+        Err(e) => Response::error(&e),
+    }
+}
+
+#[async_trait]
+impl Agent for JarAgent {
+    async fn whoami(&self, client: &Client) -> Response {
+        match client.user_id() {
+            Some(id) => Response::text(id).private(),
+            None => Response::none(),
+        }
+    }
+
+    async fn manage_link(&self, client: &Client, host: &str, signer: &LinkSigner) -> Response {
+        let token = client.signed_link(signer, LinkPurpose::Manage);
+        let url = page_url(host, &format!("/line/manage?token={token}"));
+        Response::text(&format!("店の一覧・編集はこちらから（1時間有効）\n{url}")).private()
+    }
+
+    async fn issue_api_token<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        host: &str,
+    ) -> Response {
+        // The token is only sent privately, never to the whole group
+        let Some(user_id) = client.user_id() else {
+            println!("No user to send an API token to for {client:?}");
+            return Response::none();
+        };
+        let jar: Jar = client.into();
+        let token = api_token::generate();
+        let issued = ApiToken {
+            created_at: session::now(),
+            created_by: Some(user_id.to_string()),
+        };
+        let message = match firebase_client
+            .add_api_token(&jar, &api_token::hash(&token), &issued)
+            .await
+        {
+            Ok(_) => format!(
+                "APIトークン（「api revoke」で無効化）\n{}\nAuthorization: Bearer {token}",
+                page_url(host, &format!("/api/jars/{jar}/places"))
+            ),
+            Err(e) => {
+                println!("Could not add an API token to {jar:?}: {e:?}");
+                "APIトークンを発行できませんでした".to_string()
+            }
+        };
+        Response::text(&message).private()
+    }
+
+    async fn revoke_api_tokens<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response {
+        let jar: Jar = client.into();
+        match firebase_client.revoke_api_tokens(&jar).await {
+            Ok(_) => Response::text("APIトークンをすべて無効にしました"),
+            Err(e) => {
+                println!("Could not revoke the API tokens of {jar:?}: {e:?}");
+                Response::text("APIトークンを無効にできませんでした")
+            }
+        }
+    }
+
+    async fn refresh<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response {
+        refresh(client, firebase_client, |draw| match draw {
+            None => "無予定".to_string(),
+            Some(draw) => format!("予定中:{draw}"),
+        })
+        .await
+    }
+
+    async fn try_draw<T: FirebaseApi + Sync>(
+        &self,
+        meal: Meal,
+        client: &Client,
+        firebase_client: &T,
+        coordinates: &Option<Coordinates>,
+        area: Option<&str>,
+    ) -> (DrawResult, Response) {
+        let (jar, draw) = get_current_draw(client, firebase_client).await;
+        let mut session = get_session(&jar, firebase_client).await;
+        if let Some(coordinates) = coordinates {
+            // Postback from an older quick reply carrying its own origin
+            session = transition(
+                &jar,
+                firebase_client,
+                session,
+                SessionEvent::LocationShared(coordinates.clone()),
+            )
+            .await;
+        }
+        // A saved location of that name is drawn around, an area among its places; either
+        // replaces the shared origin for this draw only
+        let saved = match area {
+            Some(name) => firebase_client
+                .get_saved_locations(&jar)
+                .await
+                .ok()
+                .and_then(|locations| locations.get(name.trim()).cloned()),
+            None => None,
+        };
+        match draw {
+            Ok(draw) => match draw {
+                None => {
+                    let origin = match (&saved, area) {
+                        (Some(saved), _) => Some(saved.clone()),
+                        (None, Some(_)) => None,
+                        (None, None) => session.origin().cloned(),
+                    };
+                    let district = area.filter(|_| saved.is_none());
+                    let draw = firebase_client.draw(&jar, &meal, &origin, district).await;
+                    match draw {
+                        Ok(Some(draw)) => {
+                            record_history(&jar, firebase_client, &draw, DrawOutcome::Drawn).await;
+                            let message = with_details(
+                                &jar,
+                                firebase_client,
+                                &draw,
+                                format!("「{}」が出ました", draw.name),
+                            )
+                            .await;
+                            let session = transition(
+                                &jar,
+                                firebase_client,
+                                session,
+                                SessionEvent::Drawn(draw.clone()),
+                            )
+                            .await;
+                            (
+                                DrawResult::Drawn(draw),
+                                Response::choices(&message, (&session).into()),
+                            )
+                        }
+                        Ok(None) => {
+                            let response = match (area, origin) {
+                                (Some(name), _) if saved.is_some() => Response::choices(
+                                    &format!("{}の近くに店はありません", name.trim()),
+                                    Choices::NoShops(meal.clone()),
+                                ),
+                                (Some(area), _) => Response::choices(
+                                    &format!("{area}の店はありません"),
+                                    Choices::NoShops(meal.clone()),
+                                ),
+                                (None, None) => Response::choices(
+                                    "何も出ませんでした",
+                                    Choices::NoShops(meal.clone()),
+                                ),
+                                (None, Some(origin)) => Response::choices(
+                                    "指定位置の近くに店ありません",
+                                    Choices::NoShopsClosedBy(meal.clone(), origin),
+                                ),
+                            };
+                            (DrawResult::NothingToDraw, response)
+                        }
+                        Err(e) => (DrawResult::Failed(e.to_string()), Response::error(&e)),
+                    }
+                }
+                Some(draw) => {
+                    let message = with_details(
+                        &jar,
+                        firebase_client,
+                        &draw,
+                        format!("「{}」が既に出ています", draw.name),
+                    )
+                    .await;
+                    let session = session.reconcile(Some(draw.clone()), session::now());
+                    save_session(&jar, firebase_client, &session).await;
+                    (
+                        DrawResult::AlreadyDrawn(draw),
+                        Response::choices(&message, (&session).into()),
+                    )
+                }
+            },
+            Err(e) => (DrawResult::Failed(e.to_string()), Response::error(&e)),
+        }
+    }
+
+    async fn postpone<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response {
+        let (jar, draw) = get_current_draw(client, firebase_client).await;
+        match draw {
+            Ok(draw) => match draw {
+                None => {
+                    println!(
+                        "Something is wrong here; tried to postpone the current shop but got no data"
+                    );
+                    Response::none()
+                }
+                Some(draw) => {
+                    let _ = firebase_client.remove_drawn_place(&jar, Some(&draw)).await;
+                    record_history(&jar, firebase_client, &draw, DrawOutcome::Postponed).await;
+                    let session = get_session(&jar, firebase_client).await;
+                    let session =
+                        transition(&jar, firebase_client, session, SessionEvent::DrawResolved)
+                            .await;
+                    Response::choices(&format!("{}を延期しました", &draw.name), (&session).into())
+                }
+            },
+            Err(e) => Response::error(&e),
+        }
+    }
+
+    async fn delete_current<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response {
+        delete_current(client, firebase_client, DrawOutcome::Deleted, |draw| {
+            format!("「{}」を削除しました", &draw)
+        })
+        .await
+    }
+
+    async fn archive_current<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response {
+        delete_current(client, firebase_client, DrawOutcome::Archived, |draw| {
+            format!("「{}」は完食になりました", &draw)
+        })
+        .await
+    }
+
+    async fn add_place<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        place_name: &str,
+        meals: Vec<Meal>,
+    ) -> (HttpResult<Place>, Response) {
+        let jar: Jar = client.into();
+        let result = firebase_client
+            .add_place(&jar, place_name, &meals, client.user_id())
+            .await;
+        let response = match &result {
+            Ok(_) => {
+                refresh(client, firebase_client, |_| {
+                    "新しい店が追加されました".to_string()
+                })
+                .await
+            }
+            Err(e) => Response::error(e).private(),
+        };
+        (result, response)
+    }
+
+    async fn locate_place<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        place: &Place,
+        mut candidates: Vec<Candidate>,
+    ) -> Response {
+        match candidates.len() {
+            0 => {
+                let message = format!(
+                    "「{}」の位置は見つかりませんでした。位置情報を送ってください",
+                    place.name
+                );
+                request_place_location(client, firebase_client, place, &message).await
+            }
+            1 => {
+                let jar: Jar = client.into();
+                save_candidate(&jar, firebase_client, place, &candidates.remove(0)).await;
+                // Only read: the chat may be in the middle of answering something else
+                let session = get_session(&jar, firebase_client).await;
+                let message = format!("「{}」の位置が見つかりました", place.name);
+                Response::choices(&message, (&session).into())
+            }
+            _ => {
+                let mut message = format!("「{}」の場所はどれですか？", place.name);
+                for (index, candidate) in candidates.iter().enumerate() {
+                    message.push_str(&format!("\n{}. {}", index + 1, candidate.address));
+                }
+                let jar: Jar = client.into();
+                let session = get_session(&jar, firebase_client).await;
+                let event = SessionEvent::InputRequested(PendingInput::Candidate {
+                    place: place.clone(),
+                    candidates,
+                });
+                let session = transition(&jar, firebase_client, session, event).await;
+                Response::choices(&message, (&session).into())
+            }
+        }
+    }
+
+    async fn choose_candidate<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        index: usize,
+    ) -> Response {
+        let jar: Jar = client.into();
+        let session = get_session(&jar, firebase_client).await;
+        let Some(PendingInput::Candidate { place, candidates }) = session.pending_input().cloned()
+        else {
+            return Response::text("候補の選択は期限切れです").private();
+        };
+        let Some(candidate) = candidates.get(index) else {
+            println!("No candidate {index} for {place:?} in {jar:?}");
+            return Response::none();
+        };
+        save_candidate(&jar, firebase_client, &place, candidate).await;
+        let session = transition(&jar, firebase_client, session, SessionEvent::InputReceived).await;
+        Response::choices(
+            &format!("「{}」の位置は{}にしました", place.name, candidate.address),
+            (&session).into(),
+        )
+    }
+
+    async fn reject_candidates<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response {
+        let jar: Jar = client.into();
+        let session = get_session(&jar, firebase_client).await;
+        let Some(PendingInput::Candidate { place, .. }) = session.pending_input().cloned() else {
+            return Response::text("候補の選択は期限切れです").private();
+        };
+        let message = format!("「{}」の位置情報を送ってください", place.name);
+        request_place_location(client, firebase_client, &place, &message).await
+    }
+
+    async fn add_place_at<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        place_name: &str,
+        coordinates: &Coordinates,
+        link: Option<&str>,
+    ) -> Response {
+        let jar: Jar = client.into();
+        let meals = vec![Meal::Lunch, Meal::Dinner];
+        match firebase_client
+            .add_place(&jar, place_name, &meals, client.user_id())
+            .await
+        {
+            Ok(place) => {
+                let _ = firebase_client
+                    .set_place_coordinates(&jar, &place, coordinates)
+                    .await;
+                if let Some(link) = link {
+                    let _ = firebase_client.set_place_link(&jar, &place, link).await;
+                }
+                refresh(client, firebase_client, |_| {
+                    format!("「{place_name}」をこの位置で追加しました")
+                })
+                .await
+            }
+            Err(e) => Response::error(&e).private(),
+        }
+    }
+
+    async fn update_location<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        latitude: f32,
+        longitude: f32,
+        title: Option<&str>,
+    ) -> Response {
+        let jar: Jar = client.into();
+        let session = get_session(&jar, firebase_client).await;
+        let coordinates = Coordinates {
+            latitude,
+            longitude,
+        };
+        // The location of a place being added rather than a draw origin
+        if let Some(PendingInput::PlaceLocation(place)) = session.pending_input().cloned() {
+            let _ = firebase_client
+                .set_place_coordinates(&jar, &place, &coordinates)
+                .await;
+            let session =
+                transition(&jar, firebase_client, session, SessionEvent::InputReceived).await;
+            return Response::choices(
+                &format!("「{}」の位置を登録しました", place.name),
+                (&session).into(),
+            );
+        }
+        let event = SessionEvent::LocationShared(coordinates.clone());
+        let session = transition(&jar, firebase_client, session, event).await;
+        match (title, Choices::from(&session)) {
+            (Some(title), Choices::Idle(_)) => Response::choices(
+                &format!("位置取得済み\n「{title}」を追加できます"),
+                Choices::SharedPlace(title.to_string(), coordinates),
+            ),
+            (_, choices) => Response::choices("位置取得済み", choices),
+        }
+    }
+
+    async fn clear_location<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response {
+        let jar: Jar = client.into();
+        let session = get_session(&jar, firebase_client).await;
+        let session = transition(
+            &jar,
+            firebase_client,
+            session,
+            SessionEvent::LocationCleared,
+        )
+        .await;
+        Response::choices("位置を消しました", (&session).into())
+    }
+
+    async fn welcome(&self) -> Response {
+        Response::choices(WELCOME_MESSAGE, Choices::Welcome)
+    }
+
+    async fn seed<T: FirebaseApi + Sync>(&self, client: &Client, firebase_client: &T) -> Response {
+        let jar: Jar = client.into();
+        let (seed_places, places) = match (
+            load_seed_places(),
+            firebase_client.get_all_places(&jar).await,
+        ) {
+            (Ok(seed_places), Ok(places)) => (seed_places, places),
+            (Err(_), _) => {
+                println!("Could not read the seed places");
+                return Response::none();
+            }
+            (_, Err(e)) => return Response::error(&e),
+        };
+        let mut added = 0;
+        for seed_place in seed_places {
+            if places.iter().any(|p| p.name == seed_place.name) {
+                continue;
+            }
+            let result = firebase_client
+                .add_place(&jar, &seed_place.name, &seed_place.meals, None)
+                .await;
+            if result.is_ok() {
+                added += 1;
+            }
+        }
+        refresh(client, firebase_client, |_| {
+            format!("サンプルの店を{added}件追加しました")
+        })
+        .await
+    }
+
+    async fn geocoding_region<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        argument: &str,
+    ) -> Response {
+        let jar: Jar = client.into();
+        let context = match argument.trim() {
+            "" => {
+                let context = firebase_client
+                    .get_geocoding_context(&jar)
+                    .await
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default();
+                return Response::text(&format!(
+                    "店の検索地域: {context}\n変更は「地域 US en」、近くを優先するには「地域 US en 40.71,-74.00」"
+                ));
+            }
+            "reset" | "リセット" => GeocodingContext::default(),
+            argument => match GeocodingContext::parse(argument) {
+                Ok(context) => context,
+                Err(message) => return Response::text(&message),
+            },
+        };
+        match firebase_client.set_geocoding_context(&jar, &context).await {
+            // Only the places added from now on are looked up there
+            Ok(_) => Response::text(&format!("店の検索地域を{context}にしました")),
+            Err(e) => Response::error(&e),
+        }
+    }
+
+    async fn draw_from_saved<T: FirebaseApi + Sync>(
+        &self,
+        meal: Meal,
+        client: &Client,
+        firebase_client: &T,
+        location_id: &str,
+    ) -> Response {
+        let jar: Jar = client.into();
+        let name = match firebase_client.get_saved_locations(&jar).await {
+            Ok(locations) => locations
+                .into_keys()
+                .find(|name| saved_location_id(name) == location_id),
+            Err(e) => return Response::error(&e),
+        };
+        match name {
+            Some(name) => {
+                let (_, response) = self
+                    .try_draw(meal, client, firebase_client, &None, Some(&name))
+                    .await;
+                response
+            }
+            None => Response::text("この場所はもう保存されていません").private(),
+        }
+    }
+
+    async fn saved_location<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        argument: &str,
+    ) -> Response {
+        let jar: Jar = client.into();
+        let argument = argument.trim();
+        if argument.is_empty() {
+            let locations = match firebase_client.get_saved_locations(&jar).await {
+                Ok(locations) => locations,
+                Err(e) => return Response::error(&e),
+            };
+            let message = match locations.keys().next() {
+                None => "保存した場所はありません\n位置を送ってから「場所 オフィス」で保存できます"
+                    .to_string(),
+                Some(first) => format!(
+                    "保存した場所: {}\n「引く {first}」で近くの店を引けます\n削除は「場所 削除 {first}」",
+                    locations.keys().cloned().collect::<Vec<_>>().join("、")
+                ),
+            };
+            let session = get_session(&jar, firebase_client).await;
+            return Response::choices(&message, (&session).into());
+        }
+        if let Some(("削除" | "delete", name)) = argument
+            .split_once(char::is_whitespace)
+            .map(|(command, name)| (command, name.trim()))
+        {
+            let locations = firebase_client
+                .get_saved_locations(&jar)
+                .await
+                .unwrap_or_default();
+            if !locations.contains_key(name) {
+                return Response::text(&format!("「{name}」は保存されていません"));
+            }
+            return match firebase_client.remove_saved_location(&jar, name).await {
+                Ok(_) => Response::text(&format!("「{name}」を削除しました")),
+                Err(e) => Response::error(&e),
+            };
+        }
+        let name = argument;
+        if name.chars().count() > MAX_LOCATION_NAME_LENGTH
+            || name
+                .chars()
+                .any(|c| c.is_control() || FORBIDDEN_KEY_CHARACTERS.contains(&c))
+        {
+            return Response::text(&format!(
+                "「{name}」は場所の名前に使えません（{MAX_LOCATION_NAME_LENGTH}文字まで、記号 . $ # [ ] / ? % は不可）"
+            ));
+        }
+        let session = get_session(&jar, firebase_client).await;
+        let Some(origin) = session.origin() else {
+            return Response::text(&format!(
+                "先に位置を送ってから「場所 {name}」と送ってください"
+            ));
+        };
+        match firebase_client.save_location(&jar, name, origin).await {
+            Ok(_) => Response::choices(
+                &format!("「{name}」を保存しました\n次からは位置を送らずに近くの店を引けます"),
+                (&session).into(),
+            ),
+            Err(e) => Response::error(&e),
+        }
+    }
+
+    async fn request_place_name<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+    ) -> Response {
+        let jar: Jar = client.into();
+        let session = get_session(&jar, firebase_client).await;
+        let event = SessionEvent::InputRequested(PendingInput::PlaceName);
+        let session = transition(&jar, firebase_client, session, event).await;
+        Response::choices("追加する店の名前を送ってください", (&session).into())
+    }
+
+    async fn receive_input<T: FirebaseApi + Sync>(
+        &self,
+        client: &Client,
+        firebase_client: &T,
+        text: &str,
+    ) -> Option<String> {
+        let jar: Jar = client.into();
+        let session = get_session(&jar, firebase_client).await;
+        match session.pending_input() {
+            Some(PendingInput::PlaceName) => {
+                transition(&jar, firebase_client, session, SessionEvent::InputReceived).await;
+                Some(text.trim().to_string()).filter(|name| !name.is_empty())
+            }
+            Some(PendingInput::Candidate { .. } | PendingInput::PlaceLocation(_)) | None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::address::Address;
+    use crate::app::agent::Agent;
+    use crate::app::coordinates::Coordinates;
+    use crate::app::core::{Action, Client, DrawResult, Meal};
+    use crate::app::history::DrawOutcome;
+    use crate::app::jar::Jar;
+    use crate::app::jar_agent::JarAgent;
+    use crate::app::response::{Audience, Choices, Reply, Response};
+    use crate::app::session::PendingInput;
+    use crate::app::user_action::UserAction;
+    use crate::gcp::api::fixtures::MemoryFirebase;
+    use crate::geocoding::api::Candidate;
+    use crate::geocoding::context::GeocodingContext;
+    use crate::line::http::LineChannel;
+
+    const LINK: &str = "https://tabelog.com/tokyo/A1303/A130301/13001234/";
+
+    fn candidate(address: &str, latitude: f32) -> Candidate {
+        Candidate {
+            address: address.to_string(),
+            details: Address {
+                line: Some(address.to_string()),
+                district: Some("渋谷区".to_string()),
+                region: Some("東京都".to_string()),
+                ..Address::default()
+            },
+            coordinates: Coordinates {
+                latitude,
+                longitude: 139.7,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn it_draws_and_archives_a_place() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let (place, response) = JarAgent
+            .add_place(&client, &firebase_client, "一蘭", vec![Meal::Lunch])
+            .await;
+        let place = place.unwrap();
+        assert_eq!(
+            response,
+            Response::choices("新しい店が追加されました", Choices::Idle(None))
+        );
+
+        let (result, response) = JarAgent
+            .try_draw(Meal::Dinner, &client, &firebase_client, &None, None)
+            .await;
+        assert!(matches!(result, DrawResult::NothingToDraw));
+        assert_eq!(
+            response,
+            Response::choices("何も出ませんでした", Choices::NoShops(Meal::Dinner))
+        );
+
+        let (result, response) = JarAgent
+            .try_draw(Meal::Lunch, &client, &firebase_client, &None, None)
+            .await;
+        assert!(matches!(result, DrawResult::Drawn(drawn) if drawn == place));
+        assert_eq!(
+            response,
+            Response::choices("「一蘭」が出ました", Choices::ActiveDraw(None))
+        );
+        let (result, _) = JarAgent
+            .try_draw(Meal::Lunch, &client, &firebase_client, &None, None)
+            .await;
+        assert!(matches!(result, DrawResult::AlreadyDrawn(_)));
+
+        let response = JarAgent.archive_current(&client, &firebase_client).await;
+        assert_eq!(
+            response,
+            Response::choices("「一蘭」は完食になりました", Choices::Idle(None))
+        );
+        firebase_client.with_jar(&Jar::from(&client), |jar| {
+            assert!(jar.places.is_empty());
+            let outcomes: Vec<DrawOutcome> =
+                jar.history.iter().map(|e| e.outcome.clone()).collect();
+            assert_eq!(outcomes, vec![DrawOutcome::Drawn, DrawOutcome::Archived]);
+        });
+    }
+
+    #[tokio::test]
+    async fn it_answers_privately_to_the_requester() {
+        let client = Client::Line(LineChannel::Group {
+            id: "G1".to_string(),
+            user_id: Some("U1".to_string()),
+        });
+        let response = JarAgent.whoami(&client).await;
+        assert_eq!(response.audience, Audience::Requester);
+        assert_eq!(response.replies, vec![Reply::Text("U1".to_string())]);
+
+        let client = Client::Line(LineChannel::Group {
+            id: "G1".to_string(),
+            user_id: None,
+        });
+        assert_eq!(JarAgent.whoami(&client).await, Response::none());
+    }
+
+    #[tokio::test]
+    async fn it_picks_among_geocoding_candidates() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let jar = Jar::from(&client);
+        let candidates = vec![
+            candidate("宇田川町13-7", 35.661),
+            candidate("神南1-22-7", 35.662),
+        ];
+        let (place, _) = JarAgent
+            .add_place(&client, &firebase_client, "一蘭", vec![Meal::Lunch])
+            .await;
+        let place = place.unwrap();
+
+        let response = JarAgent
+            .locate_place(&client, &firebase_client, &place, candidates.clone())
+            .await;
+        assert_eq!(
+            response,
+            Response::choices(
+                "「一蘭」の場所はどれですか？\n1. 宇田川町13-7\n2. 神南1-22-7",
+                Choices::Candidates(2)
+            )
+        );
+
+        let response = JarAgent
+            .choose_candidate(&client, &firebase_client, 1)
+            .await;
+        assert_eq!(
+            response,
+            Response::choices("「一蘭」の位置は神南1-22-7にしました", Choices::Idle(None))
+        );
+        firebase_client.with_jar(&jar, |jar| {
+            let coordinates = jar.places[&place.key].coordinates.clone();
+            assert_eq!(coordinates.map(|c| c.latitude), Some(35.662));
+        });
+
+        // None of the candidates: the next shared location is the place's
+        JarAgent
+            .locate_place(&client, &firebase_client, &place, candidates)
+            .await;
+        let response = JarAgent.reject_candidates(&client, &firebase_client).await;
+        assert_eq!(
+            response,
+            Response::choices(
+                "「一蘭」の位置情報を送ってください",
+                Choices::AwaitingLocation
+            )
+        );
+        let response = JarAgent
+            .update_location(&client, &firebase_client, 35.5, 139.5, None)
+            .await;
+        assert_eq!(
+            response,
+            Response::choices("「一蘭」の位置を登録しました", Choices::Idle(None))
+        );
+        firebase_client.with_jar(&jar, |jar| {
+            let coordinates = jar.places[&place.key].coordinates.clone();
+            assert_eq!(coordinates.map(|c| c.latitude), Some(35.5));
+            assert_eq!(jar.session.as_ref().and_then(|s| s.origin()), None);
+        });
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_pending_input_when_a_place_is_located() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let (place, _) = JarAgent
+            .add_place(&client, &firebase_client, "一蘭", vec![Meal::Lunch])
+            .await;
+        let place = place.unwrap();
+        JarAgent.request_place_name(&client, &firebase_client).await;
+
+        let response = JarAgent
+            .locate_place(
+                &client,
+                &firebase_client,
+                &place,
+                vec![candidate("宇田川町13-7", 35.661)],
+            )
+            .await;
+        assert_eq!(
+            response,
+            Response::choices("「一蘭」の位置が見つかりました", Choices::Idle(None))
+        );
+        firebase_client.with_jar(&Jar::from(&client), |jar| {
+            let pending = jar
+                .session
+                .as_ref()
+                .and_then(|s| s.pending_input().cloned());
+            assert_eq!(pending, Some(PendingInput::PlaceName));
+        });
+    }
+
+    #[tokio::test]
+    async fn it_draws_within_an_area() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let (place, _) = JarAgent
+            .add_place(&client, &firebase_client, "一蘭", vec![Meal::Lunch])
+            .await;
+        let place = place.unwrap();
+        JarAgent
+            .locate_place(
+                &client,
+                &firebase_client,
+                &place,
+                vec![candidate("宇田川町13-7", 35.661)],
+            )
+            .await;
+
+        let (result, response) = JarAgent
+            .try_draw(
+                Meal::Lunch,
+                &client,
+                &firebase_client,
+                &None,
+                Some("新宿区"),
+            )
+            .await;
+        assert!(matches!(result, DrawResult::NothingToDraw));
+        assert_eq!(
+            response,
+            Response::choices("新宿区の店はありません", Choices::NoShops(Meal::Lunch))
+        );
+
+        let (result, response) = JarAgent
+            .try_draw(Meal::Lunch, &client, &firebase_client, &None, Some("渋谷"))
+            .await;
+        assert!(matches!(result, DrawResult::Drawn(drawn) if drawn == place));
+        assert_eq!(
+            response,
+            Response::choices(
+                "「一蘭」が出ました\n東京都 渋谷区 宇田川町13-7",
+                Choices::ActiveDraw(None)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn it_draws_around_a_saved_location() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let (place, _) = JarAgent
+            .add_place(&client, &firebase_client, "一蘭", vec![Meal::Lunch])
+            .await;
+        let place = place.unwrap();
+        JarAgent
+            .locate_place(
+                &client,
+                &firebase_client,
+                &place,
+                vec![candidate("宇田川町13-7", 35.661)],
+            )
+            .await;
+
+        let response = JarAgent
+            .saved_location(&client, &firebase_client, "オフィス")
+            .await;
+        assert_eq!(
+            response,
+            Response::text("先に位置を送ってから「場所 オフィス」と送ってください")
+        );
+        JarAgent
+            .update_location(&client, &firebase_client, 35.662, 139.7, None)
+            .await;
+        JarAgent
+            .saved_location(&client, &firebase_client, "オフィス")
+            .await;
+        JarAgent
+            .update_location(&client, &firebase_client, 34.7, 135.5, None)
+            .await;
+        JarAgent
+            .saved_location(&client, &firebase_client, "大阪")
+            .await;
+        JarAgent.clear_location(&client, &firebase_client).await;
+
+        // The buttons of a saved location draw around it without a shared location
+        let offer =
+            serde_json::to_string(&UserAction::DrawFrom(Meal::Lunch, "オフィス".to_string()))
+                .unwrap();
+        let action = serde_json::from_str::<UserAction>(&offer)
+            .unwrap()
+            .into_action(client);
+        let Action::DrawFromSaved(client, meal, id) = action else {
+            panic!("Expected a draw, got {action:?}");
+        };
+        let response = JarAgent
+            .draw_from_saved(meal, &client, &firebase_client, &id)
+            .await;
+        assert!(matches!(
+            &response.replies[0],
+            Reply::Choices(text, Choices::ActiveDraw(None)) if text.starts_with("「一蘭」が出ました")
+        ));
+        JarAgent.postpone(&client, &firebase_client).await;
+        let response = JarAgent
+            .draw_from_saved(Meal::Lunch, &client, &firebase_client, "unknown")
+            .await;
+        assert_eq!(
+            response,
+            Response::text("この場所はもう保存されていません").private()
+        );
+
+        let (result, response) = JarAgent
+            .try_draw(Meal::Lunch, &client, &firebase_client, &None, Some("大阪"))
+            .await;
+        assert!(matches!(result, DrawResult::NothingToDraw));
+        assert_eq!(
+            response,
+            Response::choices("大阪の近くに店はありません", Choices::NoShops(Meal::Lunch))
+        );
+
+        JarAgent
+            .saved_location(&client, &firebase_client, "削除 大阪")
+            .await;
+        let response = JarAgent.saved_location(&client, &firebase_client, "").await;
+        assert_eq!(
+            response,
+            Response::choices(
+                "保存した場所: オフィス\n「引く オフィス」で近くの店を引けます\n削除は「場所 削除 オフィス」",
+                Choices::Idle(None)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_invalid_location_names() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        JarAgent
+            .update_location(&client, &firebase_client, 35.662, 139.7, None)
+            .await;
+        let names = [
+            "駅?西口",
+            "駅%2F西口",
+            "駅\u{7}西口",
+            "駅/西口",
+            "一二三四五六七八九十一二三",
+        ];
+        for name in names {
+            let response = JarAgent
+                .saved_location(&client, &firebase_client, name)
+                .await;
+            assert_eq!(
+                response,
+                Response::text(&format!(
+                    "「{name}」は場所の名前に使えません（12文字まで、記号 . $ # [ ] / ? % は不可）"
+                )),
+                "{name:?}"
+            );
+        }
+        firebase_client.with_jar(&Jar::from(&client), |jar| {
+            assert!(jar.saved_locations.is_empty());
+        });
+    }
+
+    #[tokio::test]
+    async fn it_sets_the_geocoding_region_from_the_chat() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let response = JarAgent
+            .geocoding_region(&client, &firebase_client, "us en 40.71,-74.0")
+            .await;
+        assert_eq!(
+            response,
+            Response::text("店の検索地域をUS・en・40.71,-74付近にしました")
+        );
+        firebase_client.with_jar(&Jar::from(&client), |jar| {
+            assert_eq!(
+                jar.geocoding.as_ref().map(|c| c.country.as_str()),
+                Some("US")
+            );
+        });
+
+        let response = JarAgent
+            .geocoding_region(&client, &firebase_client, "日本")
+            .await;
+        assert!(matches!(&response.replies[..], [Reply::Text(text)] if text.contains("国コード")));
+
+        JarAgent
+            .geocoding_region(&client, &firebase_client, "リセット")
+            .await;
+        firebase_client.with_jar(&Jar::from(&client), |jar| {
+            assert_eq!(jar.geocoding, Some(GeocodingContext::default()));
+        });
+    }
+
+    #[tokio::test]
+    async fn it_attaches_the_next_location_to_a_place_not_found() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let (place, _) = JarAgent
+            .add_place(&client, &firebase_client, "謎の店", vec![Meal::Lunch])
+            .await;
+        let place = place.unwrap();
+
+        let response = JarAgent
+            .locate_place(&client, &firebase_client, &place, vec![])
+            .await;
+        assert_eq!(
+            response,
+            Response::choices(
+                "「謎の店」の位置は見つかりませんでした。位置情報を送ってください",
+                Choices::AwaitingLocation
+            )
+        );
+
+        JarAgent
+            .update_location(&client, &firebase_client, 35.5, 139.5, None)
+            .await;
+        // Only the first location is the place's, the next ones are draw origins
+        let response = JarAgent
+            .update_location(&client, &firebase_client, 35.6, 139.6, None)
+            .await;
+        assert_eq!(
+            response,
+            Response::choices(
+                "位置取得済み",
+                Choices::Idle(Some(Coordinates {
+                    latitude: 35.6,
+                    longitude: 139.6
+                }))
+            )
+        );
+        firebase_client.with_jar(&Jar::from(&client), |jar| {
+            let coordinates = jar.places[&place.key].coordinates.clone();
+            assert_eq!(coordinates.map(|c| c.latitude), Some(35.5));
+        });
+    }
+
+    #[tokio::test]
+    async fn it_adds_a_shared_spot_at_its_location() {
+        let firebase_client = MemoryFirebase::default();
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let coordinates = Coordinates {
+            latitude: 35.66,
+            longitude: 139.7,
+        };
+        let response = JarAgent
+            .update_location(&client, &firebase_client, 35.66, 139.7, Some("一蘭 渋谷店"))
+            .await;
+        let choices = Choices::SharedPlace("一蘭 渋谷店".to_string(), coordinates.clone());
+        assert_eq!(
+            response,
+            Response::choices(
+                "位置取得済み\n「一蘭 渋谷店」を追加できます",
+                choices.clone()
+            )
+        );
+
+        // The offer survives the round trip through a postback
+        let offer = serde_json::to_string(&choices.buttons()[0]).unwrap();
+        let action = serde_json::from_str::<UserAction>(&offer)
+            .unwrap()
+            .into_action(client);
+        let Action::AddAt(client, name, at) = action else {
+            panic!("Unexpected {action:?}");
+        };
+        assert_eq!((name.as_str(), &at), ("一蘭 渋谷店", &coordinates));
+
+        let response = JarAgent
+            .add_place_at(&client, &firebase_client, &name, &at, Some(LINK))
+            .await;
+        assert_eq!(
+            response,
+            Response::choices(
+                "「一蘭 渋谷店」をこの位置で追加しました",
+                Choices::Idle(Some(coordinates.clone()))
+            )
+        );
+        firebase_client.with_jar(&Jar::from(&client), |jar| {
+            let place = jar.places.values().next().unwrap();
+            assert_eq!(place.name, "一蘭 渋谷店");
+            assert_eq!(place.coordinates, Some(coordinates.clone()));
+        });
+
+        // Draws point to the page the place was added from
+        let (_, response) = JarAgent
+            .try_draw(Meal::Lunch, &client, &firebase_client, &None, None)
+            .await;
+        assert_eq!(
+            response,
+            Response::choices(
+                &format!("「一蘭 渋谷店」が出ました\n{LINK}"),
+                Choices::ActiveDraw(Some(coordinates))
+            )
+        );
+    }
+}
@@ -0,0 +1,367 @@
+use std::fmt::{Display, Formatter};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+
+use crate::app::core::Client;
+use crate::line::http::LineChannel;
+use crate::slack::http::SlackChannel;
+use crate::telegram::http::TelegramChat;
+use crate::web::hub::WebRoom;
+
+// How long a link sent in the chat opens the add form
+const LINK_TTL_SECONDS: u64 = 60 * 60;
+// How long a web chat invite lets new browsers in; connected members keep getting fresh ones
+const INVITE_TTL_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, PartialEq)]
+pub enum LinkError {
+    Malformed,
+    InvalidSignature,
+    Expired,
+    WrongPurpose,
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::Malformed => write!(f, "Malformed link token"),
+            LinkError::InvalidSignature => write!(f, "Invalid link signature"),
+            LinkError::Expired => write!(f, "Expired link"),
+            LinkError::WrongPurpose => write!(f, "Link issued for another page"),
+        }
+    }
+}
+
+/// Page a signed link opens
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkPurpose {
+    #[default]
+    AddPlace,
+    Manage,
+}
+
+/// Jar and requesting user a form link was issued for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FormLink {
+    #[serde(default)]
+    purpose: LinkPurpose,
+    source_type: String,
+    source_id: String,
+    user_id: Option<String>,
+    expires_at: u64,
+}
+
+impl FormLink {
+    pub fn new(client: &Client, purpose: LinkPurpose, now: u64) -> Self {
+        let (source_type, source_id, user_id) = match client {
+            Client::Line(channel) => match channel {
+                LineChannel::User(id) => ("user", id.clone(), Some(id)),
+                LineChannel::Room { id, user_id } => ("room", id.clone(), user_id.as_ref()),
+                LineChannel::Group { id, user_id } => ("group", id.clone(), user_id.as_ref()),
+            },
+            Client::Slack(channel) => (
+                "slack",
+                format!("{}_{}", channel.team_id, channel.channel_id),
+                channel.user_id.as_ref(),
+            ),
+            Client::Telegram(chat) => ("telegram", chat.chat_id.clone(), chat.user_id.as_ref()),
+            Client::Web(room) => ("web", room.room_id.clone(), room.user_id.as_ref()),
+        };
+        FormLink {
+            purpose,
+            source_type: source_type.to_string(),
+            source_id,
+            user_id: user_id.cloned(),
+            expires_at: now + LINK_TTL_SECONDS,
+        }
+    }
+
+    pub fn client(&self) -> Option<Client> {
+        let id = self.source_id.clone();
+        let user_id = self.user_id.clone();
+        if self.source_type == "slack" {
+            let (team_id, channel_id) = id.split_once('_')?;
+            return Some(Client::Slack(SlackChannel {
+                team_id: team_id.to_string(),
+                channel_id: channel_id.to_string(),
+                user_id,
+            }));
+        }
+        if self.source_type == "telegram" {
+            return Some(Client::Telegram(TelegramChat {
+                chat_id: id,
+                user_id,
+            }));
+        }
+        if self.source_type == "web" {
+            return Some(Client::Web(WebRoom {
+                room_id: id,
+                user_id,
+            }));
+        }
+        match self.source_type.as_str() {
+            "user" => Some(LineChannel::User(id)),
+            "group" => Some(LineChannel::Group { id, user_id }),
+            "room" => Some(LineChannel::Room { id, user_id }),
+            _ => None,
+        }
+        .map(Client::Line)
+    }
+}
+
+/// Signs the form links, the CSRF tokens of the forms they open, the web chat invites and the
+/// web chat browser sessions
+pub struct LinkSigner {
+    links: hmac::Key,
+    invites: hmac::Key,
+    csrf: hmac::Key,
+    sessions: hmac::Key,
+}
+
+// Each kind of token is signed with its own key, derived from the secret, so that none can be
+// passed off as another
+fn derived_key(secret: &hmac::Key, purpose: &str) -> hmac::Key {
+    let key = hmac::sign(secret, format!("taberando/{purpose}").as_bytes());
+    hmac::Key::new(hmac::HMAC_SHA256, key.as_ref())
+}
+
+fn signed(key: &hmac::Key, payload: &str) -> String {
+    let signature = hmac::sign(key, payload.as_bytes());
+    format!("{payload}.{}", URL_SAFE_NO_PAD.encode(signature.as_ref()))
+}
+
+fn verified<'a>(key: &hmac::Key, token: &'a str) -> Result<&'a str, LinkError> {
+    let (payload, signature) = token.rsplit_once('.').ok_or(LinkError::Malformed)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| LinkError::Malformed)?;
+    hmac::verify(key, payload.as_bytes(), &signature).map_err(|_| LinkError::InvalidSignature)?;
+    Ok(payload)
+}
+
+impl LinkSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        let secret = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        LinkSigner {
+            links: derived_key(&secret, "links"),
+            invites: derived_key(&secret, "invites"),
+            csrf: derived_key(&secret, "csrf"),
+            sessions: derived_key(&secret, "sessions"),
+        }
+    }
+
+    /// Signer of the `LINK_SECRET` key, built once when the server starts
+    pub fn from_env() -> Self {
+        LinkSigner::new(
+            std::env::var("LINK_SECRET")
+                .expect("Needs to have a LINK_SECRET env variable to sign the form links")
+                .as_bytes(),
+        )
+    }
+
+    pub fn sign(&self, link: &FormLink) -> String {
+        let json = serde_json::to_string(link).unwrap();
+        signed(&self.links, &URL_SAFE_NO_PAD.encode(json))
+    }
+
+    pub fn verify(
+        &self,
+        token: &str,
+        purpose: LinkPurpose,
+        now: u64,
+    ) -> Result<FormLink, LinkError> {
+        let payload = verified(&self.links, token)?;
+        let json = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| LinkError::Malformed)?;
+        let link: FormLink = serde_json::from_slice(&json).map_err(|_| LinkError::Malformed)?;
+        if link.expires_at < now {
+            return Err(LinkError::Expired);
+        }
+        if link.purpose != purpose {
+            return Err(LinkError::WrongPurpose);
+        }
+        Ok(link)
+    }
+
+    /// Invite token of a web chat room
+    pub fn invite(&self, room_id: &str, now: u64) -> String {
+        let expires_at = now + INVITE_TTL_SECONDS;
+        signed(&self.invites, &format!("web_{room_id}.{expires_at}"))
+    }
+
+    /// Room a web chat invite token was issued for
+    pub fn verify_invite(&self, token: &str, now: u64) -> Result<String, LinkError> {
+        let payload = verified(&self.invites, token)?;
+        let (room, expires_at) = payload.rsplit_once('.').ok_or(LinkError::Malformed)?;
+        let expires_at: u64 = expires_at.parse().map_err(|_| LinkError::Malformed)?;
+        if expires_at < now {
+            return Err(LinkError::Expired);
+        }
+        room.strip_prefix("web_")
+            .map(str::to_string)
+            .ok_or(LinkError::WrongPurpose)
+    }
+
+    /// Session token of a web chat browser, the only proof of its user id
+    pub fn session(&self, user_id: &str) -> String {
+        signed(&self.sessions, user_id)
+    }
+
+    pub fn verify_session(&self, token: &str) -> Result<String, LinkError> {
+        verified(&self.sessions, token).map(str::to_string)
+    }
+
+    /// A fresh token for each rendered form, only valid along the link token it was issued with
+    pub fn csrf_token(&self, link_token: &str) -> String {
+        let mut nonce = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = URL_SAFE_NO_PAD.encode(nonce);
+        let signed = signed(&self.csrf, &format!("{nonce}.{link_token}"));
+        let (_, signature) = signed.rsplit_once('.').unwrap();
+        format!("{nonce}.{signature}")
+    }
+
+    pub fn verify_csrf(&self, link_token: &str, csrf_token: &str) -> Result<(), LinkError> {
+        let (nonce, signature) = csrf_token.split_once('.').ok_or(LinkError::Malformed)?;
+        verified(&self.csrf, &format!("{nonce}.{link_token}.{signature}")).map(|_| ())
+    }
+}
+
+/// Absolute url of a page served by this server
+pub(crate) fn page_url(host: &str, path_and_query: &str) -> String {
+    warp::http::uri::Uri::builder()
+        .scheme("https")
+        .authority(host)
+        .path_and_query(path_and_query)
+        .build()
+        .unwrap()
+        .to_string()
+}
+
+impl Client {
+    pub(crate) fn signed_link(&self, signer: &LinkSigner, purpose: LinkPurpose) -> String {
+        signer.sign(&FormLink::new(self, purpose, crate::app::session::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::core::Client;
+    use crate::app::link::{
+        verified, FormLink, LinkError, LinkPurpose, LinkSigner, INVITE_TTL_SECONDS,
+    };
+    use crate::line::http::LineChannel;
+
+    fn group() -> Client {
+        Client::Line(LineChannel::Group {
+            id: "G1".to_string(),
+            user_id: Some("U1".to_string()),
+        })
+    }
+
+    #[test]
+    fn it_verifies_signed_links() {
+        let signer = LinkSigner::new(b"secret");
+        let link = FormLink::new(&group(), LinkPurpose::AddPlace, 100);
+        let token = signer.sign(&link);
+        assert_eq!(
+            signer.verify(&token, LinkPurpose::AddPlace, 100),
+            Ok(link.clone())
+        );
+        assert!(matches!(
+            link.client(),
+            Some(Client::Line(LineChannel::Group { id, user_id: Some(user_id) }))
+                if id == "G1" && user_id == "U1"
+        ));
+    }
+
+    #[test]
+    fn it_rejects_tampered_and_expired_links() {
+        let signer = LinkSigner::new(b"secret");
+        let token = signer.sign(&FormLink::new(&group(), LinkPurpose::AddPlace, 100));
+        let (_, signature) = token.rsplit_once('.').unwrap();
+        let other = signer.sign(&FormLink::new(
+            &Client::Line(LineChannel::User("U2".to_string())),
+            LinkPurpose::AddPlace,
+            100,
+        ));
+        let (payload, _) = other.rsplit_once('.').unwrap();
+        assert_eq!(
+            signer.verify(
+                &format!("{payload}.{signature}"),
+                LinkPurpose::AddPlace,
+                100
+            ),
+            Err(LinkError::InvalidSignature)
+        );
+        assert_eq!(
+            LinkSigner::new(b"other").verify(&token, LinkPurpose::AddPlace, 100),
+            Err(LinkError::InvalidSignature)
+        );
+        assert_eq!(
+            signer.verify(&token, LinkPurpose::AddPlace, 100 + 2 * 60 * 60),
+            Err(LinkError::Expired)
+        );
+        assert_eq!(
+            signer.verify(&token, LinkPurpose::Manage, 100),
+            Err(LinkError::WrongPurpose)
+        );
+    }
+
+    #[test]
+    fn it_binds_csrf_tokens_to_their_link() {
+        let signer = LinkSigner::new(b"secret");
+        let token = signer.sign(&FormLink::new(&group(), LinkPurpose::AddPlace, 100));
+        let other = signer.sign(&FormLink::new(&group(), LinkPurpose::AddPlace, 200));
+        let csrf = signer.csrf_token(&token);
+        assert_eq!(signer.verify_csrf(&token, &csrf), Ok(()));
+        assert_eq!(
+            signer.verify_csrf(&other, &csrf),
+            Err(LinkError::InvalidSignature)
+        );
+        assert_ne!(csrf, signer.csrf_token(&token));
+    }
+
+    #[test]
+    fn it_signs_each_kind_of_token_with_its_own_key() {
+        let signer = LinkSigner::new(b"secret");
+        let invite = signer.invite("R1", 100);
+        assert_eq!(
+            verified(&signer.invites, &invite),
+            Ok(format!("web_R1.{}", 100 + INVITE_TTL_SECONDS).as_str())
+        );
+        assert_eq!(
+            verified(&signer.links, &invite),
+            Err(LinkError::InvalidSignature)
+        );
+        assert_eq!(
+            verified(&signer.csrf, &invite),
+            Err(LinkError::InvalidSignature)
+        );
+        assert_eq!(
+            signer.verify_session(&invite),
+            Err(LinkError::InvalidSignature)
+        );
+        assert_eq!(
+            signer.verify_session(&signer.session("alice")),
+            Ok("alice".to_string())
+        );
+    }
+
+    #[test]
+    fn it_expires_web_chat_invites() {
+        let signer = LinkSigner::new(b"secret");
+        let invite = signer.invite("R1", 100);
+        assert_eq!(signer.verify_invite(&invite, 100), Ok("R1".to_string()));
+        assert_eq!(
+            signer.verify_invite(&invite, 100 + INVITE_TTL_SECONDS + 1),
+            Err(LinkError::Expired)
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use crate::app::address::Address;
+use crate::app::coordinates::Coordinates;
+use crate::app::core::{Meal, Place};
+use crate::app::history;
+use crate::app::jar::Jar;
+use crate::gcp::api::FirebaseApi;
+use crate::http::HttpResult;
+
+/// A place with everything the management page shows about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaceDetails {
+    pub key: String,
+    pub name: String,
+    pub meals: Vec<Meal>,
+    pub coordinates: Option<Coordinates>,
+    pub address: Option<Address>,
+    pub tags: Vec<String>,
+    pub added_by: Option<String>,
+    pub link: Option<String>,
+    pub last_visit: Option<u64>,
+}
+
+impl PlaceDetails {
+    pub fn place(&self) -> Place {
+        Place {
+            key: self.key.clone(),
+            name: self.name.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaceSort {
+    Name,
+    LastVisit,
+    Meals,
+}
+
+impl PlaceSort {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "name" => Some(PlaceSort::Name),
+            "last_visit" => Some(PlaceSort::LastVisit),
+            "meals" => Some(PlaceSort::Meals),
+            _ => None,
+        }
+    }
+}
+
+/// Changes applied at once to the places selected on the management page
+#[derive(Debug, Clone, PartialEq)]
+pub enum BulkAction {
+    Delete,
+    SetMeals(Vec<Meal>),
+    AddTag(String),
+    RemoveTag(String),
+}
+
+pub async fn get_places<T: FirebaseApi + Sync>(
+    jar: &Jar,
+    firebase_client: &T,
+) -> HttpResult<Vec<PlaceDetails>> {
+    let mut places = firebase_client.get_place_details(jar).await?;
+    let visits = history::last_visits(&firebase_client.get_history(jar).await?);
+    for place in places.iter_mut() {
+        place.last_visit = visits.get(&place.name).copied();
+    }
+    Ok(places)
+}
+
+/// Places matching the query on their name, area or tags, in the requested order
+pub fn search(
+    places: Vec<PlaceDetails>,
+    query: &str,
+    sort: PlaceSort,
+    descending: bool,
+) -> Vec<PlaceDetails> {
+    let query = query.trim().to_lowercase();
+    let mut places: Vec<PlaceDetails> = places
+        .into_iter()
+        .filter(|place| {
+            query.is_empty()
+                || place.name.to_lowercase().contains(&query)
+                || place
+                    .address
+                    .as_ref()
+                    .is_some_and(|address| address.is_in(&query))
+                || place
+                    .tags
+                    .iter()
+                    .any(|tag| tag.to_lowercase().contains(&query))
+        })
+        .collect();
+    match sort {
+        PlaceSort::Name => places.sort_by(|a, b| a.name.cmp(&b.name)),
+        PlaceSort::LastVisit => places.sort_by_key(|place| place.last_visit),
+        PlaceSort::Meals => places.sort_by_key(|place| place.meals.len()),
+    }
+    if descending {
+        places.reverse();
+    }
+    places
+}
+
+pub async fn apply_bulk_action<T: FirebaseApi + Sync>(
+    jar: &Jar,
+    firebase_client: &T,
+    places: &[PlaceDetails],
+    keys: &[String],
+    action: &BulkAction,
+) -> HttpResult<usize> {
+    let mut updated = 0;
+    for place in places.iter().filter(|place| keys.contains(&place.key)) {
+        match action {
+            BulkAction::Delete => {
+                firebase_client.delete_place(jar, &place.place()).await?;
+            }
+            BulkAction::SetMeals(meals) => {
+                let mut place = place.clone();
+                place.meals = meals.clone();
+                firebase_client.update_place(jar, &place).await?;
+            }
+            BulkAction::AddTag(tag) => {
+                if place.tags.contains(tag) {
+                    continue;
+                }
+                let mut place = place.clone();
+                place.tags.push(tag.clone());
+                firebase_client.update_place(jar, &place).await?;
+            }
+            BulkAction::RemoveTag(tag) => {
+                if !place.tags.contains(tag) {
+                    continue;
+                }
+                let mut place = place.clone();
+                place.tags.retain(|t| t != tag);
+                firebase_client.update_place(jar, &place).await?;
+            }
+        }
+        updated += 1;
+    }
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::core::Meal;
+    use crate::app::manage::{search, PlaceDetails, PlaceSort};
+
+    fn place(name: &str, tags: &[&str], last_visit: Option<u64>) -> PlaceDetails {
+        PlaceDetails {
+            key: name.to_string(),
+            name: name.to_string(),
+            meals: vec![Meal::Lunch],
+            coordinates: None,
+            address: None,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            added_by: None,
+            link: None,
+            last_visit,
+        }
+    }
+
+    #[test]
+    fn it_searches_names_and_tags() {
+        let places = vec![
+            place("Ramen A", &["noodles"], Some(3)),
+            place("Curry", &[], None),
+            place("Udon", &["Noodles"], Some(1)),
+        ];
+        let names =
+            |places: Vec<PlaceDetails>| places.into_iter().map(|p| p.name).collect::<Vec<String>>();
+        assert_eq!(
+            names(search(places.clone(), "noodle", PlaceSort::Name, false)),
+            vec!["Ramen A", "Udon"]
+        );
+        assert_eq!(
+            names(search(places, "", PlaceSort::LastVisit, true)),
+            vec!["Ramen A", "Udon", "Curry"]
+        );
+    }
+}
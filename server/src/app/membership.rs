@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+
+use crate::app::jar::Jar;
+use crate::app::session;
+use crate::gcp::api::FirebaseApi;
+use crate::http::HttpResult;
+
+// Time given to a group to invite the bot back before its data is archived
+const ARCHIVE_GRACE_PERIOD_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JarStatus {
+    pub active: bool,
+    pub left_at: Option<u64>,
+    pub archive_after: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Member {
+    pub joined_at: u64,
+}
+
+impl JarStatus {
+    fn active() -> Self {
+        JarStatus {
+            active: true,
+            left_at: None,
+            archive_after: None,
+        }
+    }
+
+    fn inactive(now: u64) -> Self {
+        JarStatus {
+            active: false,
+            left_at: Some(now),
+            archive_after: Some(now + ARCHIVE_GRACE_PERIOD_SECONDS),
+        }
+    }
+}
+
+/// The bot joined or was followed again; any pending archival is cancelled.
+/// Returns whether the jar was not active yet, which is when the join deserves a welcome.
+pub async fn activate<T: FirebaseApi + Sync>(jar: &Jar, firebase_client: &T) -> HttpResult<bool> {
+    let was_active = firebase_client
+        .get_jar_status(jar)
+        .await?
+        .is_some_and(|status| status.active);
+    if was_active {
+        return Ok(false);
+    }
+    firebase_client
+        .set_jar_status(jar, &JarStatus::active())
+        .await?;
+    firebase_client.cancel_archival(jar).await?;
+    Ok(true)
+}
+
+/// The bot left the group or was unfollowed; the jar data is archived after a grace period
+pub async fn deactivate<T: FirebaseApi + Sync>(
+    jar: &Jar,
+    firebase_client: &T,
+    now: u64,
+) -> HttpResult<()> {
+    let status = JarStatus::inactive(now);
+    firebase_client.set_jar_status(jar, &status).await?;
+    if let Some(archive_after) = status.archive_after {
+        firebase_client
+            .schedule_archival(jar, archive_after)
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn members_joined<T: FirebaseApi + Sync>(
+    jar: &Jar,
+    firebase_client: &T,
+    user_ids: &[String],
+) -> HttpResult<()> {
+    let member = Member {
+        joined_at: session::now(),
+    };
+    for user_id in user_ids {
+        firebase_client.add_member(jar, user_id, &member).await?;
+    }
+    Ok(())
+}
+
+pub async fn members_left<T: FirebaseApi + Sync>(
+    jar: &Jar,
+    firebase_client: &T,
+    user_ids: &[String],
+) -> HttpResult<()> {
+    for user_id in user_ids {
+        firebase_client.remove_member(jar, user_id).await?;
+    }
+    Ok(())
+}
+
+/// Archive the jars whose grace period is over; returns the archived jars
+pub async fn archive_inactive_jars<T: FirebaseApi + Sync>(
+    firebase_client: &T,
+    now: u64,
+) -> HttpResult<Vec<Jar>> {
+    let due = firebase_client.get_due_archivals(now).await?;
+    let mut archived = vec![];
+    for jar in due {
+        // The bot may have been invited back after the archival was listed
+        let status = firebase_client.get_jar_status(&jar).await?;
+        if status.is_some_and(|s| s.active) {
+            firebase_client.cancel_archival(&jar).await?;
+            continue;
+        }
+        firebase_client.archive_jar(&jar).await?;
+        archived.push(jar);
+    }
+    Ok(archived)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::core::Meal;
+    use crate::app::jar::Jar;
+    use crate::app::membership::{
+        activate, archive_inactive_jars, deactivate, members_joined, members_left,
+        ARCHIVE_GRACE_PERIOD_SECONDS,
+    };
+    use crate::gcp::api::fixtures::MemoryFirebase;
+    use crate::gcp::api::FirebaseApi;
+
+    const NOW: u64 = 1_700_000_000;
+
+    async fn jar_with_a_place(firebase_client: &MemoryFirebase) -> Jar {
+        let jar = Jar::new("group_G1");
+        activate(&jar, firebase_client).await.unwrap();
+        firebase_client
+            .add_place(&jar, "一蘭", &[Meal::Lunch], None)
+            .await
+            .unwrap();
+        jar
+    }
+
+    #[tokio::test]
+    async fn it_keeps_a_jar_rejoined_within_the_grace_period() {
+        let firebase_client = MemoryFirebase::default();
+        let jar = jar_with_a_place(&firebase_client).await;
+
+        deactivate(&jar, &firebase_client, NOW).await.unwrap();
+        let status = firebase_client.get_jar_status(&jar).await.unwrap().unwrap();
+        assert!(!status.active);
+        assert_eq!(
+            status.archive_after,
+            Some(NOW + ARCHIVE_GRACE_PERIOD_SECONDS)
+        );
+
+        assert!(activate(&jar, &firebase_client).await.unwrap());
+        let later = NOW + ARCHIVE_GRACE_PERIOD_SECONDS + 1;
+        assert!(archive_inactive_jars(&firebase_client, later)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(firebase_client.get_all_places(&jar).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_archives_a_jar_once_the_grace_period_is_over() {
+        let firebase_client = MemoryFirebase::default();
+        let jar = jar_with_a_place(&firebase_client).await;
+        deactivate(&jar, &firebase_client, NOW).await.unwrap();
+
+        let last_day = NOW + ARCHIVE_GRACE_PERIOD_SECONDS - 1;
+        assert!(archive_inactive_jars(&firebase_client, last_day)
+            .await
+            .unwrap()
+            .is_empty());
+        let after = NOW + ARCHIVE_GRACE_PERIOD_SECONDS;
+        let archived = archive_inactive_jars(&firebase_client, after)
+            .await
+            .unwrap();
+        assert_eq!(
+            archived.iter().map(Jar::to_string).collect::<Vec<_>>(),
+            vec!["group_G1"]
+        );
+        assert!(firebase_client
+            .get_all_places(&jar)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            firebase_client.archived_jar(&jar, |archived| archived.map(|a| a.places.len())),
+            Some(1)
+        );
+        // Archived once
+        assert!(archive_inactive_jars(&firebase_client, after + 1)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_welcomes_a_jar_only_when_it_becomes_active() {
+        let firebase_client = MemoryFirebase::default();
+        let jar = Jar::new("web_R1");
+        assert!(activate(&jar, &firebase_client).await.unwrap());
+        assert!(!activate(&jar, &firebase_client).await.unwrap());
+        deactivate(&jar, &firebase_client, NOW).await.unwrap();
+        assert!(activate(&jar, &firebase_client).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_member_roster() {
+        let firebase_client = MemoryFirebase::default();
+        let jar = Jar::new("group_G1");
+        let users = ["U1".to_string(), "U2".to_string()];
+        members_joined(&jar, &firebase_client, &users)
+            .await
+            .unwrap();
+        members_left(&jar, &firebase_client, &users[..1])
+            .await
+            .unwrap();
+
+        let members = firebase_client.get_members(&jar).await.unwrap();
+        assert_eq!(members.keys().collect::<Vec<_>>(), vec!["U2"]);
+    }
+}
@@ -0,0 +1,176 @@
+use regex::Regex;
+use reqwest::Url;
+
+use crate::app::coordinates::Coordinates;
+
+/// A place read from a map or restaurant guide link pasted in the chat, without any request
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaceUrl {
+    pub url: String,
+    pub name: Option<String>,
+    pub coordinates: Option<Coordinates>,
+}
+
+enum Site {
+    GoogleMaps,
+    // Short links and guide pages do not name the place
+    Opaque,
+}
+
+fn site(url: &Url) -> Option<Site> {
+    let host = url.host_str()?.trim_start_matches("www.");
+    match host {
+        "maps.app.goo.gl" | "goo.gl" => Some(Site::Opaque),
+        "tabelog.com" | "s.tabelog.com" => Some(Site::Opaque),
+        _ if host.starts_with("maps.google.") => Some(Site::GoogleMaps),
+        _ if host.starts_with("google.") && url.path().starts_with("/maps") => {
+            Some(Site::GoogleMaps)
+        }
+        _ => None,
+    }
+}
+
+fn decode(component: &str) -> String {
+    // Path segments share the encoding of the query values, '+' standing for spaces
+    serde_urlencoded::from_str::<Vec<(String, String)>>(&format!(
+        "v={}",
+        component.replace('&', "%26")
+    ))
+    .ok()
+    .and_then(|mut pairs| pairs.pop())
+    .map_or_else(|| component.to_string(), |(_, value)| value)
+}
+
+fn coordinates(latitude: &str, longitude: &str) -> Option<Coordinates> {
+    let coordinates = Coordinates {
+        latitude: latitude.parse().ok()?,
+        longitude: longitude.parse().ok()?,
+    };
+    (coordinates.latitude.abs() <= 90.0 && coordinates.longitude.abs() <= 180.0)
+        .then_some(coordinates)
+}
+
+fn coordinates_pair(text: &str) -> Option<Coordinates> {
+    let (latitude, longitude) = text.split_once(',')?;
+    coordinates(latitude.trim(), longitude.trim())
+}
+
+fn google_maps(url: &Url) -> (Option<String>, Option<Coordinates>) {
+    let query = |key: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.to_string())
+    };
+    let searched = query("q").or_else(|| query("query"));
+    // The data parameter pins the place itself, the '@' segment only centers the map
+    let pin = Regex::new(r"!3d(-?\d+\.?\d*)!4d(-?\d+\.?\d*)").unwrap();
+    let center = Regex::new(r"/@(-?\d+\.?\d*),(-?\d+\.?\d*)").unwrap();
+    let path = url.path();
+    let coordinates = pin
+        .captures(path)
+        .or_else(|| center.captures(path))
+        .and_then(|c| coordinates(&c[1], &c[2]))
+        .or_else(|| searched.as_deref().and_then(coordinates_pair))
+        .or_else(|| query("ll").as_deref().and_then(coordinates_pair));
+    let segments: Vec<&str> = url.path_segments().map_or(vec![], Iterator::collect);
+    let name = segments
+        .windows(2)
+        .find(|pair| pair[0] == "place" || pair[0] == "search")
+        .map(|pair| decode(pair[1]))
+        .filter(|name| !name.trim().is_empty())
+        .or(searched)
+        .filter(|name| coordinates_pair(name).is_none());
+    (name, coordinates)
+}
+
+impl PlaceUrl {
+    /// The supported link a message consists of; links shared along other text are left alone
+    pub fn parse(text: &str) -> Option<PlaceUrl> {
+        let text = text.trim();
+        if !(text.starts_with("https://") || text.starts_with("http://"))
+            || text.contains(char::is_whitespace)
+        {
+            return None;
+        }
+        let url = Url::parse(text).ok()?;
+        let (name, coordinates) = match site(&url)? {
+            Site::GoogleMaps => google_maps(&url),
+            Site::Opaque => (None, None),
+        };
+        Some(PlaceUrl {
+            url: text.to_string(),
+            name,
+            coordinates,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::coordinates::Coordinates;
+    use crate::app::place_url::PlaceUrl;
+
+    #[test]
+    fn it_reads_google_maps_places() {
+        let url = "https://www.google.com/maps/place/%E4%B8%80%E8%98%AD+%E6%B8%8B%E8%B0%B7%E5%BA%97/@35.6611,139.6982,17z/data=!3m1!4b1!4m6!3m5!1s0x0:0x0!8m2!3d35.66124!4d139.69869";
+        let place = PlaceUrl::parse(url).unwrap();
+        assert_eq!(place.name.as_deref(), Some("一蘭 渋谷店"));
+        assert_eq!(
+            place.coordinates,
+            Some(Coordinates {
+                latitude: 35.66124,
+                longitude: 139.69869
+            })
+        );
+
+        let place = PlaceUrl::parse("https://maps.google.co.jp/maps?q=35.6812,139.7671").unwrap();
+        assert_eq!(place.name, None);
+        assert_eq!(
+            place.coordinates,
+            Some(Coordinates {
+                latitude: 35.6812,
+                longitude: 139.7671
+            })
+        );
+
+        let place =
+            PlaceUrl::parse("https://www.google.com/maps/search/?api=1&query=天下一品").unwrap();
+        assert_eq!(place.name.as_deref(), Some("天下一品"));
+        assert_eq!(place.coordinates, None);
+    }
+
+    #[test]
+    fn it_reads_nothing_but_the_link_of_opaque_links() {
+        let place =
+            PlaceUrl::parse(" https://tabelog.com/tokyo/A1303/A130301/13001234/\n").unwrap();
+        assert_eq!(
+            place.url,
+            "https://tabelog.com/tokyo/A1303/A130301/13001234/"
+        );
+        assert_eq!(place.name, None);
+        assert_eq!(place.coordinates, None);
+
+        let place = PlaceUrl::parse("https://maps.app.goo.gl/AbCd").unwrap();
+        assert_eq!(place.name, None);
+    }
+
+    #[test]
+    fn it_ignores_other_links() {
+        assert_eq!(PlaceUrl::parse("https://example.com/maps/place/x"), None);
+        assert_eq!(PlaceUrl::parse("一蘭"), None);
+    }
+
+    #[test]
+    fn it_ignores_links_shared_along_other_text() {
+        assert_eq!(
+            PlaceUrl::parse("ここ行かない？ https://maps.google.co.jp/maps?q=35.6812,139.7671"),
+            None
+        );
+        assert_eq!(
+            PlaceUrl::parse(
+                "一蘭 - 渋谷/ラーメン\nhttps://tabelog.com/tokyo/A1303/A130301/13001234/"
+            ),
+            None
+        );
+    }
+}
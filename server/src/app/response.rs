@@ -0,0 +1,185 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+
+use crate::app::coordinates::Coordinates;
+use crate::app::core::{Client, Meal};
+use crate::app::session::{PendingInput, Session, SessionState};
+use crate::app::user_action::UserAction;
+use crate::http::HttpResult;
+
+/// Actions offered along a reply, following the state of the chat
+#[derive(Debug, Clone, PartialEq)]
+pub enum Choices {
+    Welcome,
+    Idle(Option<Coordinates>),
+    ActiveDraw(Option<Coordinates>),
+    NoShops(Meal),
+    NoShopsClosedBy(Meal, Coordinates),
+    /// Pick one of this many geocoding candidates
+    Candidates(usize),
+    AwaitingLocation,
+    /// Idle at a shared location named on the map, which can be added as a place
+    SharedPlace(String, Coordinates),
+    /// Idle without a shared location, offering to draw around the locations saved in the jar
+    SavedLocations(Vec<String>),
+}
+
+impl Choices {
+    /// Buttons of the platforms without the Line location picker and add form
+    pub(crate) fn buttons(&self) -> Vec<UserAction> {
+        match self.clone() {
+            Choices::Welcome => vec![
+                UserAction::Seed,
+                UserAction::Draw(Meal::Lunch, None),
+                UserAction::Draw(Meal::Dinner, None),
+            ],
+            Choices::Idle(coordinates) => {
+                let mut actions = vec![
+                    UserAction::Draw(Meal::Lunch, coordinates.clone()),
+                    UserAction::Draw(Meal::Dinner, coordinates.clone()),
+                ];
+                if coordinates.is_some() {
+                    actions.push(UserAction::ClearLocation);
+                }
+                actions
+            }
+            Choices::ActiveDraw(coordinates) => vec![
+                UserAction::ArchiveCurrent(coordinates.clone()),
+                UserAction::Postpone(coordinates.clone()),
+                UserAction::DeleteCurrent(coordinates),
+            ],
+            Choices::NoShops(_) => vec![],
+            Choices::NoShopsClosedBy(_, _) => vec![UserAction::ClearLocation],
+            Choices::Candidates(count) => (0..count)
+                .map(UserAction::ChooseCandidate)
+                .chain([UserAction::NoCandidate])
+                .collect(),
+            Choices::AwaitingLocation => vec![],
+            Choices::SharedPlace(name, coordinates) => {
+                let mut actions = vec![UserAction::AddAt(name, coordinates.clone())];
+                actions.extend(Choices::Idle(Some(coordinates)).buttons());
+                actions
+            }
+            Choices::SavedLocations(names) => {
+                let mut actions = Choices::Idle(None).buttons();
+                for name in names {
+                    actions.push(UserAction::DrawFrom(Meal::Lunch, name.clone()));
+                    actions.push(UserAction::DrawFrom(Meal::Dinner, name));
+                }
+                actions
+            }
+        }
+    }
+}
+
+impl From<&Session> for Choices {
+    fn from(session: &Session) -> Self {
+        match &session.state {
+            SessionState::Idle => Choices::Idle(None),
+            SessionState::LocationSet { origin } => Choices::Idle(Some(origin.clone())),
+            SessionState::ActiveDraw { origin, .. } => Choices::ActiveDraw(origin.clone()),
+            SessionState::AwaitingInput { input, previous } => match input {
+                PendingInput::PlaceName => previous.as_ref().into(),
+                PendingInput::Candidate { candidates, .. } => Choices::Candidates(candidates.len()),
+                PendingInput::PlaceLocation(_) => Choices::AwaitingLocation,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Text(String),
+    Choices(String, Choices),
+    Location {
+        title: String,
+        coordinates: Coordinates,
+    },
+    Error(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Audience {
+    /// Everyone in the chat
+    Chat,
+    /// Only the user who sent the request
+    Requester,
+}
+
+/// What the bot answers to an action, rendered by the platform the action came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub audience: Audience,
+    pub replies: Vec<Reply>,
+}
+
+impl Response {
+    pub fn none() -> Self {
+        Response {
+            audience: Audience::Chat,
+            replies: vec![],
+        }
+    }
+
+    pub fn text(text: &str) -> Self {
+        Response::to_chat(Reply::Text(text.to_string()))
+    }
+
+    pub fn choices(text: &str, choices: Choices) -> Self {
+        Response::to_chat(Reply::Choices(text.to_string(), choices))
+    }
+
+    pub fn error<E: Debug>(error: &E) -> Self {
+        Response::to_chat(Reply::Error(format!("{error:?}")))
+    }
+
+    pub fn to_chat(reply: Reply) -> Self {
+        Response {
+            audience: Audience::Chat,
+            replies: vec![reply],
+        }
+    }
+
+    pub fn private(self) -> Self {
+        Response {
+            audience: Audience::Requester,
+            ..self
+        }
+    }
+}
+
+/// Delivers responses on a chat platform
+#[async_trait]
+pub trait Messenger {
+    async fn respond(&self, client: &Client, host: &str, response: Response);
+
+    /// Name of the chat, used as the jar label
+    async fn chat_name(&self, client: &Client) -> HttpResult<String>;
+}
+
+#[cfg(test)]
+pub mod fixtures {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use crate::app::core::Client;
+    use crate::app::response::{Messenger, Response};
+    use crate::http::HttpResult;
+
+    /// Keeps the responses instead of sending them
+    #[derive(Default)]
+    pub struct RecordingMessenger(pub Mutex<Vec<Response>>);
+
+    #[async_trait]
+    impl Messenger for RecordingMessenger {
+        async fn respond(&self, _client: &Client, _host: &str, response: Response) {
+            self.0.lock().unwrap().push(response);
+        }
+
+        async fn chat_name(&self, _client: &Client) -> HttpResult<String> {
+            Ok("chat".to_string())
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use serde::Deserialize;
+
+use crate::app::core::Meal;
+use crate::app::jar::JarError;
+
+const SEED_PLACES_PATH: &str = "./resources/seed_places.json";
+
+/// Template place offered to new jars
+#[derive(Debug, Deserialize, Clone)]
+pub struct SeedPlace {
+    pub name: String,
+    pub meals: Vec<Meal>,
+}
+
+pub fn load_seed_places() -> Result<Vec<SeedPlace>, JarError> {
+    let content = std::fs::read_to_string(SEED_PLACES_PATH)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::seed::load_seed_places;
+
+    #[test]
+    fn it_loads_the_bundled_seed_places() {
+        let places = load_seed_places().ok().unwrap();
+        assert!(!places.is_empty());
+        assert!(places.iter().all(|p| !p.meals.is_empty()));
+    }
+}
@@ -0,0 +1,436 @@
+use std::fmt::{Display, Formatter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::app::coordinates::Coordinates;
+use crate::app::core::Place;
+use crate::geocoding::api::Candidate;
+
+// How long a shared location is used as the draw origin
+const LOCATION_TTL_SECONDS: u64 = 3 * 60 * 60;
+// How long the bot waits for a reply after asking something
+const AWAITING_INPUT_TTL_SECONDS: u64 = 10 * 60;
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PendingInput {
+    PlaceName,
+    /// Which of the geocoding candidates is the added place
+    Candidate {
+        place: Place,
+        candidates: Vec<Candidate>,
+    },
+    /// A shared location to attach to the added place
+    PlaceLocation(Place),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SessionState {
+    Idle,
+    LocationSet {
+        origin: Coordinates,
+    },
+    ActiveDraw {
+        place: Place,
+        origin: Option<Coordinates>,
+    },
+    AwaitingInput {
+        input: PendingInput,
+        previous: Box<Session>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    Drawn(Place),
+    DrawResolved,
+    LocationShared(Coordinates),
+    LocationCleared,
+    InputRequested(PendingInput),
+    InputReceived,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TransitionError {
+    AlreadyDrawn,
+    NoActiveDraw,
+    NotAwaitingInput,
+}
+
+impl Display for TransitionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+/// Conversation state of a jar, persisted in the datastore
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Session {
+    pub state: SessionState,
+    pub expires_at: Option<u64>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new(SessionState::Idle, None)
+    }
+}
+
+impl Session {
+    fn new(state: SessionState, expires_at: Option<u64>) -> Self {
+        Session { state, expires_at }
+    }
+
+    fn location_set(origin: Coordinates, now: u64) -> Self {
+        Session::new(
+            SessionState::LocationSet { origin },
+            Some(now + LOCATION_TTL_SECONDS),
+        )
+    }
+
+    fn idle_or_located(origin: Option<Coordinates>, now: u64) -> Self {
+        match origin {
+            None => Session::default(),
+            Some(origin) => Session::location_set(origin, now),
+        }
+    }
+
+    /// The origin for location filtered draws
+    pub fn origin(&self) -> Option<&Coordinates> {
+        match &self.state {
+            SessionState::Idle => None,
+            SessionState::LocationSet { origin } => Some(origin),
+            SessionState::ActiveDraw { origin, .. } => origin.as_ref(),
+            SessionState::AwaitingInput { previous, .. } => previous.origin(),
+        }
+    }
+
+    pub fn pending_input(&self) -> Option<&PendingInput> {
+        match &self.state {
+            SessionState::AwaitingInput { input, .. } => Some(input),
+            _ => None,
+        }
+    }
+
+    /// The session as seen at `now`; expired states fall back to the state they came from
+    pub fn at(self, now: u64) -> Session {
+        match self.expires_at {
+            Some(expires_at) if expires_at <= now => match self.state {
+                SessionState::AwaitingInput { previous, .. } => previous.at(now),
+                SessionState::LocationSet { .. } => Session::default(),
+                SessionState::Idle | SessionState::ActiveDraw { .. } => {
+                    Session::new(self.state, None)
+                }
+            },
+            _ => self,
+        }
+    }
+
+    pub fn apply(self, event: SessionEvent, now: u64) -> Result<Session, TransitionError> {
+        let origin = self.origin().cloned();
+        match (self.state, event) {
+            (SessionState::ActiveDraw { .. }, SessionEvent::Drawn(_)) => {
+                Err(TransitionError::AlreadyDrawn)
+            }
+            (_, SessionEvent::Drawn(place)) => Ok(Session::new(
+                SessionState::ActiveDraw { place, origin },
+                None,
+            )),
+
+            (SessionState::ActiveDraw { origin, .. }, SessionEvent::DrawResolved) => {
+                Ok(Session::idle_or_located(origin, now))
+            }
+            (_, SessionEvent::DrawResolved) => Err(TransitionError::NoActiveDraw),
+
+            (SessionState::ActiveDraw { place, .. }, SessionEvent::LocationShared(c)) => {
+                Ok(Session::new(
+                    SessionState::ActiveDraw {
+                        place,
+                        origin: Some(c),
+                    },
+                    None,
+                ))
+            }
+            (SessionState::AwaitingInput { input, previous }, SessionEvent::LocationShared(c)) => {
+                let previous = previous.apply(SessionEvent::LocationShared(c), now)?;
+                Ok(Session::new(
+                    SessionState::AwaitingInput {
+                        input,
+                        previous: Box::new(previous),
+                    },
+                    self.expires_at,
+                ))
+            }
+            (_, SessionEvent::LocationShared(c)) => Ok(Session::location_set(c, now)),
+
+            (SessionState::ActiveDraw { place, .. }, SessionEvent::LocationCleared) => {
+                Ok(Session::new(
+                    SessionState::ActiveDraw {
+                        place,
+                        origin: None,
+                    },
+                    None,
+                ))
+            }
+            (SessionState::AwaitingInput { input, previous }, SessionEvent::LocationCleared) => {
+                let previous = previous.apply(SessionEvent::LocationCleared, now)?;
+                Ok(Session::new(
+                    SessionState::AwaitingInput {
+                        input,
+                        previous: Box::new(previous),
+                    },
+                    self.expires_at,
+                ))
+            }
+            (_, SessionEvent::LocationCleared) => Ok(Session::default()),
+
+            (SessionState::AwaitingInput { previous, .. }, SessionEvent::InputRequested(input)) => {
+                Ok(Session::new(
+                    SessionState::AwaitingInput { input, previous },
+                    Some(now + AWAITING_INPUT_TTL_SECONDS),
+                ))
+            }
+            (state, SessionEvent::InputRequested(input)) => Ok(Session::new(
+                SessionState::AwaitingInput {
+                    input,
+                    previous: Box::new(Session::new(state, self.expires_at)),
+                },
+                Some(now + AWAITING_INPUT_TTL_SECONDS),
+            )),
+
+            (SessionState::AwaitingInput { previous, .. }, SessionEvent::InputReceived) => {
+                Ok(*previous)
+            }
+            (_, SessionEvent::InputReceived) => Err(TransitionError::NotAwaitingInput),
+        }
+    }
+
+    /// Align the session with the current draw stored for the jar, dropping any pending input
+    pub fn reconcile(self, current_draw: Option<Place>, now: u64) -> Session {
+        let origin = self.origin().cloned();
+        match (self.state, current_draw) {
+            (SessionState::AwaitingInput { previous, .. }, draw) => previous.reconcile(draw, now),
+            (SessionState::ActiveDraw { place, origin }, Some(draw)) if place.key == draw.key => {
+                Session::new(SessionState::ActiveDraw { place, origin }, None)
+            }
+            (_, Some(place)) => Session::new(SessionState::ActiveDraw { place, origin }, None),
+            (SessionState::ActiveDraw { origin, .. }, None) => {
+                Session::idle_or_located(origin, now)
+            }
+            (state, None) => Session::new(state, self.expires_at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::coordinates::Coordinates;
+    use crate::app::core::Place;
+    use crate::app::session::{
+        PendingInput, Session, SessionEvent, SessionState, TransitionError,
+        AWAITING_INPUT_TTL_SECONDS, LOCATION_TTL_SECONDS,
+    };
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn place() -> Place {
+        Place {
+            key: "key".to_string(),
+            name: "name".to_string(),
+        }
+    }
+
+    fn origin() -> Coordinates {
+        Coordinates {
+            latitude: 35.6581,
+            longitude: 139.7017,
+        }
+    }
+
+    fn located() -> Session {
+        Session::default()
+            .apply(SessionEvent::LocationShared(origin()), NOW)
+            .unwrap()
+    }
+
+    fn drawn(session: Session) -> Session {
+        session.apply(SessionEvent::Drawn(place()), NOW).unwrap()
+    }
+
+    fn awaiting(session: Session) -> Session {
+        session
+            .apply(SessionEvent::InputRequested(PendingInput::PlaceName), NOW)
+            .unwrap()
+    }
+
+    #[test]
+    fn it_draws_from_idle_and_location_set() {
+        assert_eq!(
+            drawn(Session::default()).state,
+            SessionState::ActiveDraw {
+                place: place(),
+                origin: None
+            }
+        );
+        assert_eq!(
+            drawn(located()).state,
+            SessionState::ActiveDraw {
+                place: place(),
+                origin: Some(origin())
+            }
+        );
+    }
+
+    #[test]
+    fn it_draws_while_awaiting_input() {
+        let session = drawn(awaiting(located()));
+        assert_eq!(session.origin(), Some(&origin()));
+        assert!(session.pending_input().is_none());
+    }
+
+    #[test]
+    fn it_rejects_a_second_draw() {
+        assert_eq!(
+            drawn(Session::default()).apply(SessionEvent::Drawn(place()), NOW),
+            Err(TransitionError::AlreadyDrawn)
+        );
+    }
+
+    #[test]
+    fn it_resolves_a_draw_back_to_the_previous_origin() {
+        let session = drawn(located())
+            .apply(SessionEvent::DrawResolved, NOW)
+            .unwrap();
+        assert_eq!(session, located());
+
+        let session = drawn(Session::default())
+            .apply(SessionEvent::DrawResolved, NOW)
+            .unwrap();
+        assert_eq!(session, Session::default());
+    }
+
+    #[test]
+    fn it_rejects_resolving_without_draw() {
+        for session in [Session::default(), located(), awaiting(Session::default())] {
+            assert_eq!(
+                session.apply(SessionEvent::DrawResolved, NOW),
+                Err(TransitionError::NoActiveDraw)
+            );
+        }
+    }
+
+    #[test]
+    fn it_sets_the_location() {
+        let session = located();
+        assert_eq!(
+            session.state,
+            SessionState::LocationSet { origin: origin() }
+        );
+        assert_eq!(session.expires_at, Some(NOW + LOCATION_TTL_SECONDS));
+
+        let session = drawn(Session::default())
+            .apply(SessionEvent::LocationShared(origin()), NOW)
+            .unwrap();
+        assert_eq!(session.origin(), Some(&origin()));
+
+        let session = awaiting(Session::default())
+            .apply(SessionEvent::LocationShared(origin()), NOW)
+            .unwrap();
+        assert_eq!(session.pending_input(), Some(&PendingInput::PlaceName));
+        assert_eq!(session.origin(), Some(&origin()));
+    }
+
+    #[test]
+    fn it_clears_the_location() {
+        assert_eq!(
+            located().apply(SessionEvent::LocationCleared, NOW),
+            Ok(Session::default())
+        );
+        assert_eq!(
+            Session::default().apply(SessionEvent::LocationCleared, NOW),
+            Ok(Session::default())
+        );
+
+        let session = drawn(located())
+            .apply(SessionEvent::LocationCleared, NOW)
+            .unwrap();
+        assert_eq!(session, drawn(Session::default()));
+
+        let session = awaiting(located())
+            .apply(SessionEvent::LocationCleared, NOW)
+            .unwrap();
+        assert_eq!(session.pending_input(), Some(&PendingInput::PlaceName));
+        assert_eq!(session.origin(), None);
+    }
+
+    #[test]
+    fn it_awaits_input_and_restores_the_previous_state() {
+        for previous in [Session::default(), located(), drawn(located())] {
+            let session = awaiting(previous.clone());
+            assert_eq!(session.pending_input(), Some(&PendingInput::PlaceName));
+            assert_eq!(session.expires_at, Some(NOW + AWAITING_INPUT_TTL_SECONDS));
+            assert_eq!(
+                session.apply(SessionEvent::InputReceived, NOW),
+                Ok(previous)
+            );
+        }
+    }
+
+    #[test]
+    fn it_does_not_nest_pending_inputs() {
+        let session = awaiting(awaiting(located()));
+        assert_eq!(
+            session.apply(SessionEvent::InputReceived, NOW),
+            Ok(located())
+        );
+    }
+
+    #[test]
+    fn it_rejects_input_when_not_awaiting() {
+        for session in [Session::default(), located(), drawn(Session::default())] {
+            assert_eq!(
+                session.apply(SessionEvent::InputReceived, NOW),
+                Err(TransitionError::NotAwaitingInput)
+            );
+        }
+    }
+
+    #[test]
+    fn it_expires_states() {
+        let later = NOW + LOCATION_TTL_SECONDS;
+        assert_eq!(located().at(later), Session::default());
+        assert_eq!(located().at(later - 1), located());
+
+        let session = awaiting(located()).at(NOW + AWAITING_INPUT_TTL_SECONDS);
+        assert_eq!(session, located());
+        let session = awaiting(located()).at(later);
+        assert_eq!(session, Session::default());
+
+        assert_eq!(drawn(located()).at(later), drawn(located()));
+    }
+
+    #[test]
+    fn it_reconciles_with_the_current_draw() {
+        assert_eq!(located().reconcile(Some(place()), NOW), drawn(located()));
+        assert_eq!(drawn(located()).reconcile(None, NOW), located());
+        assert_eq!(awaiting(located()).reconcile(None, NOW), located());
+        assert_eq!(Session::default().reconcile(None, NOW), Session::default());
+    }
+
+    #[test]
+    fn it_serializes_sessions() {
+        let session = awaiting(drawn(located()));
+        let json = serde_json::to_string(&session).unwrap();
+        assert_eq!(serde_json::from_str::<Session>(&json).unwrap(), session);
+    }
+}
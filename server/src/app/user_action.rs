@@ -1,10 +1,13 @@
 use std::fmt::Formatter;
 
 use crate::app::coordinates::Coordinates;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::digest;
 use serde::de::Visitor;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::app::core::Meal;
+use crate::app::core::{Action, Client, Meal};
 
 const DRAW_LUNCH_ACTION: &str = "lunch_action";
 const DRAW_DINNER_ACTION: &str = "dinner_action";
@@ -14,7 +17,20 @@ const ARCHIVE_ACTION: &str = "archive_action";
 const ADD_ACTION: &str = "add_action";
 const REFRESH_ACTION: &str = "refresh_action";
 const CLEAR_LOCATION_ACTION: &str = "clear_location_action";
+const SEED_ACTION: &str = "seed_action";
+const CHOOSE_CANDIDATE_ACTION: &str = "candidate_action";
+const NO_CANDIDATE_ACTION: &str = "no_candidate_action";
+const ADD_AT_ACTION: &str = "add_at_action";
+const DRAW_LUNCH_FROM_ACTION: &str = "lunch_from_action";
+const DRAW_DINNER_FROM_ACTION: &str = "dinner_from_action";
 
+// Longest quick reply label Line accepts
+const MAX_LABEL_LENGTH: usize = 20;
+// Bytes of the name hash identifying a saved location in a button
+const SAVED_LOCATION_ID_BYTES: usize = 6;
+
+// The draw origin is kept in the jar session; coordinates are only read from postbacks sent by
+// older quick replies and are never written back
 pub enum UserAction {
     Draw(Meal, Option<Coordinates>),
     Postpone(Option<Coordinates>),
@@ -23,6 +39,22 @@ pub enum UserAction {
     Add,
     ClearLocation,
     Refresh,
+    Seed,
+    /// Pick a geocoding candidate of the place being added, by its index
+    ChooseCandidate(usize),
+    NoCandidate,
+    /// Add the named spot of a shared location, at its coordinates
+    AddAt(String, Coordinates),
+    /// Draw around a location saved in the jar, by its name
+    DrawFrom(Meal, String),
+    /// A pressed `DrawFrom` button, which only carries the id of the location name
+    DrawFromSaved(Meal, String),
+}
+
+/// Short id of a saved location name; a whole name may not fit in a Telegram callback
+pub(crate) fn saved_location_id(name: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, name.as_bytes());
+    URL_SAFE_NO_PAD.encode(&hash.as_ref()[..SAVED_LOCATION_ID_BYTES])
 }
 
 impl UserAction {
@@ -34,6 +66,15 @@ impl UserAction {
     const LABEL_ARCHIVE_CURRENT: &str = "✓ 完";
     const LABEL_ADD: &str = "+ 加";
     const LABEL_CLEAR_LOCATION: &str = "消";
+    const LABEL_SEED: &str = "🌱 例";
+    const LABEL_NO_CANDIDATE: &str = "✗ 無";
+
+    fn draw_label(meal: &Meal) -> &'static str {
+        match meal {
+            Meal::Lunch => Self::LABEL_DRAW_LUNCH,
+            Meal::Dinner => Self::LABEL_DRAW_DINNER,
+        }
+    }
 
     pub fn label(&self) -> String {
         match self {
@@ -53,6 +94,45 @@ impl UserAction {
             UserAction::Add => Self::LABEL_ADD.to_string(),
             UserAction::Refresh => panic!("No quick reply for refresh"),
             UserAction::ClearLocation => Self::LABEL_CLEAR_LOCATION.to_string(),
+            UserAction::Seed => Self::LABEL_SEED.to_string(),
+            UserAction::ChooseCandidate(index) => {
+                format!("{}{}", Self::SUFFIX_COORDINATES, index + 1)
+            }
+            UserAction::NoCandidate => Self::LABEL_NO_CANDIDATE.to_string(),
+            UserAction::AddAt(name, _) => format!("{} {name}", Self::LABEL_ADD)
+                .chars()
+                .take(MAX_LABEL_LENGTH)
+                .collect(),
+            UserAction::DrawFrom(meal, name) => format!(
+                "{}{}{name}",
+                Self::draw_label(meal),
+                Self::SUFFIX_COORDINATES
+            )
+            .chars()
+            .take(MAX_LABEL_LENGTH)
+            .collect(),
+            UserAction::DrawFromSaved(meal, _) => {
+                format!("{}{}", Self::draw_label(meal), Self::SUFFIX_COORDINATES)
+            }
+        }
+    }
+
+    /// The action a button sent by the bot triggers for the chat it was pressed in
+    pub(crate) fn into_action(self, client: Client) -> Action {
+        match self {
+            UserAction::Draw(meal, coordinates) => Action::Draw(client, meal, coordinates, None),
+            UserAction::Postpone(_) => Action::PostponeCurrent(client),
+            UserAction::DeleteCurrent(_) => Action::RemoveCurrent(client),
+            UserAction::ArchiveCurrent(_) => Action::ArchiveCurrent(client),
+            UserAction::ClearLocation => Action::ClearLocation(client),
+            UserAction::Add => Action::RequestPlaceName(client),
+            UserAction::Refresh => Action::Refresh(client),
+            UserAction::Seed => Action::Seed(client),
+            UserAction::ChooseCandidate(index) => Action::ChooseCandidate(client, index),
+            UserAction::NoCandidate => Action::NoCandidate(client),
+            UserAction::AddAt(name, coordinates) => Action::AddAt(client, name, coordinates),
+            UserAction::DrawFrom(meal, name) => Action::DrawIn(client, meal, name),
+            UserAction::DrawFromSaved(meal, id) => Action::DrawFromSaved(client, meal, id),
         }
     }
 }
@@ -63,40 +143,46 @@ impl Serialize for UserAction {
         S: Serializer,
     {
         let relative_url = match self {
-            UserAction::Draw(meal, coordinates) => {
-                let path = match meal {
-                    Meal::Lunch => DRAW_LUNCH_ACTION,
-                    Meal::Dinner => DRAW_DINNER_ACTION,
-                };
-                path_with_coordinates(path, coordinates)
+            UserAction::Draw(Meal::Lunch, _) => DRAW_LUNCH_ACTION,
+            UserAction::Draw(Meal::Dinner, _) => DRAW_DINNER_ACTION,
+            UserAction::Postpone(_) => POSTPONE_ACTION,
+            UserAction::DeleteCurrent(_) => DELETE_ACTION,
+            UserAction::ArchiveCurrent(_) => ARCHIVE_ACTION,
+            UserAction::Add => ADD_ACTION,
+            UserAction::Refresh => REFRESH_ACTION,
+            UserAction::ClearLocation => CLEAR_LOCATION_ACTION,
+            UserAction::Seed => SEED_ACTION,
+            UserAction::ChooseCandidate(index) => {
+                return serializer
+                    .serialize_str(&format!("{CHOOSE_CANDIDATE_ACTION}?index={index}"));
             }
-            UserAction::Postpone(coordinates) => {
-                path_with_coordinates(POSTPONE_ACTION, coordinates)
+            UserAction::NoCandidate => NO_CANDIDATE_ACTION,
+            UserAction::AddAt(name, coordinates) => {
+                let query = serde_urlencoded::to_string([
+                    ("name", name.clone()),
+                    ("lat", coordinates.latitude.to_string()),
+                    ("long", coordinates.longitude.to_string()),
+                ])
+                .map_err(serde::ser::Error::custom)?;
+                return serializer.serialize_str(&format!("{ADD_AT_ACTION}?{query}"));
             }
-            UserAction::DeleteCurrent(coordinates) => {
-                path_with_coordinates(DELETE_ACTION, coordinates)
+            UserAction::DrawFrom(meal, name) => {
+                return serializer.serialize_str(&draw_from_url(meal, &saved_location_id(name)));
             }
-            UserAction::ArchiveCurrent(coordinates) => {
-                path_with_coordinates(ARCHIVE_ACTION, coordinates)
+            UserAction::DrawFromSaved(meal, id) => {
+                return serializer.serialize_str(&draw_from_url(meal, id));
             }
-            UserAction::Add => ADD_ACTION.to_string(),
-            UserAction::Refresh => REFRESH_ACTION.to_string(),
-            UserAction::ClearLocation => CLEAR_LOCATION_ACTION.to_string(),
         };
-        serializer.serialize_str(relative_url.as_str())
+        serializer.serialize_str(relative_url)
     }
 }
 
-impl Coordinates {
-    fn to_path(&self, path: &str) -> String {
-        format!("{}?lat={}&long={}", path, self.latitude, self.longitude)
-    }
-}
-
-fn path_with_coordinates(path: &str, coordinates: &Option<Coordinates>) -> String {
-    coordinates
-        .as_ref()
-        .map_or(path.to_string(), |c| c.to_path(path))
+fn draw_from_url(meal: &Meal, id: &str) -> String {
+    let action = match meal {
+        Meal::Lunch => DRAW_LUNCH_FROM_ACTION,
+        Meal::Dinner => DRAW_DINNER_FROM_ACTION,
+    };
+    format!("{action}?id={id}")
 }
 
 struct UserActionVisitor;
@@ -129,6 +215,35 @@ impl<'de> Visitor<'de> for UserActionVisitor {
             ADD_ACTION => Ok(UserAction::Add),
             REFRESH_ACTION => Ok(UserAction::Refresh),
             CLEAR_LOCATION_ACTION => Ok(UserAction::ClearLocation),
+            SEED_ACTION => Ok(UserAction::Seed),
+            CHOOSE_CANDIDATE_ACTION => url
+                .query_pairs()
+                .find(|(k, _)| k == "index")
+                .and_then(|(_, v)| v.parse::<usize>().ok())
+                .map(UserAction::ChooseCandidate)
+                .ok_or_else(|| E::custom(format!("Missing candidate index in {v}"))),
+            NO_CANDIDATE_ACTION => Ok(UserAction::NoCandidate),
+            ADD_AT_ACTION => url
+                .query_pairs()
+                .find(|(k, _)| k == "name")
+                .map(|(_, name)| name.to_string())
+                .zip(coordinates)
+                .map(|(name, coordinates)| UserAction::AddAt(name, coordinates))
+                .ok_or_else(|| E::custom(format!("Missing place name or coordinates in {v}"))),
+            path @ (DRAW_LUNCH_FROM_ACTION | DRAW_DINNER_FROM_ACTION) => {
+                let meal = match path {
+                    DRAW_LUNCH_FROM_ACTION => Meal::Lunch,
+                    _ => Meal::Dinner,
+                };
+                // Buttons sent before the ids carry the whole name
+                url.query_pairs()
+                    .find_map(|(k, v)| match k.as_ref() {
+                        "id" => Some(UserAction::DrawFromSaved(meal.clone(), v.to_string())),
+                        "name" => Some(UserAction::DrawFrom(meal.clone(), v.to_string())),
+                        _ => None,
+                    })
+                    .ok_or_else(|| E::custom(format!("Missing location id in {v}")))
+            }
             v => Err(E::custom(format!("Unknown action value {v}"))),
         }
     }
@@ -1,9 +1,8 @@
-use std::collections::HashMap;
-
+use server::app::geocoding_queue::GeocodingQueue;
 use server::app::jar::Jar;
-use server::bing::http::{get_bing_context, BingClient, BingError};
 use server::gcp::api::FirebaseApi;
 pub(crate) use server::gcp::http_api::FirebaseApiV2;
+use server::geocoding::api::Provider;
 
 #[tokio::main]
 async fn main() {
@@ -18,26 +17,37 @@ async fn main() {
     let db_group = group.unwrap();
     println!("{db_group:?}");
     let jar = &Jar::new(&db_group.to_string());
-    let places = firebase_api.get_all_places(jar).await.unwrap();
-    let client = BingClient::default();
+    let places = firebase_api.get_place_details(jar).await.unwrap();
+    let missing: Vec<_> = places
+        .iter()
+        .filter(|place| place.coordinates.is_none())
+        .collect();
+    println!(
+        "{} of {} places lack coordinates",
+        missing.len(),
+        places.len()
+    );
 
-    let mut results: HashMap<&String, BingError> = HashMap::new();
-    for place in places.iter() {
-        let result = client
-            .find_geo_coordinates_from_query(&place.name, &get_bing_context())
-            .await;
-        match result {
-            Ok(coordinates) => {
-                let _ = firebase_api
-                    .set_place_coordinates(jar, place, &coordinates)
-                    .await;
+    // Same queue, retries and cache as the server, the chat is just not told
+    let queue = GeocodingQueue::new(FirebaseApiV2::default().await);
+    for place in &missing {
+        if let Err(e) = queue.enqueue(jar, &place.place(), "", false).await {
+            println!("Could not queue {}: {e:?}", place.name);
+        }
+    }
+    let geocoder = Provider::from_env();
+    // Only the backfill of the jar: the server notifies the chats of their own jobs
+    loop {
+        match queue.run_due_backfills(jar, &firebase_api, &geocoder).await {
+            Ok(0) => break,
+            Ok(left) => {
+                println!("{left} jobs left, waiting for their retries");
+                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
             }
             Err(e) => {
-                results.insert(&place.name, e);
+                println!("{e:?}");
+                break;
             }
         }
     }
-    println!("{:?}", places.len());
-    println!("{:?}", results.len());
-    println!("{results:?}");
 }
@@ -7,7 +7,7 @@ use std::error::Error;
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let line_token = std::env::var("LINE_TOKEN").unwrap();
-    let line_client = LineClient::new(&line_token);
+    let line_client = LineClient::from_env(&line_token);
     let firebase_api = FirebaseApiV2::default().await;
     let jars = firebase_api.get_all_groups().await?;
     for jar in jars.iter() {
@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use clap::{Arg, ArgMatches};
 use serde::Deserialize;
 use serde::Serialize;
@@ -5,16 +7,21 @@ use serde::Serialize;
 use server::line::api::LineApi;
 use server::line::http::LineClient;
 use server::line::json::RichMenu;
+use server::line::menu;
 
-const COMMANDS: [CommandAction; 6] = [
+const COMMANDS: [CommandAction; 8] = [
     CommandAction::List,
     CommandAction::Delete,
     CommandAction::Create,
     CommandAction::Default,
     CommandAction::SetDefault,
     CommandAction::SetAlias,
+    CommandAction::Plan,
+    CommandAction::Apply,
 ];
 
+const DEFAULT_MANIFEST: &str = "./src/menus/menus.json";
+
 #[tokio::main]
 async fn main() {
     env_logger::init();
@@ -94,6 +101,20 @@ async fn main() {
                         .unwrap();
                     println!("Menu alias {alias} set for menu {menu_id}")
                 }
+
+                CommandAction::Plan => {
+                    let (_, steps) = plan(m).await;
+                    print_plan(&steps);
+                }
+
+                CommandAction::Apply => {
+                    let (local, steps) = plan(m).await;
+                    print_plan(&steps);
+                    menu::apply(&get_http_client(m), &local, &steps)
+                        .await
+                        .unwrap();
+                    println!("Menus applied")
+                }
             }
         }
     }
@@ -105,7 +126,21 @@ fn get_http_client(m: &ArgMatches) -> LineClient {
         .map(|a| a.to_string())
         .or_else(|| std::env::var("LINE_TOKEN").ok())
         .expect("Please specify a line token");
-    LineClient::new(&token)
+    LineClient::from_env(&token)
+}
+
+async fn plan(m: &ArgMatches) -> (Vec<menu::LocalMenu>, Vec<menu::PlanStep>) {
+    let manifest = m.get_one::<String>("manifest").unwrap();
+    let local = menu::load_manifest(Path::new(manifest)).unwrap_or_else(|e| panic!("{e}"));
+    let remote = menu::get_remote_menus(&get_http_client(m)).await.unwrap();
+    let steps = menu::plan(&local, &remote);
+    (local, steps)
+}
+
+fn print_plan(steps: &[menu::PlanStep]) {
+    for step in steps {
+        println!("{step}")
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -122,6 +157,10 @@ enum CommandAction {
     SetDefault,
     #[serde(rename(serialize = "set-alias", deserialize = "set-alias"))]
     SetAlias,
+    #[serde(rename(serialize = "plan", deserialize = "plan"))]
+    Plan,
+    #[serde(rename(serialize = "apply", deserialize = "apply"))]
+    Apply,
 }
 
 impl CommandAction {
@@ -133,6 +172,8 @@ impl CommandAction {
             CommandAction::Default => "Get channel default menu",
             CommandAction::SetDefault => "Set channel default menu",
             CommandAction::SetAlias => "Set channel menu alias",
+            CommandAction::Plan => "Show the changes needed to match the menus manifest",
+            CommandAction::Apply => "Create, relink and delete menus to match the menus manifest",
         }
     }
 }
@@ -182,6 +223,13 @@ impl From<CommandAction> for clap::Command {
                         .help("Alias to set on the menu"),
                 )
                 .arg(Arg::new("menu").short('m').required(true).help("Menu id")),
+
+            CommandAction::Plan | CommandAction::Apply => app.arg(
+                Arg::new("manifest")
+                    .short('f')
+                    .default_value(DEFAULT_MANIFEST)
+                    .help("Path to the menus manifest json file"),
+            ),
         }
     }
 }
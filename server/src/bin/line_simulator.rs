@@ -0,0 +1,23 @@
+extern crate server;
+
+use server::line::simulator::{self, Simulator};
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let port = std::env::var("SIMULATOR_PORT").map_or(4020, |v| v.parse::<u16>().unwrap());
+    let server_url =
+        std::env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:4001".to_string());
+    let channel_secret = std::env::var("LINE_CHANNEL_SECRET")
+        .expect("Needs the LINE_CHANNEL_SECRET of the server to sign the webhook events");
+    println!(
+        "Simulating Line for {server_url} on http://localhost:{port}; \
+        start the server with LINE_API_URL=http://localhost:{port}"
+    );
+    warp::serve(simulator::route(Simulator::new(
+        &server_url,
+        &channel_secret,
+    )))
+    .run(([127, 0, 0, 1], port))
+    .await;
+}
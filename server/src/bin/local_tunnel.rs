@@ -18,7 +18,7 @@ fn main() {
 }
 
 fn open_local_url(port: i32, line_token: String) {
-    let child = Command::new("ngrok")
+    let mut child = Command::new("ngrok")
         .arg("http")
         .arg(port.to_string())
         .arg("--log")
@@ -27,12 +27,13 @@ fn open_local_url(port: i32, line_token: String) {
         .spawn()
         .unwrap_or_else(|_| panic!("failed to execute lt opening port process on port {port}"));
 
-    let out = BufReader::new(child.stdout.unwrap());
+    let out = BufReader::new(child.stdout.take().unwrap());
 
     out.lines().for_each(|line| {
         let string = line.unwrap();
         parse_output(string, line_token.as_str());
     });
+    let _ = child.wait();
 }
 
 fn parse_output(output: String, line_token: &str) {
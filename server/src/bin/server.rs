@@ -1,12 +1,25 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::{Receiver, Sender};
-use warp::Filter;
+use tokio::sync::mpsc::Receiver;
+use warp::{Filter, Rejection, Reply};
 
 use server::app::core::Action;
+use server::app::geocoding_queue::{GeocodingQueue, GeocodingStore};
+use server::app::link::LinkSigner;
+use server::app::{membership, session};
 use server::gcp::api::FirebaseApi;
 use server::gcp::http_api::FirebaseApiV2;
+use server::geocoding::api::{Geocoder, Provider};
+use server::line::dedup::{Deduplicator, EventStore};
 use server::line::http::LineClient;
-use server::{app, line};
+use server::messengers::Messengers;
+use server::slack::http::SlackClient;
+use server::slack::signature::SignatureVerifier;
+use server::telegram::http::TelegramClient;
+use server::web::hub::WebHub;
+use server::{app, line, rest, slack, telegram, web};
 
 #[tokio::main]
 async fn main() {
@@ -16,41 +29,134 @@ async fn main() {
         .map(|port| port.parse::<u16>().unwrap())
         .unwrap_or(4001);
     let line_token = std::env::var("LINE_TOKEN").expect("Please specify a LINE_TOKEN env variable");
-    let line_client = LineClient::new(&line_token);
+    let line_channel_secret = std::env::var("LINE_CHANNEL_SECRET")
+        .expect("Needs to have a LINE_CHANNEL_SECRET env variables to verify incoming webhook");
+    // Fails at start rather than on the first link sent to a chat
+    let link_signer = Arc::new(LinkSigner::from_env());
+    let line_client = LineClient::from_env(&line_token).with_link_signer(link_signer.clone());
+    // Slack is optional; its routes answer 404 without a signing secret
+    let slack_client = std::env::var("SLACK_BOT_TOKEN")
+        .ok()
+        .map(|token| SlackClient::new(&token));
+    let slack_verifier = std::env::var("SLACK_SIGNING_SECRET")
+        .ok()
+        .map(|secret| Arc::new(SignatureVerifier::new(&secret)));
+    // Telegram is optional too; its webhook answers 404 without a secret token
+    let telegram_client = std::env::var("TELEGRAM_BOT_TOKEN")
+        .ok()
+        .map(|token| TelegramClient::new(&token));
+    let telegram_secret = std::env::var("TELEGRAM_SECRET_TOKEN").ok().map(Arc::new);
+    let messengers = Messengers {
+        line: line_client,
+        slack: slack_client,
+        telegram: telegram_client,
+        web: WebHub::default(),
+    };
+
+    let geocoder = Provider::from_env();
 
     let (tx, rx) = mpsc::channel(32);
 
     let fc = FirebaseApiV2::default().await;
+    let deduplicator = Arc::new(Deduplicator::new(FirebaseApiV2::default().await));
+    let geocoding_queue = GeocodingQueue::new(FirebaseApiV2::default().await);
+    let form_firebase_client = Arc::new(FirebaseApiV2::default().await);
+    let routes = line::webhook::route(tx.clone(), deduplicator.clone(), &line_channel_secret)
+        .or(line::html::route(
+            tx.clone(),
+            form_firebase_client.clone(),
+            link_signer.clone(),
+        ))
+        .or(line::manage::route(
+            form_firebase_client.clone(),
+            link_signer.clone(),
+        ))
+        .or(rest::route(tx.clone(), form_firebase_client))
+        .or(slack::webhook::route(tx.clone(), slack_verifier))
+        .or(telegram::webhook::route(tx.clone(), telegram_secret))
+        .or(web::route::route(
+            tx,
+            messengers.web.clone(),
+            link_signer.clone(),
+        ));
     let _ = tokio::try_join!(
-        launch_server(port, &line_client, tx),
-        launch_core_agent(rx, &line_client, &fc)
+        launch_server(port, routes),
+        launch_core_agent(rx, &messengers, &fc, &geocoding_queue, &link_signer),
+        launch_geocoding_worker(&geocoding_queue, &messengers, &fc, &geocoder),
+        launch_archiver(&fc),
+        launch_event_pruner(&deduplicator)
     );
 }
 
 async fn launch_server(
     port: u16,
-    line_client: &line::http::LineClient,
-    tx: Sender<(String, Action)>,
+    routes: impl Filter<Extract = impl Reply, Error = Rejection> + Clone + Send + Sync + 'static,
 ) -> Result<(), &'static str> {
-    warp::serve(
-        line::webhook::route(line_client.clone(), tx.clone())
-            .or(line::html::route(tx.clone()))
-            .with(warp::log("")),
-    )
-    .run(([0, 0, 0, 0], port))
-    .await;
+    warp::serve(routes.with(warp::log("")))
+        .run(([0, 0, 0, 0], port))
+        .await;
     Result::Ok(())
 }
 
-async fn launch_core_agent<T: FirebaseApi + Sync>(
+async fn launch_core_agent<T: FirebaseApi + Sync, S: GeocodingStore + Sync>(
     mut rx: Receiver<(String, Action)>,
-    line_client: &line::http::LineClient,
+    messengers: &Messengers,
     firebase_client: &T,
+    geocoding_queue: &GeocodingQueue<S>,
+    link_signer: &LinkSigner,
 ) -> Result<(), &'static str> {
     println!("Receiving");
     while let Some(action) = rx.recv().await {
         println!("Got action {action:?}");
-        app::core::handle_action(action, line_client, firebase_client).await;
+        app::core::handle_action(
+            action,
+            messengers,
+            firebase_client,
+            geocoding_queue,
+            link_signer,
+        )
+        .await;
     }
     Result::Ok(())
 }
+
+async fn launch_geocoding_worker<S, T, G>(
+    geocoding_queue: &GeocodingQueue<S>,
+    messengers: &Messengers,
+    firebase_client: &T,
+    geocoder: &G,
+) -> Result<(), &'static str>
+where
+    S: GeocodingStore + Sync,
+    T: FirebaseApi + Sync,
+    G: Geocoder + Sync,
+{
+    geocoding_queue
+        .run(firebase_client, messengers, geocoder)
+        .await;
+    Result::Ok(())
+}
+
+async fn launch_archiver<T: FirebaseApi + Sync>(firebase_client: &T) -> Result<(), &'static str> {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match membership::archive_inactive_jars(firebase_client, session::now()).await {
+            Ok(jars) if !jars.is_empty() => println!("Archived {jars:?}"),
+            Ok(_) => {}
+            Err(e) => println!("Could not archive inactive jars: {e:?}"),
+        }
+    }
+}
+
+async fn launch_event_pruner<S: EventStore>(
+    deduplicator: &Deduplicator<S>,
+) -> Result<(), &'static str> {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        if let Err(e) = deduplicator.prune().await {
+            println!("Could not prune webhook events: {e:?}");
+        }
+    }
+}
@@ -1,31 +1,29 @@
+use crate::app::address::Address;
 use crate::app::coordinates::Coordinates;
-use futures::TryFutureExt;
+use crate::geocoding::api::{
+    context_from_json, merge_parameters, Candidate, Geocoder, GeocodingError,
+};
+use crate::geocoding::context::GeocodingContext;
+use async_trait::async_trait;
+use futures::future::join_all;
 use reqwest::Url;
 use serde::Deserialize;
-use serde_json::Value;
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Debug, Display};
 
 const BING_API_KEY: &str = env!("BING_MAP_API_KEY");
 const BING_CONTEXT: Option<&str> = option_env!("BING_MAP_API_CONTEXT");
+const BASE_BING_URL: &str = "https://dev.virtualearth.net/REST/v1";
+const NO_ADDRESS: &str = "Could not find address";
+// Meters around the user location the suggestions are biased to
+const USER_LOCATION_RADIUS: u32 = 5000;
 
 pub fn get_bing_context() -> Vec<(String, String)> {
     BING_CONTEXT
         .map(|ctx| ctx.to_string())
         .or(std::env::var("BING_MAP_API_CONTEXT").ok())
-        .map_or_else(Vec::new, |ctx| {
-            serde_json::from_str::<Value>(&ctx).map_or(vec![], |bing_json| match bing_json {
-                Value::Object(o) => {
-                    let mut collect: Vec<(String, String)> = vec![];
-                    for (k, v) in o {
-                        collect.push((k, v.as_str().unwrap().to_string()))
-                    }
-                    collect
-                }
-                _ => vec![],
-            })
-        })
+        .map_or_else(Vec::new, |ctx| context_from_json(&ctx))
 }
 
 #[derive(Debug)]
@@ -38,12 +36,18 @@ pub struct BingErrorNoResourceSets {
 
 impl Display for BingError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Bing Error")
+        write!(f, "Bing Error: {}", self.0)
     }
 }
 
 impl Error for BingError {}
 
+impl From<BingError> for GeocodingError {
+    fn from(value: BingError) -> Self {
+        GeocodingError(value.0)
+    }
+}
+
 impl From<reqwest::Error> for BingError {
     fn from(value: reqwest::Error) -> Self {
         BingError(format!("{value:?}"))
@@ -85,47 +89,136 @@ pub struct AutoSuggestResourceValue {
 }
 
 #[derive(Deserialize, Debug, Clone)]
-#[allow(dead_code)]
 pub struct AutoSuggestResourceValueAddress {
     #[serde(rename(deserialize = "adminDistrict"))]
     admin_district: Option<String>,
     #[serde(rename(deserialize = "adminDistrict2"))]
     admin_district2: Option<String>,
+    locality: Option<String>,
+    #[serde(rename(deserialize = "postalCode"))]
+    postal_code: Option<String>,
+    #[serde(rename(deserialize = "countryRegion"))]
+    country_region: Option<String>,
     #[serde(rename(deserialize = "addressLine"))]
     pub address_line: Option<String>,
+    #[serde(rename(deserialize = "formattedAddress"))]
+    pub formatted_address: Option<String>,
 }
 
-pub struct BingClient(reqwest::Client);
+impl AutoSuggestResourceValueAddress {
+    fn details(&self) -> Address {
+        Address {
+            line: self.address_line.clone(),
+            // Tokyo wards are the second division, other cities may only have a locality
+            district: self.admin_district2.clone().or(self.locality.clone()),
+            region: self.admin_district.clone(),
+            postal_code: self.postal_code.clone(),
+            country: self.country_region.clone(),
+        }
+    }
+}
+
+pub struct BingClient {
+    client: reqwest::Client,
+    base_url: String,
+}
 
 impl Default for BingClient {
     fn default() -> Self {
-        BingClient(reqwest::Client::new())
+        BingClient::with_base_url(BASE_BING_URL)
     }
 }
 
 impl BingClient {
-    pub async fn find_geo_coordinates_from_query(
+    /// Client of a REST API served elsewhere, such as a local fake
+    pub fn with_base_url(base_url: &str) -> Self {
+        BingClient {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    // The auto suggestion takes a radius along the user location, the locations do not
+    fn user_location(context: &GeocodingContext, with_radius: bool) -> Vec<(String, String)> {
+        let mut parameters = vec![];
+        if let Some(near) = &context.near {
+            let location = match with_radius {
+                true => format!(
+                    "{},{},{USER_LOCATION_RADIUS}",
+                    near.latitude, near.longitude
+                ),
+                false => format!("{},{}", near.latitude, near.longitude),
+            };
+            parameters.push(("userLocation".to_string(), location));
+        }
+        if let Some(bounds) = &context.bounds {
+            let view = format!(
+                "{},{},{},{}",
+                bounds.south_west.latitude,
+                bounds.south_west.longitude,
+                bounds.north_east.latitude,
+                bounds.north_east.longitude
+            );
+            parameters.push(("userMapView".to_string(), view));
+        }
+        parameters
+    }
+
+    /// Suggested addresses of the query, each located by its address line
+    pub async fn find_candidates_from_query(
         &self,
         query: &str,
+        context: &GeocodingContext,
         location_refinements: &[(String, String)],
-    ) -> Result<Coordinates, BingError> {
-        self.find_address(query, location_refinements)
-            .and_then(|address| async move { self.find_geo_coordinates(&address).await })
+        limit: usize,
+    ) -> Result<Vec<Candidate>, BingError> {
+        let addresses = match self
+            .find_addresses(query, context, location_refinements, limit)
             .await
+        {
+            Ok(addresses) => addresses,
+            // Unknown places are not errors, these are retried
+            Err(BingError(message)) if message == NO_ADDRESS => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let located = join_all(addresses.iter().map(|address| async move {
+            let line = address.address_line.as_deref().unwrap_or_default();
+            self.find_geo_coordinates(line, context)
+                .await
+                .map(|coordinates| Candidate {
+                    address: address
+                        .formatted_address
+                        .clone()
+                        .unwrap_or(line.to_string()),
+                    details: address.details(),
+                    coordinates,
+                })
+        }))
+        .await;
+        Ok(located.into_iter().filter_map(Result::ok).collect())
     }
 
-    pub async fn find_address(
+    pub async fn find_addresses(
         &self,
         query: &str,
+        context: &GeocodingContext,
         location_refinements: &[(String, String)],
-    ) -> Result<String, BingError> {
-        self.find_locations(query, location_refinements)
+        limit: usize,
+    ) -> Result<Vec<AutoSuggestResourceValueAddress>, BingError> {
+        let addresses: Vec<AutoSuggestResourceValueAddress> = self
+            .find_locations(query, context, location_refinements)
             .await?
-            .first()
-            .and_then(|bing_resource| bing_resource.value.as_ref())
-            .and_then(|resource_values| resource_values.first())
-            .and_then(|resource_value| resource_value.address.address_line.clone())
-            .ok_or_else(|| BingError("Could not find address".to_string()))
+            .into_iter()
+            .flat_map(|bing_resource| bing_resource.value.unwrap_or_default())
+            .map(|resource_value| resource_value.address)
+            .filter(|address| address.address_line.is_some())
+            .take(limit)
+            .collect();
+        if addresses.is_empty() {
+            Err(BingError(NO_ADDRESS.to_string()))
+        } else {
+            Ok(addresses)
+        }
     }
 
     // See refinement options here
@@ -133,16 +226,24 @@ impl BingClient {
     async fn find_locations(
         &self,
         query: &str,
+        context: &GeocodingContext,
         location_refinements: &[(String, String)],
     ) -> Result<Vec<BingResource>, BingError> {
-        let mut url = Url::parse("https://dev.virtualearth.net/REST/v1/AutoSuggest/?c=ja").unwrap();
+        let mut url = Url::parse(&format!("{}/AutoSuggest/", self.base_url)).unwrap();
         url.query_pairs_mut().append_pair("query", query);
-        location_refinements.iter().for_each(|(k, v)| {
-            url.query_pairs_mut().append_pair(k, v);
-        });
+        let mut parameters = vec![
+            ("c".to_string(), context.culture.clone()),
+            ("countryFilter".to_string(), context.country.clone()),
+        ];
+        parameters.extend(Self::user_location(context, true));
+        merge_parameters(parameters, location_refinements)
+            .iter()
+            .for_each(|(k, v)| {
+                url.query_pairs_mut().append_pair(k, v);
+            });
         let url_base = url.clone();
         url.query_pairs_mut().append_pair("key", BING_API_KEY);
-        let response = self.0.get(url).send().await?.error_for_status()?;
+        let response = self.client.get(url).send().await?.error_for_status()?;
         let resource_sets: BingResourceSets = response.json().await?;
         resource_sets
             .resource_sets
@@ -157,14 +258,21 @@ impl BingClient {
             )
     }
 
-    pub async fn find_geo_coordinates(&self, address: &str) -> Result<Coordinates, BingError> {
-        let mut url = Url::parse(
-            "http://dev.virtualearth.net/REST/v1/Locations?countryRegion=JP&c=ja&maxResults=1",
-        )
-        .unwrap();
+    pub async fn find_geo_coordinates(
+        &self,
+        address: &str,
+        context: &GeocodingContext,
+    ) -> Result<Coordinates, BingError> {
+        let mut url = Url::parse(&format!("{}/Locations?maxResults=1", self.base_url)).unwrap();
+        url.query_pairs_mut()
+            .append_pair("countryRegion", &context.country)
+            .append_pair("c", &context.culture);
+        for (k, v) in Self::user_location(context, false) {
+            url.query_pairs_mut().append_pair(&k, &v);
+        }
         url.query_pairs_mut().append_pair("key", BING_API_KEY);
         url.query_pairs_mut().append_pair("addressLine", address);
-        let resource_sets: BingResourceSets = self.0.get(url).send().await?.json().await?;
+        let resource_sets: BingResourceSets = self.client.get(url).send().await?.json().await?;
         let bing_coordinates = resource_sets
             .resource_sets
             .first()
@@ -179,3 +287,70 @@ impl BingClient {
         })
     }
 }
+
+#[async_trait]
+impl Geocoder for BingClient {
+    async fn candidates(
+        &self,
+        query: &str,
+        context: &GeocodingContext,
+        limit: usize,
+    ) -> Result<Vec<Candidate>, GeocodingError> {
+        self.find_candidates_from_query(query, context, &get_bing_context(), limit)
+            .await
+            .map_err(GeocodingError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use warp::Filter;
+
+    use crate::bing::http::BingClient;
+    use crate::geocoding::api::Geocoder;
+    use crate::geocoding::context::GeocodingContext;
+
+    #[tokio::test]
+    async fn it_finds_coordinates_through_the_address() {
+        let autosuggest = warp::path!("AutoSuggest")
+            .map(|| include_str!("../../resources/geocoding/bing_autosuggest.json"));
+        let locations = warp::path!("Locations")
+            .and(warp::query::<HashMap<String, String>>())
+            .map(|query: HashMap<String, String>| {
+                // The addresses found by the auto suggestion are located, in the jar country
+                match (
+                    query["addressLine"].as_str(),
+                    query["countryRegion"].as_str(),
+                ) {
+                    ("宇田川町13-7", "JP") => {
+                        include_str!("../../resources/geocoding/bing_locations.json")
+                    }
+                    _ => r#"{"resourceSets": [{"resources": []}]}"#,
+                }
+            });
+        let api = autosuggest
+            .or(locations)
+            .unify()
+            .map(|body| warp::reply::with_header(body, "content-type", "application/json"));
+        let (address, server) = warp::serve(api).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let bing = BingClient::with_base_url(&format!("http://{address}"));
+        let context = GeocodingContext::default();
+        let coordinates = bing.geocode("一蘭 渋谷", &context).await.unwrap();
+        assert_eq!(coordinates.latitude, 35.66124);
+        assert_eq!(coordinates.longitude, 139.69869);
+
+        // Addresses that cannot be located are left out
+        let candidates = bing.candidates("一蘭 渋谷", &context, 3).await.unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(
+            candidates[0].address,
+            "宇田川町13-7, 渋谷区, 東京都 150-0042"
+        );
+        assert_eq!(candidates[0].details.district.as_deref(), Some("渋谷区"));
+        assert_eq!(candidates[0].details.region.as_deref(), Some("東京都"));
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 
 use async_trait::async_trait;
@@ -5,37 +6,73 @@ use rand::seq::IteratorRandom;
 
 use serde_json::Value;
 
+use crate::app::address::Address;
+use crate::app::api_token::ApiToken;
 use crate::app::coordinates::Coordinates;
 use crate::app::core::{Meal, Place};
+use crate::app::history::HistoryEntry;
 use crate::app::jar::Jar;
+use crate::app::manage::PlaceDetails;
+use crate::app::membership::{JarStatus, Member};
+use crate::app::session::Session;
 use crate::gcp::constants::{
-    BASE_URL, FIREBASE_API_V2_CURRENT_DRAW_KEY, FIREBASE_API_V2_PLACES_KEY,
+    ARCHIVAL_INDEX_PATH, ARCHIVE_PATH, BASE_URL, FIREBASE_API_V2_API_TOKENS_KEY,
+    FIREBASE_API_V2_CURRENT_DRAW_KEY, FIREBASE_API_V2_GEOCODING_KEY, FIREBASE_API_V2_HISTORY_KEY,
+    FIREBASE_API_V2_MEMBERS_KEY, FIREBASE_API_V2_PLACES_KEY, FIREBASE_API_V2_PLACE_ADDRESS_TABLE,
     FIREBASE_API_V2_PLACE_COORDINATES_TABLE, FIREBASE_API_V2_PLACE_NAME_TABLE,
-    FIREBASE_API_V2_SLOTS_KEY, LABEL_PATH,
+    FIREBASE_API_V2_SAVED_LOCATIONS_KEY, FIREBASE_API_V2_SESSION_KEY, FIREBASE_API_V2_SLOTS_KEY,
+    FIREBASE_API_V2_STATUS_KEY, LABEL_PATH,
 };
 use crate::gcp::http_api::FirebaseApiV2;
+use crate::geocoding::context::GeocodingContext;
 use crate::http::HttpResult;
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 pub struct ApiV2Place {
     pub(crate) name: String,
     timeslot: Vec<Meal>,
+    // Line user id of whoever added the place, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    added_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    // Map or guide page the place was added from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link: Option<String>,
 }
 
 #[async_trait]
 pub trait FirebaseApi {
     async fn add_label(&self, jar: &Jar, label: &str) -> HttpResult<String>;
 
+    async fn get_label(&self, jar: &Jar) -> HttpResult<Option<String>>;
+
     async fn get_current_draw(&self, jar: &Jar) -> HttpResult<Option<Place>>;
 
+    /// Draw a place of the meal close to the coordinates or within the area, when given
     async fn draw(
         &self,
         jar: &Jar,
         meal: &Meal,
         coordinates: &Option<Coordinates>,
+        area: Option<&str>,
     ) -> HttpResult<Option<Place>>;
 
-    async fn add_place(&self, jar: &Jar, place_name: &str, meal: &[Meal]) -> HttpResult<Place>;
+    async fn add_place(
+        &self,
+        jar: &Jar,
+        place_name: &str,
+        meal: &[Meal],
+        added_by: Option<&str>,
+    ) -> HttpResult<Place>;
+
+    async fn get_all_places(&self, jar: &Jar) -> HttpResult<Vec<Place>>;
+
+    /// Places with their meals, tags and coordinates; the last visit is left to the caller
+    async fn get_place_details(&self, jar: &Jar) -> HttpResult<Vec<PlaceDetails>>;
+
+    /// Rename the place and replace its meals and tags
+    async fn update_place(&self, jar: &Jar, place: &PlaceDetails) -> HttpResult<()>;
 
     async fn set_place_coordinates(
         &self,
@@ -44,15 +81,87 @@ pub trait FirebaseApi {
         coordinates: &Coordinates,
     ) -> HttpResult<()>;
 
+    async fn set_place_address(
+        &self,
+        jar: &Jar,
+        place: &Place,
+        address: &Address,
+    ) -> HttpResult<()>;
+
+    async fn get_place_address(&self, jar: &Jar, place: &Place) -> HttpResult<Option<Address>>;
+
+    async fn set_place_link(&self, jar: &Jar, place: &Place, link: &str) -> HttpResult<()>;
+
+    async fn get_place_link(&self, jar: &Jar, place: &Place) -> HttpResult<Option<String>>;
+
     async fn remove_drawn_place(&self, jar: &Jar, place: Option<&Place>) -> HttpResult<()>;
 
     async fn delete_place(&self, jar: &Jar, place: &Place) -> HttpResult<Place>;
 
+    async fn append_history(&self, jar: &Jar, entry: &HistoryEntry) -> HttpResult<()>;
+
+    /// Draw history, oldest first
+    async fn get_history(&self, jar: &Jar) -> HttpResult<Vec<HistoryEntry>>;
+
+    /// Store the hash of a newly issued API token
+    async fn add_api_token(&self, jar: &Jar, token_hash: &str, token: &ApiToken) -> HttpResult<()>;
+
+    async fn has_api_token(&self, jar: &Jar, token_hash: &str) -> HttpResult<bool>;
+
+    async fn revoke_api_tokens(&self, jar: &Jar) -> HttpResult<()>;
+
+    async fn get_session(&self, jar: &Jar) -> HttpResult<Option<Session>>;
+
+    async fn set_session(&self, jar: &Jar, session: &Session) -> HttpResult<()>;
+
+    /// Where the places of the jar are looked up, when set from the chat
+    async fn get_geocoding_context(&self, jar: &Jar) -> HttpResult<Option<GeocodingContext>>;
+
+    async fn set_geocoding_context(&self, jar: &Jar, context: &GeocodingContext) -> HttpResult<()>;
+
+    /// Named origins of the nearby draws, such as オフィス
+    async fn get_saved_locations(&self, jar: &Jar) -> HttpResult<BTreeMap<String, Coordinates>>;
+
+    async fn save_location(
+        &self,
+        jar: &Jar,
+        name: &str,
+        coordinates: &Coordinates,
+    ) -> HttpResult<()>;
+
+    async fn remove_saved_location(&self, jar: &Jar, name: &str) -> HttpResult<()>;
+
+    async fn get_jar_status(&self, jar: &Jar) -> HttpResult<Option<JarStatus>>;
+
+    async fn set_jar_status(&self, jar: &Jar, status: &JarStatus) -> HttpResult<()>;
+
+    async fn get_members(&self, jar: &Jar) -> HttpResult<HashMap<String, Member>>;
+
+    async fn add_member(&self, jar: &Jar, user_id: &str, member: &Member) -> HttpResult<()>;
+
+    async fn remove_member(&self, jar: &Jar, user_id: &str) -> HttpResult<()>;
+
+    async fn schedule_archival(&self, jar: &Jar, archive_after: u64) -> HttpResult<()>;
+
+    async fn cancel_archival(&self, jar: &Jar) -> HttpResult<()>;
+
+    async fn get_due_archivals(&self, now: u64) -> HttpResult<Vec<Jar>>;
+
+    /// Move the jar data out of the live tree
+    async fn archive_jar(&self, jar: &Jar) -> HttpResult<()>;
+
     fn firebase_url(&self, jar: &Jar, path: &str) -> String {
         format!("{BASE_URL}/{jar}/{path}.json")
     }
 }
 
+// Names typed in the chat go in the url as a single percent-encoded segment
+fn path_segment(name: &str) -> String {
+    let mut url = reqwest::Url::parse("https://segment/").unwrap();
+    url.path_segments_mut().unwrap().push(name);
+    url.path().trim_start_matches('/').to_string()
+}
+
 #[derive(Debug, serde::Deserialize, Clone)]
 struct AppendedKey {
     #[serde(rename(deserialize = "name"))]
@@ -62,12 +171,17 @@ struct AppendedKey {
 #[async_trait]
 impl FirebaseApi for FirebaseApiV2 {
     async fn add_label(&self, jar: &Jar, label: &str) -> HttpResult<String> {
-        let _ = self
+        let _: Value = self
             .make_json_request(|client| client.put(self.firebase_url(jar, LABEL_PATH)).json(label))
             .await?;
         Ok(label.to_string())
     }
 
+    async fn get_label(&self, jar: &Jar) -> HttpResult<Option<String>> {
+        self.make_json_request(|client| client.get(self.firebase_url(jar, LABEL_PATH)))
+            .await
+    }
+
     async fn get_current_draw(&self, jar: &Jar) -> HttpResult<Option<Place>> {
         // Get the current draw key
         let key: Option<String> = self
@@ -92,14 +206,18 @@ impl FirebaseApi for FirebaseApiV2 {
         jar: &Jar,
         meal: &Meal,
         coordinates: &Option<Coordinates>,
+        area: Option<&str>,
     ) -> HttpResult<Option<Place>> {
         let places = self.get_list_of_places_keys(jar, meal).await?;
         let maybe_drawn_place_key = match places {
             None => None,
             Some(meal_places) => {
-                let place_keys: Vec<String> = match coordinates {
-                    None => meal_places.keys().map(|k| k.to_string()).collect(),
-                    Some(origin) => self.find_close_places(jar, meal_places, origin).await?,
+                let place_keys: Vec<String> = match (coordinates, area) {
+                    (_, Some(area)) => self.find_places_in_area(jar, meal_places, area).await?,
+                    (Some(origin), None) => {
+                        self.find_close_places(jar, meal_places, origin).await?
+                    }
+                    (None, None) => meal_places.keys().map(|k| k.to_string()).collect(),
                 };
                 place_keys
                     .iter()
@@ -119,7 +237,13 @@ impl FirebaseApi for FirebaseApiV2 {
         Ok(None)
     }
 
-    async fn add_place(&self, jar: &Jar, place_name: &str, meals: &[Meal]) -> HttpResult<Place> {
+    async fn add_place(
+        &self,
+        jar: &Jar,
+        place_name: &str,
+        meals: &[Meal],
+        added_by: Option<&str>,
+    ) -> HttpResult<Place> {
         let response: AppendedKey = self
             .make_json_request(|client| {
                 client
@@ -127,6 +251,9 @@ impl FirebaseApi for FirebaseApiV2 {
                     .json::<ApiV2Place>(&ApiV2Place {
                         name: place_name.to_string(),
                         timeslot: meals.to_vec(),
+                        added_by: added_by.map(str::to_string),
+                        tags: vec![],
+                        link: None,
                     })
             })
             .await?;
@@ -175,13 +302,111 @@ impl FirebaseApi for FirebaseApiV2 {
         })
     }
 
+    async fn get_all_places(&self, jar: &Jar) -> HttpResult<Vec<Place>> {
+        let places: Option<HashMap<String, ApiV2Place>> = self
+            .make_json_request(|client| {
+                client.get(self.firebase_url(jar, FIREBASE_API_V2_PLACES_KEY))
+            })
+            .await?;
+        Ok(places
+            .unwrap_or_default()
+            .iter()
+            .map(|(key, place)| Place {
+                key: key.clone(),
+                name: place.name.clone(),
+            })
+            .collect())
+    }
+
+    async fn get_place_details(&self, jar: &Jar) -> HttpResult<Vec<PlaceDetails>> {
+        let places: Option<HashMap<String, ApiV2Place>> = self
+            .make_json_request(|client| {
+                client.get(self.firebase_url(jar, FIREBASE_API_V2_PLACES_KEY))
+            })
+            .await?;
+        let coordinates: Option<HashMap<String, Coordinates>> = self
+            .make_json_request(|client| {
+                client.get(self.firebase_url(jar, FIREBASE_API_V2_PLACE_COORDINATES_TABLE))
+            })
+            .await?;
+        let mut coordinates = coordinates.unwrap_or_default();
+        let addresses: Option<HashMap<String, Address>> = self
+            .make_json_request(|client| {
+                client.get(self.firebase_url(jar, FIREBASE_API_V2_PLACE_ADDRESS_TABLE))
+            })
+            .await?;
+        let mut addresses = addresses.unwrap_or_default();
+        Ok(places
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, place)| PlaceDetails {
+                coordinates: coordinates.remove(&key),
+                address: addresses.remove(&key),
+                key,
+                name: place.name,
+                meals: place.timeslot,
+                tags: place.tags,
+                added_by: place.added_by,
+                link: place.link,
+                last_visit: None,
+            })
+            .collect())
+    }
+
+    async fn update_place(&self, jar: &Jar, place: &PlaceDetails) -> HttpResult<()> {
+        // Patch so the fields not edited from the management page are kept
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .patch(self.firebase_url(
+                    jar,
+                    format!("{}/{}", FIREBASE_API_V2_PLACES_KEY, place.key).as_str(),
+                ))
+                .json(&serde_json::json!({
+                    "name": place.name,
+                    "timeslot": place.meals,
+                    "tags": place.tags,
+                }))
+        })
+        .await?;
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(self.firebase_url(
+                    jar,
+                    format!("{}/{}", FIREBASE_API_V2_PLACE_NAME_TABLE, place.key).as_str(),
+                ))
+                .json(&place.name)
+        })
+        .await?;
+        for meal in [Meal::Lunch, Meal::Dinner] {
+            let url = self.firebase_url(
+                jar,
+                format!(
+                    "{}/{}/{}",
+                    FIREBASE_API_V2_SLOTS_KEY,
+                    meal.serialized(),
+                    place.key
+                )
+                .as_str(),
+            );
+            if place.meals.contains(&meal) {
+                self.make_json_request::<Value, _>(|client| {
+                    client.put(url).json(&Value::Bool(true))
+                })
+                .await?;
+            } else {
+                self.make_request(|client| client.delete(url)).await?;
+            }
+        }
+        Ok(())
+    }
+
     async fn set_place_coordinates(
         &self,
         jar: &Jar,
         place: &Place,
         coordinates: &Coordinates,
     ) -> HttpResult<()> {
-        self.make_json_request(|client| {
+        self.make_json_request::<Value, _>(|client| {
             client
                 .put(self.firebase_url(
                     jar,
@@ -193,10 +418,61 @@ impl FirebaseApi for FirebaseApiV2 {
         Ok(())
     }
 
+    async fn set_place_address(
+        &self,
+        jar: &Jar,
+        place: &Place,
+        address: &Address,
+    ) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(self.firebase_url(
+                    jar,
+                    format!("{}/{}", FIREBASE_API_V2_PLACE_ADDRESS_TABLE, place.key).as_str(),
+                ))
+                .json(address)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get_place_address(&self, jar: &Jar, place: &Place) -> HttpResult<Option<Address>> {
+        self.make_json_request(|client| {
+            client.get(self.firebase_url(
+                jar,
+                format!("{}/{}", FIREBASE_API_V2_PLACE_ADDRESS_TABLE, place.key).as_str(),
+            ))
+        })
+        .await
+    }
+
+    async fn set_place_link(&self, jar: &Jar, place: &Place, link: &str) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(self.firebase_url(
+                    jar,
+                    format!("{}/{}/link", FIREBASE_API_V2_PLACES_KEY, place.key).as_str(),
+                ))
+                .json(link)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get_place_link(&self, jar: &Jar, place: &Place) -> HttpResult<Option<String>> {
+        self.make_json_request(|client| {
+            client.get(self.firebase_url(
+                jar,
+                format!("{}/{}/link", FIREBASE_API_V2_PLACES_KEY, place.key).as_str(),
+            ))
+        })
+        .await
+    }
+
     async fn remove_drawn_place(&self, jar: &Jar, _place: Option<&Place>) -> HttpResult<()> {
         // TODO use the passed parameter
         if let Some(_drawn_place) = self.get_current_draw(jar).await? {
-            self.make_json_request(|client| {
+            self.make_json_request::<Value, _>(|client| {
                 client.delete(self.firebase_url(jar, FIREBASE_API_V2_CURRENT_DRAW_KEY))
             })
             .await?;
@@ -218,6 +494,7 @@ impl FirebaseApi for FirebaseApiV2 {
             dinner.as_str(),
             FIREBASE_API_V2_PLACE_NAME_TABLE,
             FIREBASE_API_V2_PLACE_COORDINATES_TABLE,
+            FIREBASE_API_V2_PLACE_ADDRESS_TABLE,
         ];
 
         for bucket in buckets {
@@ -231,7 +508,610 @@ impl FirebaseApi for FirebaseApiV2 {
         Ok(place.clone())
     }
 
+    async fn append_history(&self, jar: &Jar, entry: &HistoryEntry) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .post(self.firebase_url(jar, FIREBASE_API_V2_HISTORY_KEY))
+                .json(entry)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get_history(&self, jar: &Jar) -> HttpResult<Vec<HistoryEntry>> {
+        let entries: Option<HashMap<String, HistoryEntry>> = self
+            .make_json_request(|client| {
+                client.get(self.firebase_url(jar, FIREBASE_API_V2_HISTORY_KEY))
+            })
+            .await?;
+        let mut entries: Vec<HistoryEntry> = entries.unwrap_or_default().into_values().collect();
+        entries.sort_by_key(|entry| entry.at);
+        Ok(entries)
+    }
+
+    async fn add_api_token(&self, jar: &Jar, token_hash: &str, token: &ApiToken) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(self.firebase_url(
+                    jar,
+                    format!("{FIREBASE_API_V2_API_TOKENS_KEY}/{token_hash}").as_str(),
+                ))
+                .json(token)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn has_api_token(&self, jar: &Jar, token_hash: &str) -> HttpResult<bool> {
+        let token: Option<ApiToken> = self
+            .make_json_request(|client| {
+                client.get(self.firebase_url(
+                    jar,
+                    format!("{FIREBASE_API_V2_API_TOKENS_KEY}/{token_hash}").as_str(),
+                ))
+            })
+            .await?;
+        Ok(token.is_some())
+    }
+
+    async fn revoke_api_tokens(&self, jar: &Jar) -> HttpResult<()> {
+        self.make_request(|client| {
+            client.delete(self.firebase_url(jar, FIREBASE_API_V2_API_TOKENS_KEY))
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get_session(&self, jar: &Jar) -> HttpResult<Option<Session>> {
+        self.make_json_request(|client| {
+            client.get(self.firebase_url(jar, FIREBASE_API_V2_SESSION_KEY))
+        })
+        .await
+    }
+
+    async fn set_session(&self, jar: &Jar, session: &Session) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(self.firebase_url(jar, FIREBASE_API_V2_SESSION_KEY))
+                .json(session)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get_geocoding_context(&self, jar: &Jar) -> HttpResult<Option<GeocodingContext>> {
+        self.make_json_request(|client| {
+            client.get(self.firebase_url(jar, FIREBASE_API_V2_GEOCODING_KEY))
+        })
+        .await
+    }
+
+    async fn set_geocoding_context(&self, jar: &Jar, context: &GeocodingContext) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(self.firebase_url(jar, FIREBASE_API_V2_GEOCODING_KEY))
+                .json(context)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get_saved_locations(&self, jar: &Jar) -> HttpResult<BTreeMap<String, Coordinates>> {
+        let locations: Option<BTreeMap<String, Coordinates>> = self
+            .make_json_request(|client| {
+                client.get(self.firebase_url(jar, FIREBASE_API_V2_SAVED_LOCATIONS_KEY))
+            })
+            .await?;
+        Ok(locations.unwrap_or_default())
+    }
+
+    async fn save_location(
+        &self,
+        jar: &Jar,
+        name: &str,
+        coordinates: &Coordinates,
+    ) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(
+                    self.firebase_url(
+                        jar,
+                        format!(
+                            "{FIREBASE_API_V2_SAVED_LOCATIONS_KEY}/{}",
+                            path_segment(name)
+                        )
+                        .as_str(),
+                    ),
+                )
+                .json(coordinates)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_saved_location(&self, jar: &Jar, name: &str) -> HttpResult<()> {
+        self.make_request(|client| {
+            client.delete(
+                self.firebase_url(
+                    jar,
+                    format!(
+                        "{FIREBASE_API_V2_SAVED_LOCATIONS_KEY}/{}",
+                        path_segment(name)
+                    )
+                    .as_str(),
+                ),
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get_jar_status(&self, jar: &Jar) -> HttpResult<Option<JarStatus>> {
+        self.make_json_request(|client| {
+            client.get(self.firebase_url(jar, FIREBASE_API_V2_STATUS_KEY))
+        })
+        .await
+    }
+
+    async fn set_jar_status(&self, jar: &Jar, status: &JarStatus) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(self.firebase_url(jar, FIREBASE_API_V2_STATUS_KEY))
+                .json(status)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get_members(&self, jar: &Jar) -> HttpResult<HashMap<String, Member>> {
+        let members: Option<HashMap<String, Member>> = self
+            .make_json_request(|client| {
+                client.get(self.firebase_url(jar, FIREBASE_API_V2_MEMBERS_KEY))
+            })
+            .await?;
+        Ok(members.unwrap_or_default())
+    }
+
+    async fn add_member(&self, jar: &Jar, user_id: &str, member: &Member) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(self.firebase_url(
+                    jar,
+                    format!("{FIREBASE_API_V2_MEMBERS_KEY}/{user_id}").as_str(),
+                ))
+                .json(member)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_member(&self, jar: &Jar, user_id: &str) -> HttpResult<()> {
+        self.make_request(|client| {
+            client.delete(self.firebase_url(
+                jar,
+                format!("{FIREBASE_API_V2_MEMBERS_KEY}/{user_id}").as_str(),
+            ))
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn schedule_archival(&self, jar: &Jar, archive_after: u64) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(format!("{BASE_URL}/{ARCHIVAL_INDEX_PATH}/{jar}.json"))
+                .json(&archive_after)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn cancel_archival(&self, jar: &Jar) -> HttpResult<()> {
+        self.make_request(|client| {
+            client.delete(format!("{BASE_URL}/{ARCHIVAL_INDEX_PATH}/{jar}.json"))
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get_due_archivals(&self, now: u64) -> HttpResult<Vec<Jar>> {
+        let archivals: Option<HashMap<String, u64>> = self
+            .make_json_request(|client| {
+                client.get(format!("{BASE_URL}/{ARCHIVAL_INDEX_PATH}.json"))
+            })
+            .await?;
+        Ok(archivals
+            .unwrap_or_default()
+            .iter()
+            .filter(|(_, archive_after)| **archive_after <= now)
+            .map(|(jar, _)| Jar::new(jar))
+            .collect())
+    }
+
+    async fn archive_jar(&self, jar: &Jar) -> HttpResult<()> {
+        let data: Value = self
+            .make_json_request(|client| client.get(format!("{BASE_URL}/v2/{jar}.json")))
+            .await?;
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(format!("{BASE_URL}/{ARCHIVE_PATH}/{jar}.json"))
+                .json(&data)
+        })
+        .await?;
+        self.make_request(|client| client.delete(format!("{BASE_URL}/v2/{jar}.json")))
+            .await?;
+        self.cancel_archival(jar).await
+    }
+
     fn firebase_url(&self, jar: &Jar, path: &str) -> String {
         format!("{BASE_URL}/v2/{jar}/{path}.json")
     }
 }
+
+#[cfg(test)]
+pub mod fixtures {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use crate::app::address::Address;
+    use crate::app::api_token::ApiToken;
+    use crate::app::coordinates::Coordinates;
+    use crate::app::core::{Meal, Place};
+    use crate::app::history::HistoryEntry;
+    use crate::app::jar::Jar;
+    use crate::app::manage::PlaceDetails;
+    use crate::app::membership::{JarStatus, Member};
+    use crate::app::session::Session;
+    use crate::gcp::api::FirebaseApi;
+    use crate::gcp::constants::CLOSE_PLACE_RADIUS_METER;
+    use crate::geocoding::context::GeocodingContext;
+    use crate::http::HttpResult;
+
+    #[derive(Default)]
+    pub struct MemoryJar {
+        pub label: Option<String>,
+        pub places: BTreeMap<String, PlaceDetails>,
+        pub current_draw: Option<String>,
+        pub history: Vec<HistoryEntry>,
+        pub session: Option<Session>,
+        pub geocoding: Option<GeocodingContext>,
+        pub saved_locations: BTreeMap<String, Coordinates>,
+        pub status: Option<JarStatus>,
+        pub members: HashMap<String, Member>,
+        pub api_tokens: HashMap<String, ApiToken>,
+    }
+
+    /// Datastore kept in memory; draws pick the first matching place so tests are deterministic
+    #[derive(Default)]
+    pub struct MemoryFirebase {
+        jars: Mutex<HashMap<String, MemoryJar>>,
+        archivals: Mutex<HashMap<String, u64>>,
+        archive: Mutex<HashMap<String, MemoryJar>>,
+        next_key: AtomicUsize,
+    }
+
+    impl MemoryFirebase {
+        pub fn with_jar<R>(&self, jar: &Jar, f: impl FnOnce(&mut MemoryJar) -> R) -> R {
+            let mut jars = self.jars.lock().unwrap();
+            f(jars.entry(jar.to_string()).or_default())
+        }
+
+        pub fn archived_jar<R>(&self, jar: &Jar, f: impl FnOnce(Option<&MemoryJar>) -> R) -> R {
+            f(self.archive.lock().unwrap().get(&jar.to_string()))
+        }
+    }
+
+    #[async_trait]
+    impl FirebaseApi for MemoryFirebase {
+        async fn add_label(&self, jar: &Jar, label: &str) -> HttpResult<String> {
+            self.with_jar(jar, |data| data.label = Some(label.to_string()));
+            Ok(label.to_string())
+        }
+
+        async fn get_label(&self, jar: &Jar) -> HttpResult<Option<String>> {
+            Ok(self.with_jar(jar, |data| data.label.clone()))
+        }
+
+        async fn get_current_draw(&self, jar: &Jar) -> HttpResult<Option<Place>> {
+            Ok(self.with_jar(jar, |data| {
+                data.current_draw
+                    .as_ref()
+                    .and_then(|key| data.places.get(key))
+                    .map(PlaceDetails::place)
+            }))
+        }
+
+        async fn draw(
+            &self,
+            jar: &Jar,
+            meal: &Meal,
+            coordinates: &Option<Coordinates>,
+            area: Option<&str>,
+        ) -> HttpResult<Option<Place>> {
+            Ok(self.with_jar(jar, |data| {
+                let place = data
+                    .places
+                    .values()
+                    .filter(|place| place.meals.contains(meal))
+                    .find(|place| match (area, coordinates) {
+                        // The area replaces the origin
+                        (Some(area), _) => place
+                            .address
+                            .as_ref()
+                            .is_some_and(|address| address.is_in(area)),
+                        (None, Some(origin)) => place
+                            .coordinates
+                            .as_ref()
+                            .is_some_and(|c| origin.distance(c) <= CLOSE_PLACE_RADIUS_METER),
+                        (None, None) => true,
+                    })
+                    .map(PlaceDetails::place);
+                if let Some(place) = &place {
+                    data.current_draw = Some(place.key.clone());
+                }
+                place
+            }))
+        }
+
+        async fn add_place(
+            &self,
+            jar: &Jar,
+            place_name: &str,
+            meals: &[Meal],
+            added_by: Option<&str>,
+        ) -> HttpResult<Place> {
+            let key = format!("place_{}", self.next_key.fetch_add(1, Ordering::Relaxed));
+            let place = PlaceDetails {
+                key: key.clone(),
+                name: place_name.to_string(),
+                meals: meals.to_vec(),
+                coordinates: None,
+                address: None,
+                tags: vec![],
+                added_by: added_by.map(str::to_string),
+                link: None,
+                last_visit: None,
+            };
+            self.with_jar(jar, |data| data.places.insert(key, place.clone()));
+            Ok(place.place())
+        }
+
+        async fn get_all_places(&self, jar: &Jar) -> HttpResult<Vec<Place>> {
+            Ok(self.with_jar(jar, |data| {
+                data.places.values().map(PlaceDetails::place).collect()
+            }))
+        }
+
+        async fn get_place_details(&self, jar: &Jar) -> HttpResult<Vec<PlaceDetails>> {
+            Ok(self.with_jar(jar, |data| data.places.values().cloned().collect()))
+        }
+
+        async fn update_place(&self, jar: &Jar, place: &PlaceDetails) -> HttpResult<()> {
+            self.with_jar(jar, |data| {
+                if let Some(stored) = data.places.get_mut(&place.key) {
+                    stored.name = place.name.clone();
+                    stored.meals = place.meals.clone();
+                    stored.tags = place.tags.clone();
+                }
+            });
+            Ok(())
+        }
+
+        async fn set_place_coordinates(
+            &self,
+            jar: &Jar,
+            place: &Place,
+            coordinates: &Coordinates,
+        ) -> HttpResult<()> {
+            self.with_jar(jar, |data| {
+                if let Some(stored) = data.places.get_mut(&place.key) {
+                    stored.coordinates = Some(coordinates.clone());
+                }
+            });
+            Ok(())
+        }
+
+        async fn set_place_address(
+            &self,
+            jar: &Jar,
+            place: &Place,
+            address: &Address,
+        ) -> HttpResult<()> {
+            self.with_jar(jar, |data| {
+                if let Some(stored) = data.places.get_mut(&place.key) {
+                    stored.address = Some(address.clone());
+                }
+            });
+            Ok(())
+        }
+
+        async fn get_place_address(&self, jar: &Jar, place: &Place) -> HttpResult<Option<Address>> {
+            Ok(self.with_jar(jar, |data| {
+                data.places
+                    .get(&place.key)
+                    .and_then(|stored| stored.address.clone())
+            }))
+        }
+
+        async fn set_place_link(&self, jar: &Jar, place: &Place, link: &str) -> HttpResult<()> {
+            self.with_jar(jar, |data| {
+                if let Some(stored) = data.places.get_mut(&place.key) {
+                    stored.link = Some(link.to_string());
+                }
+            });
+            Ok(())
+        }
+
+        async fn get_place_link(&self, jar: &Jar, place: &Place) -> HttpResult<Option<String>> {
+            Ok(self.with_jar(jar, |data| {
+                data.places
+                    .get(&place.key)
+                    .and_then(|stored| stored.link.clone())
+            }))
+        }
+
+        async fn remove_drawn_place(&self, jar: &Jar, _place: Option<&Place>) -> HttpResult<()> {
+            self.with_jar(jar, |data| data.current_draw = None);
+            Ok(())
+        }
+
+        async fn delete_place(&self, jar: &Jar, place: &Place) -> HttpResult<Place> {
+            self.with_jar(jar, |data| {
+                data.places.remove(&place.key);
+                data.current_draw = None;
+            });
+            Ok(place.clone())
+        }
+
+        async fn append_history(&self, jar: &Jar, entry: &HistoryEntry) -> HttpResult<()> {
+            self.with_jar(jar, |data| data.history.push(entry.clone()));
+            Ok(())
+        }
+
+        async fn get_history(&self, jar: &Jar) -> HttpResult<Vec<HistoryEntry>> {
+            Ok(self.with_jar(jar, |data| data.history.clone()))
+        }
+
+        async fn add_api_token(
+            &self,
+            jar: &Jar,
+            token_hash: &str,
+            token: &ApiToken,
+        ) -> HttpResult<()> {
+            self.with_jar(jar, |data| {
+                data.api_tokens
+                    .insert(token_hash.to_string(), token.clone())
+            });
+            Ok(())
+        }
+
+        async fn has_api_token(&self, jar: &Jar, token_hash: &str) -> HttpResult<bool> {
+            Ok(self.with_jar(jar, |data| data.api_tokens.contains_key(token_hash)))
+        }
+
+        async fn revoke_api_tokens(&self, jar: &Jar) -> HttpResult<()> {
+            self.with_jar(jar, |data| data.api_tokens.clear());
+            Ok(())
+        }
+
+        async fn get_session(&self, jar: &Jar) -> HttpResult<Option<Session>> {
+            Ok(self.with_jar(jar, |data| data.session.clone()))
+        }
+
+        async fn set_session(&self, jar: &Jar, session: &Session) -> HttpResult<()> {
+            self.with_jar(jar, |data| data.session = Some(session.clone()));
+            Ok(())
+        }
+
+        async fn get_geocoding_context(&self, jar: &Jar) -> HttpResult<Option<GeocodingContext>> {
+            Ok(self.with_jar(jar, |data| data.geocoding.clone()))
+        }
+
+        async fn set_geocoding_context(
+            &self,
+            jar: &Jar,
+            context: &GeocodingContext,
+        ) -> HttpResult<()> {
+            self.with_jar(jar, |data| data.geocoding = Some(context.clone()));
+            Ok(())
+        }
+
+        async fn get_saved_locations(
+            &self,
+            jar: &Jar,
+        ) -> HttpResult<BTreeMap<String, Coordinates>> {
+            Ok(self.with_jar(jar, |data| data.saved_locations.clone()))
+        }
+
+        async fn save_location(
+            &self,
+            jar: &Jar,
+            name: &str,
+            coordinates: &Coordinates,
+        ) -> HttpResult<()> {
+            self.with_jar(jar, |data| {
+                data.saved_locations
+                    .insert(name.to_string(), coordinates.clone())
+            });
+            Ok(())
+        }
+
+        async fn remove_saved_location(&self, jar: &Jar, name: &str) -> HttpResult<()> {
+            self.with_jar(jar, |data| data.saved_locations.remove(name));
+            Ok(())
+        }
+
+        async fn get_jar_status(&self, jar: &Jar) -> HttpResult<Option<JarStatus>> {
+            Ok(self.with_jar(jar, |data| data.status.clone()))
+        }
+
+        async fn set_jar_status(&self, jar: &Jar, status: &JarStatus) -> HttpResult<()> {
+            self.with_jar(jar, |data| data.status = Some(status.clone()));
+            Ok(())
+        }
+
+        async fn get_members(&self, jar: &Jar) -> HttpResult<HashMap<String, Member>> {
+            Ok(self.with_jar(jar, |data| data.members.clone()))
+        }
+
+        async fn add_member(&self, jar: &Jar, user_id: &str, member: &Member) -> HttpResult<()> {
+            self.with_jar(jar, |data| {
+                data.members.insert(user_id.to_string(), member.clone())
+            });
+            Ok(())
+        }
+
+        async fn remove_member(&self, jar: &Jar, user_id: &str) -> HttpResult<()> {
+            self.with_jar(jar, |data| data.members.remove(user_id));
+            Ok(())
+        }
+
+        async fn schedule_archival(&self, jar: &Jar, archive_after: u64) -> HttpResult<()> {
+            self.archivals
+                .lock()
+                .unwrap()
+                .insert(jar.to_string(), archive_after);
+            Ok(())
+        }
+
+        async fn cancel_archival(&self, jar: &Jar) -> HttpResult<()> {
+            self.archivals.lock().unwrap().remove(&jar.to_string());
+            Ok(())
+        }
+
+        async fn get_due_archivals(&self, now: u64) -> HttpResult<Vec<Jar>> {
+            Ok(self
+                .archivals
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, archive_after)| **archive_after <= now)
+                .map(|(jar, _)| Jar::new(jar))
+                .collect())
+        }
+
+        async fn archive_jar(&self, jar: &Jar) -> HttpResult<()> {
+            if let Some(data) = self.jars.lock().unwrap().remove(&jar.to_string()) {
+                self.archive.lock().unwrap().insert(jar.to_string(), data);
+            }
+            self.cancel_archival(jar).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gcp::api::path_segment;
+
+    #[test]
+    fn it_percent_encodes_path_segments() {
+        assert_eq!(
+            path_segment("オフィス"),
+            "%E3%82%AA%E3%83%95%E3%82%A3%E3%82%B9"
+        );
+        assert_eq!(path_segment("a b?c%d/e#f"), "a%20b%3Fc%25d%2Fe%23f");
+    }
+}
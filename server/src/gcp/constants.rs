@@ -7,6 +7,20 @@ pub(crate) const FIREBASE_API_V2_PLACES_KEY: &str = "places";
 pub(crate) const FIREBASE_API_V2_SLOTS_KEY: &str = "timeslots";
 pub(crate) const FIREBASE_API_V2_PLACE_NAME_TABLE: &str = "place_id_name";
 pub(crate) const FIREBASE_API_V2_PLACE_COORDINATES_TABLE: &str = "place_id_coordinates";
+pub(crate) const FIREBASE_API_V2_PLACE_ADDRESS_TABLE: &str = "place_id_address";
+pub(crate) const FIREBASE_API_V2_SESSION_KEY: &str = "session";
+pub(crate) const FIREBASE_API_V2_STATUS_KEY: &str = "status";
+pub(crate) const FIREBASE_API_V2_MEMBERS_KEY: &str = "members";
+pub(crate) const FIREBASE_API_V2_HISTORY_KEY: &str = "history";
+pub(crate) const FIREBASE_API_V2_API_TOKENS_KEY: &str = "api_tokens";
+pub(crate) const FIREBASE_API_V2_GEOCODING_KEY: &str = "geocoding";
+pub(crate) const FIREBASE_API_V2_SAVED_LOCATIONS_KEY: &str = "saved_locations";
+// Top level tables, outside of the jars
+pub(crate) const ARCHIVAL_INDEX_PATH: &str = "archival";
+pub(crate) const ARCHIVE_PATH: &str = "archive";
+pub(crate) const WEBHOOK_EVENTS_PATH: &str = "webhook_events";
+pub(crate) const GEOCODING_QUEUE_PATH: &str = "geocoding_queue";
+pub(crate) const GEOCODING_CACHE_PATH: &str = "geocoding_cache";
 pub(crate) const LABEL_PATH: &str = "label";
 
 pub(crate) const CLOSE_PLACE_RADIUS_METER: f32 = 1000_f32;
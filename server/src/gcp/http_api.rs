@@ -1,24 +1,29 @@
 use std::collections::HashMap;
 
+use async_trait::async_trait;
 use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
 use reqwest::{Client, Response};
 use serde::de::DeserializeOwned;
 use serde_json::Value;
 
+use crate::app::address::Address;
 use crate::app::coordinates::Coordinates;
-use crate::app::core::{Meal, Place};
+use crate::app::core::Meal;
+use crate::app::geocoding_queue::{CachedCandidates, GeocodingJob, GeocodingStore};
 use crate::app::jar::Jar;
-use crate::gcp::api::ApiV2Place;
 use crate::gcp::api::FirebaseApi;
 use crate::gcp::constants::BASE_URL;
 use crate::gcp::constants::CLOSE_PLACE_RADIUS_METER;
 use crate::gcp::constants::FIREBASE_API_V2_CURRENT_DRAW_KEY;
-use crate::gcp::constants::FIREBASE_API_V2_PLACES_KEY;
+use crate::gcp::constants::FIREBASE_API_V2_PLACE_ADDRESS_TABLE;
 use crate::gcp::constants::FIREBASE_API_V2_PLACE_COORDINATES_TABLE;
 use crate::gcp::constants::FIREBASE_API_V2_PLACE_NAME_TABLE;
 use crate::gcp::constants::FIREBASE_API_V2_SLOTS_KEY;
+use crate::gcp::constants::WEBHOOK_EVENTS_PATH;
+use crate::gcp::constants::{GEOCODING_CACHE_PATH, GEOCODING_QUEUE_PATH};
 use crate::gcp::oauth;
 use crate::http::{ApiError, HttpClient, HttpResult};
+use crate::line::dedup::EventStore;
 
 pub struct FirebaseApiV2 {
     client: Client,
@@ -69,21 +74,6 @@ impl FirebaseApiV2 {
         Ok(place)
     }
 
-    pub async fn get_all_places(&self, jar: &Jar) -> HttpResult<Vec<Place>> {
-        let places: HashMap<String, ApiV2Place> = self
-            .make_json_request(|client| {
-                client.get(self.firebase_url(jar, FIREBASE_API_V2_PLACES_KEY))
-            })
-            .await?;
-        Ok(places
-            .iter()
-            .map(|(key, place)| Place {
-                key: key.clone(),
-                name: place.name.clone(),
-            })
-            .collect())
-    }
-
     pub async fn update_current_draw(&self, jar: &Jar, drawn_place_key: &str) -> HttpResult<()> {
         let _: Value = self
             .make_json_request(|client| {
@@ -137,6 +127,26 @@ impl FirebaseApiV2 {
         Ok(closed_places)
     }
 
+    pub(crate) async fn find_places_in_area(
+        &self,
+        jar: &Jar,
+        meal_places: HashMap<String, Value>,
+        area: &str,
+    ) -> HttpResult<Vec<String>> {
+        let addresses = self
+            .make_json_request::<Option<HashMap<String, Address>>, _>(|client| {
+                client.get(self.firebase_url(jar, FIREBASE_API_V2_PLACE_ADDRESS_TABLE))
+            })
+            .await?;
+        Ok(addresses
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(key, address)| {
+                (address.is_in(area) && meal_places.contains_key(&key)).then_some(key)
+            })
+            .collect())
+    }
+
     pub async fn get_all_groups(&self) -> HttpResult<Vec<Jar>> {
         self.make_json_request::<HashMap<String, Value>, _>(|client| {
             client
@@ -147,15 +157,12 @@ impl FirebaseApiV2 {
         .map(|raw| raw.keys().map(|k| Jar::new(k)).collect::<Vec<_>>())
     }
 
-    pub(crate) async fn make_json_request<
-        T: DeserializeOwned,
-        O: FnOnce(&Client) -> reqwest::RequestBuilder,
-    >(
+    pub(crate) async fn make_json_request<T: DeserializeOwned, O>(
         &self,
         to_request: O,
     ) -> HttpResult<T>
     where
-        O: Send,
+        O: FnOnce(&Client) -> reqwest::RequestBuilder + Send,
     {
         self.client
             .make_request(to_request)
@@ -165,13 +172,102 @@ impl FirebaseApiV2 {
             .map_err(|e| ApiError::JsonParsing { error: e })
     }
 
-    pub(crate) async fn make_request<O: FnOnce(&Client) -> reqwest::RequestBuilder>(
-        &self,
-        to_request: O,
-    ) -> HttpResult<Response>
+    pub(crate) async fn make_request<O>(&self, to_request: O) -> HttpResult<Response>
     where
-        O: Send,
+        O: FnOnce(&Client) -> reqwest::RequestBuilder + Send,
     {
         self.client.make_request(to_request).await
     }
 }
+
+#[async_trait]
+impl EventStore for FirebaseApiV2 {
+    async fn insert_event(&self, event_id: &str, received_at: u64) -> HttpResult<bool> {
+        // Only written when absent, so that two instances cannot both claim an event
+        let written = self
+            .make_request(|client| {
+                client
+                    .put(format!("{BASE_URL}/{WEBHOOK_EVENTS_PATH}/{event_id}.json"))
+                    .header("if-match", "null_etag")
+                    .json(&received_at)
+            })
+            .await;
+        match written {
+            Ok(_) => Ok(true),
+            Err(ApiError::Http { code: 412, .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn prune_events(&self, received_before: u64) -> HttpResult<()> {
+        let events: Option<HashMap<String, u64>> = self
+            .make_json_request(|client| {
+                client.get(format!("{BASE_URL}/{WEBHOOK_EVENTS_PATH}.json"))
+            })
+            .await?;
+        // Null values delete the matching children in a single update
+        let expired: HashMap<String, Value> = events
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(_, received_at)| *received_at < received_before)
+            .map(|(event_id, _)| (event_id, Value::Null))
+            .collect();
+        if expired.is_empty() {
+            return Ok(());
+        }
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .patch(format!("{BASE_URL}/{WEBHOOK_EVENTS_PATH}.json"))
+                .json(&expired)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl GeocodingStore for FirebaseApiV2 {
+    async fn get_jobs(&self) -> HttpResult<Vec<GeocodingJob>> {
+        let jobs: Option<HashMap<String, GeocodingJob>> = self
+            .make_json_request(|client| {
+                client.get(format!("{BASE_URL}/{GEOCODING_QUEUE_PATH}.json"))
+            })
+            .await?;
+        Ok(jobs.unwrap_or_default().into_values().collect())
+    }
+
+    async fn put_job(&self, id: &str, job: &GeocodingJob) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(format!("{BASE_URL}/{GEOCODING_QUEUE_PATH}/{id}.json"))
+                .json(job)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_job(&self, id: &str) -> HttpResult<()> {
+        self.make_request(|client| {
+            client.delete(format!("{BASE_URL}/{GEOCODING_QUEUE_PATH}/{id}.json"))
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get_cached_candidates(&self, key: &str) -> HttpResult<Option<CachedCandidates>> {
+        self.make_json_request(|client| {
+            client.get(format!("{BASE_URL}/{GEOCODING_CACHE_PATH}/{key}.json"))
+        })
+        .await
+    }
+
+    async fn cache_candidates(&self, key: &str, cached: &CachedCandidates) -> HttpResult<()> {
+        self.make_json_request::<Value, _>(|client| {
+            client
+                .put(format!("{BASE_URL}/{GEOCODING_CACHE_PATH}/{key}.json"))
+                .json(cached)
+        })
+        .await?;
+        Ok(())
+    }
+}
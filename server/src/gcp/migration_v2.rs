@@ -56,7 +56,7 @@ async fn migrating_entry(jar: &Jar, values: &serde_json::Value, api: &FirebaseAp
         }
         println!("Adding shops {shops:?}");
         for (name, meals) in shops.iter() {
-            let _ = api.add_place(jar, name, meals).await;
+            let _ = api.add_place(jar, name, meals, None).await;
         }
     } else {
         println!("Unknown entry {values:?}")
@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::app::address::Address;
+use crate::app::coordinates::Coordinates;
+use crate::bing::http::BingClient;
+use crate::geocoding::context::GeocodingContext;
+use crate::geocoding::nominatim::{NominatimClient, BASE_NOMINATIM_URL};
+
+#[derive(Debug)]
+pub struct GeocodingError(pub String);
+
+impl Display for GeocodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Geocoding Error: {}", self.0)
+    }
+}
+
+impl Error for GeocodingError {}
+
+impl From<reqwest::Error> for GeocodingError {
+    fn from(value: reqwest::Error) -> Self {
+        GeocodingError(format!("{value:?}"))
+    }
+}
+
+/// A location a place name may refer to, such as one branch of a chain
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub address: String,
+    /// Same address split in parts, saved with the place
+    #[serde(default)]
+    pub details: Address,
+    pub coordinates: Coordinates,
+}
+
+/// Finds where the places of the jars are
+#[async_trait]
+pub trait Geocoder {
+    /// Locations a place name may refer to within the context, best match first
+    async fn candidates(
+        &self,
+        query: &str,
+        context: &GeocodingContext,
+        limit: usize,
+    ) -> Result<Vec<Candidate>, GeocodingError>;
+
+    /// Coordinates of the best match of a place name
+    async fn geocode(
+        &self,
+        query: &str,
+        context: &GeocodingContext,
+    ) -> Result<Coordinates, GeocodingError> {
+        self.candidates(query, context, 1)
+            .await?
+            .into_iter()
+            .next()
+            .map(|candidate| candidate.coordinates)
+            .ok_or_else(|| GeocodingError(format!("Could not find {query}")))
+    }
+}
+
+/// Query parameters narrowing the searches, from a json object of strings
+pub(crate) fn context_from_json(json: &str) -> Vec<(String, String)> {
+    match serde_json::from_str::<Value>(json) {
+        Ok(Value::Object(o)) => o
+            .into_iter()
+            .filter_map(|(k, v)| v.as_str().map(|v| (k, v.to_string())))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Parameters of the jar context, followed by the configured ones the context leaves unset
+pub(crate) fn merge_parameters(
+    mut parameters: Vec<(String, String)>,
+    configured: &[(String, String)],
+) -> Vec<(String, String)> {
+    for (key, value) in configured {
+        if !parameters.iter().any(|(k, _)| k == key) {
+            parameters.push((key.clone(), value.clone()));
+        }
+    }
+    parameters
+}
+
+/// Geocoder picked by the `GEOCODER` env variable
+pub enum Provider {
+    Bing(BingClient),
+    Nominatim(NominatimClient),
+}
+
+impl Provider {
+    pub fn from_env() -> Self {
+        match std::env::var("GEOCODER").as_deref() {
+            Ok("nominatim") => {
+                let base_url = std::env::var("NOMINATIM_URL")
+                    .unwrap_or_else(|_| BASE_NOMINATIM_URL.to_string());
+                let context = std::env::var("NOMINATIM_CONTEXT")
+                    .map_or_else(|_| vec![], |context| context_from_json(&context));
+                Provider::Nominatim(NominatimClient::new(&base_url, context))
+            }
+            Ok("bing") | Err(_) => Provider::Bing(BingClient::default()),
+            Ok(other) => panic!("Unknown GEOCODER {other}, expected bing or nominatim"),
+        }
+    }
+}
+
+#[async_trait]
+impl Geocoder for Provider {
+    async fn candidates(
+        &self,
+        query: &str,
+        context: &GeocodingContext,
+        limit: usize,
+    ) -> Result<Vec<Candidate>, GeocodingError> {
+        match self {
+            Provider::Bing(client) => client.candidates(query, context, limit).await,
+            Provider::Nominatim(client) => client.candidates(query, context, limit).await,
+        }
+    }
+}
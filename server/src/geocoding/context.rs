@@ -0,0 +1,148 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::app::coordinates::Coordinates;
+
+const DEFAULT_COUNTRY: &str = "JP";
+const DEFAULT_CULTURE: &str = "ja";
+const USAGE: &str = "例: 「地域 US en」、近くを優先するには「地域 US en 40.71,-74.00」";
+
+/// Area the results are searched in first, by its south-west and north-east corners
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BoundingBox {
+    pub south_west: Coordinates,
+    pub north_east: Coordinates,
+}
+
+/// Where and in which language a jar looks its places up
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GeocodingContext {
+    /// ISO 3166-1 alpha-2 code, such as JP
+    pub country: String,
+    /// Language of the addresses, such as ja
+    pub culture: String,
+    /// Results close to this point come first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub near: Option<Coordinates>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<BoundingBox>,
+}
+
+impl Default for GeocodingContext {
+    fn default() -> Self {
+        GeocodingContext {
+            country: DEFAULT_COUNTRY.to_string(),
+            culture: DEFAULT_CULTURE.to_string(),
+            near: None,
+            bounds: None,
+        }
+    }
+}
+
+impl Display for GeocodingContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}・{}", self.country, self.culture)?;
+        if let Some(near) = &self.near {
+            write!(f, "・{},{}付近", near.latitude, near.longitude)?;
+        }
+        if let Some(bounds) = &self.bounds {
+            write!(
+                f,
+                "・{},{}〜{},{}の範囲",
+                bounds.south_west.latitude,
+                bounds.south_west.longitude,
+                bounds.north_east.latitude,
+                bounds.north_east.longitude
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn numbers(text: &str) -> Option<Vec<f32>> {
+    text.split(',').map(|n| n.trim().parse().ok()).collect()
+}
+
+fn coordinates(latitude: f32, longitude: f32) -> Option<Coordinates> {
+    (latitude.abs() <= 90.0 && longitude.abs() <= 180.0).then_some(Coordinates {
+        latitude,
+        longitude,
+    })
+}
+
+impl GeocodingContext {
+    /// Context of a chat command argument such as `US en`, `FR fr 48.85,2.35` or
+    /// `JP ja 35.5,139.5,35.8,139.9` for a bounding box; errors are shown as is in the chat
+    pub fn parse(text: &str) -> Result<GeocodingContext, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let (country, culture, area) = match words.as_slice() {
+            [country] => (*country, DEFAULT_CULTURE, None),
+            [country, culture] => (*country, *culture, None),
+            [country, culture, area] => (*country, *culture, Some(*area)),
+            _ => return Err(format!("地域を読めませんでした。{USAGE}")),
+        };
+        if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(format!("「{country}」は国コードではありません。{USAGE}"));
+        }
+        if !culture.chars().all(|c| c.is_ascii_alphabetic() || c == '-') {
+            return Err(format!("「{culture}」は言語コードではありません。{USAGE}"));
+        }
+        let mut context = GeocodingContext {
+            country: country.to_uppercase(),
+            culture: culture.to_string(),
+            near: None,
+            bounds: None,
+        };
+        if let Some(area) = area {
+            match numbers(area).as_deref() {
+                Some([latitude, longitude]) => {
+                    context.near = coordinates(*latitude, *longitude);
+                    context.near.as_ref()
+                }
+                Some([south, west, north, east]) if south < north && west < east => {
+                    context.bounds = coordinates(*south, *west)
+                        .zip(coordinates(*north, *east))
+                        .map(|(south_west, north_east)| BoundingBox {
+                            south_west,
+                            north_east,
+                        });
+                    context.bounds.as_ref().map(|b| &b.south_west)
+                }
+                _ => None,
+            }
+            .ok_or_else(|| format!("「{area}」は位置ではありません。{USAGE}"))?;
+        }
+        Ok(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::coordinates::Coordinates;
+    use crate::geocoding::context::GeocodingContext;
+
+    #[test]
+    fn it_parses_chat_arguments() {
+        let context = GeocodingContext::parse("us en 40.71,-74.0").unwrap();
+        assert_eq!(context.country, "US");
+        assert_eq!(context.culture, "en");
+        assert_eq!(
+            context.near,
+            Some(Coordinates {
+                latitude: 40.71,
+                longitude: -74.0
+            })
+        );
+        assert_eq!(context.to_string(), "US・en・40.71,-74付近");
+
+        let context = GeocodingContext::parse("JP ja 35.5,139.5,35.8,139.9").unwrap();
+        assert_eq!(context.bounds.unwrap().north_east.latitude, 35.8);
+        assert_eq!(GeocodingContext::parse("FR").unwrap().culture, "ja");
+
+        assert!(GeocodingContext::parse("").is_err());
+        assert!(GeocodingContext::parse("日本").is_err());
+        assert!(GeocodingContext::parse("JP ja 91,0").is_err());
+        assert!(GeocodingContext::parse("JP ja 35.8,139.9,35.5,139.5").is_err());
+    }
+}
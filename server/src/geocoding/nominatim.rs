@@ -0,0 +1,193 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::app::address::Address;
+use crate::app::coordinates::Coordinates;
+use crate::geocoding::api::{merge_parameters, Candidate, Geocoder, GeocodingError};
+use crate::geocoding::context::GeocodingContext;
+
+pub(crate) const BASE_NOMINATIM_URL: &str = "https://nominatim.openstreetmap.org";
+
+// The usage policy of the public instance asks for an identifying user agent
+const USER_AGENT: &str = "taberando";
+// Nominatim has no point bias, results are preferred within this many degrees around it
+const NEAR_DEGREES: f32 = 0.05;
+
+// Nominatim writes the coordinates as strings
+#[derive(Deserialize, Debug)]
+struct NominatimPlace {
+    lat: String,
+    lon: String,
+    display_name: String,
+    #[serde(default)]
+    address: NominatimAddress,
+}
+
+// Only the keys present for the place are sent, named after their OpenStreetMap kind
+#[derive(Deserialize, Debug, Default)]
+struct NominatimAddress {
+    house_number: Option<String>,
+    road: Option<String>,
+    quarter: Option<String>,
+    neighbourhood: Option<String>,
+    suburb: Option<String>,
+    city: Option<String>,
+    town: Option<String>,
+    village: Option<String>,
+    county: Option<String>,
+    state: Option<String>,
+    province: Option<String>,
+    postcode: Option<String>,
+    country: Option<String>,
+}
+
+impl NominatimAddress {
+    fn details(self) -> Address {
+        let street = self
+            .road
+            .or(self.quarter)
+            .or(self.neighbourhood)
+            .or(self.suburb);
+        Address {
+            line: match (street, self.house_number) {
+                (Some(street), Some(number)) => Some(format!("{street}{number}")),
+                (street, _) => street,
+            },
+            district: self.city.or(self.town).or(self.village).or(self.county),
+            region: self.state.or(self.province),
+            postal_code: self.postcode,
+            country: self.country,
+        }
+    }
+}
+
+impl NominatimPlace {
+    fn candidate(self) -> Option<Candidate> {
+        Some(Candidate {
+            address: self.display_name,
+            details: self.address.details(),
+            coordinates: Coordinates {
+                latitude: self.lat.parse().ok()?,
+                longitude: self.lon.parse().ok()?,
+            },
+        })
+    }
+}
+
+/// Client of the OpenStreetMap Nominatim search API, or of any compatible server
+pub struct NominatimClient {
+    client: reqwest::Client,
+    base_url: String,
+    context: Vec<(String, String)>,
+}
+
+impl NominatimClient {
+    /// `context` holds query parameters narrowing the searches, such as `countrycodes`
+    pub fn new(base_url: &str, context: Vec<(String, String)>) -> Self {
+        NominatimClient {
+            client: reqwest::Client::builder()
+                .user_agent(USER_AGENT)
+                .build()
+                .unwrap(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            context,
+        }
+    }
+}
+
+#[async_trait]
+impl Geocoder for NominatimClient {
+    async fn candidates(
+        &self,
+        query: &str,
+        context: &GeocodingContext,
+        limit: usize,
+    ) -> Result<Vec<Candidate>, GeocodingError> {
+        let mut parameters = vec![
+            ("q".to_string(), query.to_string()),
+            ("format".to_string(), "jsonv2".to_string()),
+            ("limit".to_string(), limit.to_string()),
+            ("addressdetails".to_string(), "1".to_string()),
+            ("accept-language".to_string(), context.culture.clone()),
+            ("countrycodes".to_string(), context.country.to_lowercase()),
+        ];
+        // The view box only orders the results, unless bounded is set
+        let view_box = match (&context.bounds, &context.near) {
+            (Some(bounds), _) => Some((&bounds.south_west, &bounds.north_east, 0.0)),
+            (None, Some(near)) => Some((near, near, NEAR_DEGREES)),
+            (None, None) => None,
+        };
+        if let Some((south_west, north_east, margin)) = view_box {
+            let view_box = format!(
+                "{},{},{},{}",
+                south_west.longitude - margin,
+                north_east.latitude + margin,
+                north_east.longitude + margin,
+                south_west.latitude - margin
+            );
+            parameters.push(("viewbox".to_string(), view_box));
+        }
+        let parameters = merge_parameters(parameters, &self.context);
+        let places: Vec<NominatimPlace> = self
+            .client
+            .get(format!("{}/search", self.base_url))
+            .query(&parameters)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(places
+            .into_iter()
+            .filter_map(NominatimPlace::candidate)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use warp::Filter;
+
+    use crate::geocoding::api::Geocoder;
+    use crate::geocoding::context::GeocodingContext;
+    use crate::geocoding::nominatim::NominatimClient;
+
+    #[tokio::test]
+    async fn it_reads_the_first_search_result() {
+        let search = warp::path!("search")
+            .and(warp::query::<HashMap<String, String>>())
+            .map(|query: HashMap<String, String>| {
+                let body = match (
+                    query.get("q").map(String::as_str),
+                    query.get("countrycodes").map(String::as_str),
+                ) {
+                    (Some("一蘭 渋谷"), Some("jp")) => {
+                        include_str!("../../resources/geocoding/nominatim_search.json")
+                    }
+                    _ => "[]",
+                };
+                warp::reply::with_header(body, "content-type", "application/json")
+            });
+        let (address, server) = warp::serve(search).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        // The configured parameters do not override the ones of the jar
+        let configured = vec![("countrycodes".to_string(), "fr".to_string())];
+        let nominatim = NominatimClient::new(&format!("http://{address}/"), configured);
+        let japan = GeocodingContext::default();
+
+        let coordinates = nominatim.geocode("一蘭 渋谷", &japan).await.unwrap();
+        assert_eq!(coordinates.latitude, 35.66124);
+        assert_eq!(coordinates.longitude, 139.69869);
+        assert!(nominatim.geocode("nowhere", &japan).await.is_err());
+        let france = GeocodingContext::parse("FR fr").unwrap();
+        assert!(nominatim.geocode("一蘭 渋谷", &france).await.is_err());
+
+        let candidates = nominatim.candidates("一蘭 渋谷", &japan, 3).await.unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert!(candidates[1].address.starts_with("一蘭 渋谷スペイン坂店"));
+        assert_eq!(candidates[1].details.district.as_deref(), Some("渋谷区"));
+        assert_eq!(candidates[1].details.line.as_deref(), Some("神南一丁目"));
+    }
+}
@@ -1,5 +1,11 @@
 pub mod app;
 pub mod bing;
 pub mod gcp;
+pub mod geocoding;
 mod http;
 pub mod line;
+pub mod messengers;
+pub mod rest;
+pub mod slack;
+pub mod telegram;
+pub mod web;
@@ -1,6 +1,13 @@
 pub mod api;
 pub mod bot;
+pub mod dedup;
+pub mod form;
 pub mod html;
 pub mod http;
 pub mod json;
+pub mod liff;
+pub mod manage;
+pub mod menu;
+pub mod render;
+pub mod simulator;
 pub mod webhook;
@@ -7,11 +7,10 @@ use reqwest::Body;
 
 use crate::http::{ApiError, Empty, HttpClient, HttpResult};
 use crate::line::http::{LineChannel, LineClient};
+use crate::line::menu;
 
 use super::json::*;
 
-const BASE_LINE_URL: &str = "https://api.line.me/v2/bot";
-
 #[async_trait]
 pub trait LineApi {
     async fn set_rich_menu(&self, rich_menu_id: &str, user: Option<&str>) -> HttpResult<Empty>;
@@ -22,6 +21,12 @@ pub trait LineApi {
 
     async fn get_rich_menu_id_from_alias(&self, alias: &str) -> HttpResult<String>;
 
+    async fn get_rich_menu_aliases(&self) -> HttpResult<Vec<RichMenuAlias>>;
+
+    async fn update_rich_menu_alias(&self, rich_menu_id: &str, alias: &str) -> HttpResult<Empty>;
+
+    async fn delete_rich_menu_alias(&self, alias: &str) -> HttpResult<Empty>;
+
     async fn create_rich_menu(&self, menu: &RichMenu, image_bytes: Vec<u8>) -> HttpResult<String>;
 
     async fn delete_rich_menu(&self, menu_id: &str) -> HttpResult<Empty>;
@@ -34,10 +39,6 @@ pub trait LineApi {
 
     async fn get_jar_info(&self, jar: &Jar) -> HttpResult<String>;
 
-    fn api_url(path: &str) -> String {
-        format!("{BASE_LINE_URL}/{path}")
-    }
-
     async fn set_rich_menu_from_alias(
         &self,
         menu_alias: &str,
@@ -55,14 +56,14 @@ impl LineApi for LineClient {
             Some(id) => format!("user/{id}/richmenu/{rich_menu_id}"),
             None => format!("user/all/richmenu/{rich_menu_id}"),
         };
-        self.make_json_request(|client| client.post(Self::api_url(path.as_str())))
+        self.make_json_request(|client| client.post(self.api_url(path.as_str())))
             .await
     }
 
     async fn set_rich_menu_alias(&self, rich_menu_id: &str, alias: &str) -> HttpResult<Empty> {
         self.make_json_request(|client| {
             client
-                .post(Self::api_url("richmenu/alias"))
+                .post(self.api_url("richmenu/alias"))
                 .json(&HashMap::from([
                     ("richMenuId", rich_menu_id),
                     ("richMenuAliasId", alias),
@@ -73,7 +74,7 @@ impl LineApi for LineClient {
 
     async fn get_rich_menus(&self) -> HttpResult<Vec<RichMenu>> {
         let menus: RichMenus = self
-            .make_json_request(|client| client.get(Self::api_url("richmenu/list")))
+            .make_json_request(|client| client.get(self.api_url("richmenu/list")))
             .await?;
         Ok(menus.rich_menus)
     }
@@ -81,7 +82,7 @@ impl LineApi for LineClient {
     async fn get_rich_menu_id_from_alias(&self, alias: &str) -> HttpResult<String> {
         let response: HashMap<String, String> = self
             .make_json_request(|client| {
-                client.get(Self::api_url(&format!("richmenu/alias/{alias}")))
+                client.get(self.api_url(&format!("richmenu/alias/{alias}")))
             })
             .await?;
         let menu_id = response.get("richMenuId").ok_or(ApiError::Unknown {
@@ -90,18 +91,44 @@ impl LineApi for LineClient {
         HttpResult::Ok(menu_id.to_string())
     }
 
+    async fn get_rich_menu_aliases(&self) -> HttpResult<Vec<RichMenuAlias>> {
+        let aliases: RichMenuAliases = self
+            .make_json_request(|client| client.get(self.api_url("richmenu/alias/list")))
+            .await?;
+        Ok(aliases.aliases)
+    }
+
+    async fn update_rich_menu_alias(&self, rich_menu_id: &str, alias: &str) -> HttpResult<Empty> {
+        self.make_json_request(|client| {
+            client
+                .post(self.api_url(format!("richmenu/alias/{alias}").as_str()))
+                .json(&HashMap::from([("richMenuId", rich_menu_id)]))
+        })
+        .await
+    }
+
+    async fn delete_rich_menu_alias(&self, alias: &str) -> HttpResult<Empty> {
+        self.make_json_request(|client| {
+            client.delete(self.api_url(format!("richmenu/alias/{alias}").as_str()))
+        })
+        .await
+    }
+
     async fn create_rich_menu(&self, menu: &RichMenu, image: Vec<u8>) -> HttpResult<String> {
         let menu: RichMenuId = self
-            .make_json_request(|client| client.post(Self::api_url("richmenu")).json(menu))
+            .make_json_request(|client| client.post(self.api_url("richmenu")).json(menu))
             .await?;
 
         let menu_id = menu.rich_menu_id;
-        let menu_url = format!("https://api-data.line.me/v2/bot/richmenu/{menu_id}/content");
+        let menu_url = self.data_url(&format!("richmenu/{menu_id}/content"));
         let _: Empty = self
             .make_json_request(|client| {
                 client
                     .post(menu_url)
-                    .header(CONTENT_TYPE, HeaderValue::from_static("image/jpeg"))
+                    .header(
+                        CONTENT_TYPE,
+                        HeaderValue::from_static(menu::image_content_type(&image)),
+                    )
                     .body(Body::from(image))
             })
             .await?;
@@ -110,15 +137,15 @@ impl LineApi for LineClient {
 
     async fn delete_rich_menu(&self, menu_id: &str) -> HttpResult<Empty> {
         self.make_json_request(|client| {
-            client.delete(Self::api_url(format!("richmenu/{menu_id}").as_str()))
+            client.delete(self.api_url(format!("richmenu/{menu_id}").as_str()))
         })
         .await
     }
 
     async fn get_default_menu(&self, user_id: Option<&str>) -> HttpResult<String> {
         self.make_json_request(|client| match user_id {
-            Some(id) => client.get(Self::api_url(format!("user/{id}/richmenu").as_str())),
-            None => client.get(Self::api_url("user/all/richmenu")),
+            Some(id) => client.get(self.api_url(format!("user/{id}/richmenu").as_str())),
+            None => client.get(self.api_url("user/all/richmenu")),
         })
         .await
         .map(|m: RichMenuId| m.rich_menu_id)
@@ -131,33 +158,30 @@ impl LineApi for LineClient {
         };
         self.make_json_request(|client| {
             client
-                .put(Self::api_url("channel/webhook/endpoint"))
+                .put(self.api_url("channel/webhook/endpoint"))
                 .json(&payload)
         })
         .await
     }
 
     async fn send_messages(&self, message: &Message) -> HttpResult<Empty> {
-        self.make_json_request(|client| client.post(Self::api_url("message/push")).json(message))
+        self.make_json_request(|client| client.post(self.api_url("message/push")).json(message))
             .await
     }
 
     async fn get_jar_info(&self, jar: &Jar) -> HttpResult<String> {
-        let raw_id = jar.line_channel().and_then(|channel| match channel {
-            LineChannel::User(_) => Err(JarError),
+        let (path, name_key) = jar.line_channel().and_then(|channel| match channel {
+            LineChannel::User(id) => Ok((format!("profile/{id}"), "displayName")),
             LineChannel::Room { .. } => Err(JarError),
-            LineChannel::Group { id, .. } => Ok(id),
+            LineChannel::Group { id, .. } => Ok((format!("group/{id}/summary"), "groupName")),
         })?;
 
-        let result: HashMap<String, String> = self
-            .make_json_request(|client| {
-                let url = Self::api_url(format!("group/{raw_id}/summary").as_str());
-                client.get(url)
-            })
+        let result: HashMap<String, serde_json::Value> = self
+            .make_json_request(|client| client.get(self.api_url(path.as_str())))
             .await?;
-        result.get("groupName").map_or(
+        result.get(name_key).and_then(|name| name.as_str()).map_or(
             Err(ApiError::Unknown {
-                message: "Could not get groupName from group info".to_string(),
+                message: format!("Could not get {name_key} from jar info"),
             }),
             |a| Ok(a.to_string()),
         )
@@ -1,8 +1,7 @@
 use serde::Deserialize;
 
 use crate::app::core::Client;
-use crate::http::HttpResult;
-use crate::line::http::{LineChannel, LineClient};
+use crate::line::http::LineChannel;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct EventSource {
@@ -22,6 +21,10 @@ pub struct Postback {
 }
 
 impl EventSource {
+    pub(crate) fn user_id(&self) -> Option<String> {
+        self.user_id.clone()
+    }
+
     pub fn to_client(&self) -> Option<Client> {
         let user_id = self.user_id.as_ref();
         match self.source_type.as_str() {
@@ -42,10 +45,3 @@ impl EventSource {
         }
     }
 }
-
-pub async fn setup(_client: &LineClient, _source: &EventSource) -> HttpResult<()> {
-    // let menu_id = client.create_rich_menu(&RichMenu::default()).await?;
-    // println!("{}", menu_id);
-    // client.set_rich_menu(menu_id.as_str()).await?;
-    Ok(())
-}
@@ -0,0 +1,192 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::app::session;
+use crate::http::HttpResult;
+use crate::line::json::Event;
+
+// Number of event ids kept in memory; older ones are only found in the persistent store
+const RECENT_EVENTS_CAPACITY: usize = 1024;
+// Line stops redelivering a webhook well before this delay
+const EVENT_RETENTION_SECONDS: u64 = 24 * 60 * 60;
+
+/// Persistent record of the webhook events already handled
+#[async_trait]
+pub trait EventStore {
+    /// Record the event unless it already is; false when it was recorded before, possibly by
+    /// another instance
+    async fn insert_event(&self, event_id: &str, received_at: u64) -> HttpResult<bool>;
+
+    async fn prune_events(&self, received_before: u64) -> HttpResult<()>;
+}
+
+struct RecentEvents {
+    order: VecDeque<String>,
+    ids: HashSet<String>,
+}
+
+impl RecentEvents {
+    fn contains(&self, event_id: &str) -> bool {
+        self.ids.contains(event_id)
+    }
+
+    fn insert(&mut self, event_id: &str) {
+        if !self.ids.insert(event_id.to_string()) {
+            return;
+        }
+        self.order.push_back(event_id.to_string());
+        if self.order.len() > RECENT_EVENTS_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Drop the webhook events Line delivers more than once
+pub struct Deduplicator<S: EventStore> {
+    recent: Mutex<RecentEvents>,
+    store: S,
+    late_redeliveries: AtomicU64,
+}
+
+impl<S: EventStore> Deduplicator<S> {
+    pub fn new(store: S) -> Self {
+        Deduplicator {
+            recent: Mutex::new(RecentEvents {
+                order: VecDeque::new(),
+                ids: HashSet::new(),
+            }),
+            store,
+            late_redeliveries: AtomicU64::new(0),
+        }
+    }
+
+    /// Record the event and tell whether it was already seen, here or by another instance.
+    /// First deliveries are recorded too, as one may reach another instance than its redelivery.
+    pub async fn is_duplicate(&self, event: &Event) -> bool {
+        let event_id = match &event.webhook_event_id {
+            None => return false,
+            Some(id) => id.as_str(),
+        };
+        if event.is_redelivery() {
+            println!("Line redelivered webhook event {event_id}");
+        }
+        if self.recent.lock().unwrap().contains(event_id) {
+            return true;
+        }
+        self.recent.lock().unwrap().insert(event_id);
+        match self.store.insert_event(event_id, session::now()).await {
+            Ok(true) if event.is_redelivery() => {
+                // Its first delivery was lost, or recorded so long ago that it was pruned
+                let count = self.late_redeliveries.fetch_add(1, Ordering::Relaxed) + 1;
+                println!("Redelivered webhook event {event_id} was not recorded ({count} so far)");
+                false
+            }
+            Ok(inserted) => !inserted,
+            // Handling an event twice beats dropping it
+            Err(e) => {
+                println!("Could not store webhook event {event_id}: {e:?}");
+                false
+            }
+        }
+    }
+
+    /// Redelivered events whose first delivery was not on record
+    pub fn late_redeliveries(&self) -> u64 {
+        self.late_redeliveries.load(Ordering::Relaxed)
+    }
+
+    pub async fn prune(&self) -> HttpResult<()> {
+        self.store
+            .prune_events(session::now().saturating_sub(EVENT_RETENTION_SECONDS))
+            .await
+    }
+}
+
+#[cfg(test)]
+pub mod fixtures {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+
+    use crate::http::HttpResult;
+    use crate::line::dedup::EventStore;
+
+    /// Clones share their events, like instances sharing the database
+    #[derive(Default, Clone)]
+    pub struct MemoryEventStore(pub Arc<Mutex<HashMap<String, u64>>>);
+
+    #[async_trait]
+    impl EventStore for MemoryEventStore {
+        async fn insert_event(&self, event_id: &str, received_at: u64) -> HttpResult<bool> {
+            let mut events = self.0.lock().unwrap();
+            if events.contains_key(event_id) {
+                return Ok(false);
+            }
+            events.insert(event_id.to_string(), received_at);
+            Ok(true)
+        }
+
+        async fn prune_events(&self, received_before: u64) -> HttpResult<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .retain(|_, received_at| *received_at >= received_before);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::line::dedup::fixtures::MemoryEventStore;
+    use crate::line::dedup::Deduplicator;
+    use crate::line::json::Event;
+
+    fn event(event_id: &str, is_redelivery: bool) -> Event {
+        serde_json::from_value(serde_json::json!({
+            "type": "message",
+            "mode": "active",
+            "webhookEventId": event_id,
+            "deliveryContext": { "isRedelivery": is_redelivery },
+            "source": { "type": "user", "userId": "U1" },
+            "message": { "type": "text", "text": "whoami" }
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_drops_redelivered_events() {
+        let deduplicator = Deduplicator::new(MemoryEventStore::default());
+        assert!(!deduplicator.is_duplicate(&event("01H1", false)).await);
+        assert!(deduplicator.is_duplicate(&event("01H1", true)).await);
+        assert!(!deduplicator.is_duplicate(&event("01H2", true)).await);
+        assert_eq!(deduplicator.late_redeliveries(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_drops_events_seen_by_another_instance() {
+        let store = MemoryEventStore::default();
+        let first = Deduplicator::new(store.clone());
+        let second = Deduplicator::new(store);
+        assert!(!first.is_duplicate(&event("01H1", false)).await);
+        assert!(second.is_duplicate(&event("01H1", false)).await);
+        assert!(second.is_duplicate(&event("01H1", true)).await);
+        assert_eq!(second.late_redeliveries(), 0);
+    }
+
+    #[tokio::test]
+    async fn it_prunes_old_events() {
+        let store = MemoryEventStore::default();
+        store.0.lock().unwrap().insert("01H1".to_string(), 0);
+        let deduplicator = Deduplicator::new(store);
+        deduplicator.prune().await.unwrap();
+        assert!(!deduplicator.is_duplicate(&event("01H1", true)).await);
+        assert_eq!(deduplicator.late_redeliveries(), 1);
+    }
+}
@@ -0,0 +1,303 @@
+use crate::app::core::{AddOutcome, Meal, Place};
+use crate::app::geocoding_queue::FirstAttempt;
+
+const MAX_PLACE_NAME_LENGTH: usize = 50;
+
+pub(crate) struct TimeSlot {
+    pub(crate) value: &'static str,
+    pub(crate) label: &'static str,
+    pub(crate) meals: &'static [Meal],
+}
+
+// Options of the time input, the first one being checked by default
+pub(crate) const TIME_SLOTS: &[TimeSlot] = &[
+    TimeSlot {
+        value: "both",
+        label: "昼また夜",
+        meals: &[Meal::Lunch, Meal::Dinner],
+    },
+    TimeSlot {
+        value: "lunch",
+        label: "昼",
+        meals: &[Meal::Lunch],
+    },
+    TimeSlot {
+        value: "dinner",
+        label: "夜",
+        meals: &[Meal::Dinner],
+    },
+];
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum FormError {
+    EmptyName,
+    NameTooLong,
+    UnknownTimeSlot,
+    AlreadyAdded(String),
+    SimilarNames,
+}
+
+impl FormError {
+    pub(crate) fn message(&self) -> String {
+        match self {
+            FormError::EmptyName => "お店の名前を入力してください".to_string(),
+            FormError::NameTooLong => {
+                format!("お店の名前は{MAX_PLACE_NAME_LENGTH}文字以内にしてください")
+            }
+            FormError::UnknownTimeSlot => "いつ行くかを選んでください".to_string(),
+            FormError::AlreadyAdded(name) => format!("「{name}」はすでに登録されています"),
+            FormError::SimilarNames => {
+                "似た名前の店があります。それでも追加する場合はもう一度送信してください".to_string()
+            }
+        }
+    }
+}
+
+pub(crate) struct Submission {
+    pub(crate) place: String,
+    pub(crate) meals: Vec<Meal>,
+}
+
+fn normalized(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Places whose name contains, or is contained in, the given name
+pub(crate) fn similar_places<'a>(name: &str, places: &'a [Place]) -> Vec<&'a Place> {
+    let name = normalized(name);
+    if name.is_empty() {
+        return vec![];
+    }
+    places
+        .iter()
+        .filter(|place| {
+            let other = normalized(&place.name);
+            !other.is_empty() && (other.contains(&name) || name.contains(&other))
+        })
+        .collect()
+}
+
+/// Check the submitted entry against the jar places; similar names need a confirmed submission
+pub(crate) fn validate(
+    place: &str,
+    time: Option<&str>,
+    confirmed: bool,
+    places: &[Place],
+) -> Result<Submission, Vec<FormError>> {
+    let place = place.trim();
+    let mut errors = vec![];
+    if place.is_empty() {
+        errors.push(FormError::EmptyName);
+    } else if place.chars().count() > MAX_PLACE_NAME_LENGTH {
+        errors.push(FormError::NameTooLong);
+    }
+    let slot = TIME_SLOTS.iter().find(|slot| Some(slot.value) == time);
+    if slot.is_none() {
+        errors.push(FormError::UnknownTimeSlot);
+    }
+    let similar = similar_places(place, places);
+    if let Some(existing) = similar
+        .iter()
+        .find(|existing| normalized(&existing.name) == normalized(place))
+    {
+        errors.push(FormError::AlreadyAdded(existing.name.clone()));
+    } else if !similar.is_empty() && !confirmed {
+        errors.push(FormError::SimilarNames);
+    }
+    match slot {
+        Some(slot) if errors.is_empty() => Ok(Submission {
+            place: place.to_string(),
+            meals: slot.meals.to_vec(),
+        }),
+        _ => Err(errors),
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Values rendered in the add form template
+pub(crate) struct FormView<'a> {
+    pub(crate) label: Option<&'a str>,
+    pub(crate) csrf: &'a str,
+    pub(crate) place: &'a str,
+    pub(crate) time: Option<&'a str>,
+    pub(crate) errors: &'a [FormError],
+    pub(crate) similar: Vec<&'a Place>,
+}
+
+impl FormView<'_> {
+    pub(crate) fn render(&self, template: &str) -> String {
+        let checked = self.time.unwrap_or(TIME_SLOTS[0].value);
+        let time_slots: String = TIME_SLOTS
+            .iter()
+            .map(|slot| {
+                let checked = if slot.value == checked {
+                    " checked=\"checked\""
+                } else {
+                    ""
+                };
+                format!(
+                    "<label><input type=\"radio\" name=\"time\" value=\"{}\"{checked}/>{}</label>\n",
+                    slot.value, slot.label
+                )
+            })
+            .collect();
+        let errors: String = self
+            .errors
+            .iter()
+            .map(|error| format!("<li>{}</li>\n", escape(&error.message())))
+            .collect();
+        let similar: String = self
+            .similar
+            .iter()
+            .map(|place| format!("<li>{}</li>\n", escape(&place.name)))
+            .collect();
+        let confirmed = if self.errors.contains(&FormError::SimilarNames) {
+            "<input type=\"hidden\" name=\"confirmed\" value=\"true\"/>"
+        } else {
+            ""
+        };
+        template
+            .replace("{{label}}", &escape(self.label.unwrap_or("お店")))
+            .replace("{{csrf}}", self.csrf)
+            .replace("{{place}}", &escape(self.place))
+            .replace("{{time_slots}}", &time_slots)
+            .replace("{{errors}}", &errors)
+            .replace("{{similar}}", &similar)
+            .replace("{{confirmed}}", confirmed)
+    }
+}
+
+pub(crate) fn render_result(
+    template: &str,
+    outcome: Option<&AddOutcome>,
+    first_attempt: Option<&FirstAttempt>,
+) -> String {
+    let (title, message) = match outcome {
+        Some(AddOutcome::Added(place, _)) => (
+            format!("「{}」を追加しました", escape(&place.name)),
+            match first_attempt {
+                Some(FirstAttempt::Located(candidate)) => {
+                    format!("位置: {}", escape(&candidate.address))
+                }
+                Some(FirstAttempt::Ambiguous(count)) => {
+                    format!("位置の候補が{count}件見つかりました。トークで選んでください。")
+                }
+                Some(FirstAttempt::NotFound) => {
+                    "位置が見つかりませんでした。トークで位置情報を送ってください。".to_string()
+                }
+                Some(FirstAttempt::Retrying) | None => {
+                    "店の位置を探しています。見つかったらトークでお知らせします。".to_string()
+                }
+            },
+        ),
+        Some(AddOutcome::Failed(error)) => ("追加できませんでした".to_string(), escape(error)),
+        None => (
+            "追加を受け付けました".to_string(),
+            "結果はトークに届きます。".to_string(),
+        ),
+    };
+    template
+        .replace("{{title}}", &title)
+        .replace("{{message}}", &message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::coordinates::Coordinates;
+    use crate::app::core::{AddOutcome, Place};
+    use crate::app::geocoding_queue::FirstAttempt;
+    use crate::geocoding::api::Candidate;
+    use crate::line::form::{render_result, similar_places, validate, FormError, FormView};
+
+    fn places() -> Vec<Place> {
+        ["Saizeriya 新宿店", "一蘭"]
+            .iter()
+            .enumerate()
+            .map(|(key, name)| Place {
+                key: key.to_string(),
+                name: name.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_validates_entries() {
+        let places = places();
+        let submission = validate(" 松屋 ", Some("lunch"), false, &places).unwrap();
+        assert_eq!(submission.place, "松屋");
+        assert_eq!(submission.meals.len(), 1);
+        assert_eq!(
+            validate("", Some("brunch"), false, &places).err(),
+            Some(vec![FormError::EmptyName, FormError::UnknownTimeSlot])
+        );
+        assert_eq!(
+            validate("一 蘭", Some("both"), true, &places).err(),
+            Some(vec![FormError::AlreadyAdded("一蘭".to_string())])
+        );
+    }
+
+    #[test]
+    fn it_asks_to_confirm_similar_names() {
+        let places = places();
+        assert_eq!(
+            similar_places("saizeriya", &places)
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Saizeriya 新宿店"]
+        );
+        assert_eq!(
+            validate("saizeriya", Some("both"), false, &places).err(),
+            Some(vec![FormError::SimilarNames])
+        );
+        assert!(validate("saizeriya", Some("both"), true, &places).is_ok());
+    }
+
+    #[test]
+    fn it_renders_escaped_values() {
+        let places = places();
+        let html = FormView {
+            label: Some("<b>team</b>"),
+            csrf: "token",
+            place: "\"a\"",
+            time: Some("dinner"),
+            errors: &[FormError::EmptyName],
+            similar: similar_places("一蘭", &places),
+        }
+        .render("{{label}}|{{place}}|{{time_slots}}|{{similar}}");
+        assert!(html.starts_with("&lt;b&gt;team&lt;/b&gt;|&quot;a&quot;|"));
+        assert!(html.contains("value=\"dinner\" checked=\"checked\""));
+        assert!(html.contains("<li>一蘭</li>"));
+    }
+
+    #[test]
+    fn it_renders_the_first_attempt_at_locating_the_place() {
+        let added = AddOutcome::Added(places().remove(1), None);
+        let located = FirstAttempt::Located(Candidate {
+            address: "<渋谷区>".to_string(),
+            details: Default::default(),
+            coordinates: Coordinates {
+                latitude: 35.661,
+                longitude: 139.698,
+            },
+        });
+        let render = |attempt| render_result("{{title}}|{{message}}", Some(&added), attempt);
+        assert_eq!(
+            render(Some(&located)),
+            "「一蘭」を追加しました|位置: &lt;渋谷区&gt;"
+        );
+        assert!(render(Some(&FirstAttempt::Ambiguous(3))).contains("候補が3件"));
+        assert!(render(Some(&FirstAttempt::NotFound)).contains("位置情報を送って"));
+        assert!(render(None).contains("探しています"));
+    }
+}
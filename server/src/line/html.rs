@@ -1,27 +1,77 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Sender;
-use warp::Filter;
+use tokio::sync::oneshot;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Reply};
 
-use crate::app::core::{Action, Client, Meal};
+use crate::app::core::{Action, AddOutcome, Client, Place};
+use crate::app::jar::Jar;
+use crate::app::link::{LinkError, LinkPurpose, LinkSigner};
+use crate::app::session;
+use crate::gcp::api::FirebaseApi;
+use crate::line::form;
+use crate::line::form::FormView;
 use crate::line::http::LineChannel;
+use crate::line::liff::IdTokenVerifier;
+
+// Adding a place includes geocoding it; past this delay the result page only points to the chat
+const ADD_RESULT_TIMEOUT: Duration = Duration::from_secs(20);
 
 #[derive(Deserialize, Serialize, Debug)]
 struct Source {
     source: String,
-    source_type: String,
-    source_id: String,
+    token: String,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 struct Entry {
+    #[serde(default)]
+    place: String,
+    time: Option<String>,
+    csrf: String,
+    confirmed: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LiffEntry {
+    token: String,
+    id_token: String,
     place: String,
     time: String,
 }
 
+// LIFF pages are only served once a LIFF app is configured for the channel
+struct Liff {
+    id: String,
+    verifier: IdTokenVerifier,
+}
+
+impl Liff {
+    fn from_env() -> Option<Self> {
+        let id = std::env::var("LIFF_ID").ok()?;
+        let channel_id = std::env::var("LIFF_CHANNEL_ID")
+            .expect("Needs to have a LIFF_CHANNEL_ID env variable to verify LIFF ID tokens");
+        Some(Liff {
+            id,
+            verifier: IdTokenVerifier::new(&channel_id),
+        })
+    }
+}
+
 #[allow(opaque_hidden_inferred_bound)]
-pub fn route(
+pub fn route<T>(
     sender: Sender<(String, Action)>,
-) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Sync + Send {
+    firebase_client: Arc<T>,
+    signer: Arc<LinkSigner>,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Sync + Send
+where
+    T: FirebaseApi + Send + Sync + 'static,
+{
+    let liff = Arc::new(Liff::from_env());
     let source = warp::query::<Source>().and_then(|source: Source| async move {
         if source.source == "line" {
             Ok(source)
@@ -29,58 +79,247 @@ pub fn route(
             Err(warp::reject::not_found())
         }
     });
+    let with_signer = warp::any().map(move || signer.clone());
+    let with_firebase = warp::any().map(move || firebase_client.clone());
+    let with_sender = warp::any().map(move || sender.clone());
     let form_get = source
         .and(warp::get())
-        .map(|_| ())
-        .untuple_one()
-        .and(warp::fs::file("./resources/line/add.html"));
+        .and(with_signer.clone())
+        .and(with_firebase.clone())
+        .then(
+            |source: Source, signer: Arc<LinkSigner>, firebase_client: Arc<T>| async move {
+                let client =
+                    match signer.verify(&source.token, LinkPurpose::AddPlace, session::now()) {
+                        Ok(link) => link.client(),
+                        Err(e) => return rejected(&e),
+                    };
+                let Some(client) = client else {
+                    return StatusCode::BAD_REQUEST.into_response();
+                };
+                let (label, _) = jar_context(&client, firebase_client.as_ref()).await;
+                let csrf = signer.csrf_token(&source.token);
+                html_file("./resources/line/add.html", |template| {
+                    FormView {
+                        label: label.as_deref(),
+                        csrf: &csrf,
+                        place: "",
+                        time: None,
+                        errors: &[],
+                        similar: vec![],
+                    }
+                    .render(template)
+                })
+                .await
+            },
+        );
     let form_post = warp::post()
         .and(source)
         .and(warp::body::form::<Entry>())
         .and(warp::header::<String>("host"))
-        .and(warp::any().map(move || sender.clone()))
+        .and(with_signer.clone())
+        .and(with_firebase.clone())
+        .and(with_sender.clone())
         .then(
-            |source: Source, body: Entry, host: String, sender: Sender<(String, Action)>| async move {
-                let action = to_action(&source, &body);
-
-                if let Some(action) = action {
-                    tokio::spawn(async move {
+            |source: Source,
+             body: Entry,
+             host: String,
+             signer: Arc<LinkSigner>,
+             firebase_client: Arc<T>,
+             sender: Sender<(String, Action)>| async move {
+                let link = signer
+                    .verify(&source.token, LinkPurpose::AddPlace, session::now())
+                    .and_then(|link| signer.verify_csrf(&source.token, &body.csrf).map(|_| link));
+                let client = match link {
+                    Ok(link) => link.client(),
+                    Err(e) => return rejected(&e),
+                };
+                let Some(client) = client else {
+                    return StatusCode::BAD_REQUEST.into_response();
+                };
+                let (label, places) = jar_context(&client, firebase_client.as_ref()).await;
+                let submission = form::validate(
+                    &body.place,
+                    body.time.as_deref(),
+                    body.confirmed.is_some(),
+                    &places,
+                );
+                match submission {
+                    Ok(submission) => {
+                        let (responder, outcome) = oneshot::channel();
+                        let action = Action::Add(
+                            client,
+                            submission.place,
+                            submission.meals,
+                            Some(responder),
+                        );
                         let _ = sender.send((host, action)).await;
-                    });
+                        // The page waits for the first attempt at locating the place too
+                        let deadline = tokio::time::Instant::now() + ADD_RESULT_TIMEOUT;
+                        let mut outcome = tokio::time::timeout_at(deadline, outcome)
+                            .await
+                            .ok()
+                            .and_then(|outcome| outcome.ok());
+                        let first_attempt = match &mut outcome {
+                            Some(AddOutcome::Added(_, Some(first_attempt))) => {
+                                tokio::time::timeout_at(deadline, first_attempt)
+                                    .await
+                                    .ok()
+                                    .and_then(|first_attempt| first_attempt.ok())
+                            }
+                            _ => None,
+                        };
+                        html_file("./resources/line/result.html", |template| {
+                            form::render_result(template, outcome.as_ref(), first_attempt.as_ref())
+                        })
+                        .await
+                    }
+                    Err(errors) => {
+                        let csrf = signer.csrf_token(&source.token);
+                        html_file("./resources/line/add.html", |template| {
+                            FormView {
+                                label: label.as_deref(),
+                                csrf: &csrf,
+                                place: &body.place,
+                                time: body.time.as_deref(),
+                                errors: &errors,
+                                similar: form::similar_places(&body.place, &places),
+                            }
+                            .render(template)
+                        })
+                        .await
+                    }
+                }
+            },
+        );
+
+    let with_liff =
+        warp::any()
+            .map(move || liff.clone())
+            .and_then(|liff: Arc<Option<Liff>>| async move {
+                if liff.is_some() {
+                    Ok(liff)
                 } else {
-                    println!("Could not handle {source:?} {body:?}");
+                    Err(warp::reject::not_found())
+                }
+            });
+    // The LIFF page reads the link token from its own url once the LIFF SDK redirected to it
+    let liff_get = warp::get()
+        .and(with_liff.clone())
+        .then(|liff: Arc<Option<Liff>>| async move {
+            let id = liff.as_ref().as_ref().map_or("", |liff| liff.id.as_str());
+            html_file("./resources/line/liff.html", |template| {
+                template.replace("{{liff_id}}", id)
+            })
+            .await
+        });
+    let liff_post = warp::post()
+        .and(warp::body::json::<LiffEntry>())
+        .and(warp::header::<String>("host"))
+        .and(with_liff)
+        .and(with_signer)
+        .and(with_firebase)
+        .and(with_sender)
+        .then(
+            |body: LiffEntry,
+             host: String,
+             liff: Arc<Option<Liff>>,
+             signer: Arc<LinkSigner>,
+             firebase_client: Arc<T>,
+             sender: Sender<(String, Action)>| async move {
+                let link = match signer.verify(&body.token, LinkPurpose::AddPlace, session::now()) {
+                    Ok(link) => link,
+                    Err(e) => return rejected(&e),
+                };
+                let verifier = &liff.as_ref().as_ref().unwrap().verifier;
+                let claims = match verifier.verify(&body.id_token).await {
+                    Ok(claims) => claims,
+                    Err(e) => {
+                        println!("Rejected LIFF ID token: {e}");
+                        return StatusCode::UNAUTHORIZED.into_response();
+                    }
+                };
+                let Some(client) = link
+                    .client()
+                    .and_then(|client| with_user(client, &claims.sub))
+                else {
+                    println!("Could not handle {link:?} {body:?}");
+                    return StatusCode::BAD_REQUEST.into_response();
+                };
+                let (_, places) = jar_context(&client, firebase_client.as_ref()).await;
+                // The LIFF page has no confirmation step for similar names
+                match form::validate(&body.place, Some(&body.time), true, &places) {
+                    Ok(submission) => {
+                        println!("{} adds {}", claims.sub, submission.place);
+                        let action = Action::Add(client, submission.place, submission.meals, None);
+                        let _ = sender.send((host, action)).await;
+                        StatusCode::OK.into_response()
+                    }
+                    Err(errors) => {
+                        let messages: Vec<String> = errors.iter().map(|e| e.message()).collect();
+                        warp::reply::with_status(messages.join("\n"), StatusCode::BAD_REQUEST)
+                            .into_response()
+                    }
                 }
             },
-        )
-        .untuple_one()
-        .and(warp::fs::file("./resources/line/autoclose.html"));
+        );
 
-    warp::path!("line" / "draw").and(form_get.or(form_post))
+    warp::path!("line" / "draw")
+        .and(form_get.or(form_post))
+        .or(warp::path!("line" / "liff").and(liff_get.or(liff_post)))
 }
 
-fn to_action(source: &Source, body: &Entry) -> Option<Action> {
-    let source_id = source.source_id.clone();
-    let client = match source.source_type.as_str() {
-        "user" => Some(LineChannel::User(source_id)),
-        "group" => Some(LineChannel::Group {
-            id: source_id,
-            user_id: None,
-        }),
-        "room" => Some(LineChannel::Room {
-            id: source_id,
-            user_id: None,
-        }),
-        _ => None,
+// Label and places of the jar the form adds to; the form still works without them
+async fn jar_context<T: FirebaseApi + Sync>(
+    client: &Client,
+    firebase_client: &T,
+) -> (Option<String>, Vec<Place>) {
+    let jar: Jar = client.into();
+    let label = firebase_client.get_label(&jar).await.unwrap_or_else(|e| {
+        println!("Could not get the label of {jar:?}: {e:?}");
+        None
+    });
+    let places = firebase_client
+        .get_all_places(&jar)
+        .await
+        .unwrap_or_else(|e| {
+            println!("Could not get the places of {jar:?}: {e:?}");
+            vec![]
+        });
+    (label, places)
+}
+
+// The verified user adds the place; a one-on-one jar only accepts its own user
+fn with_user(client: Client, user_id: &str) -> Option<Client> {
+    let user_id = Some(user_id.to_string());
+    match client {
+        Client::Line(LineChannel::User(id)) => {
+            (user_id.as_ref() == Some(&id)).then_some(Client::Line(LineChannel::User(id)))
+        }
+        Client::Line(LineChannel::Group { id, .. }) => {
+            Some(Client::Line(LineChannel::Group { id, user_id }))
+        }
+        Client::Line(LineChannel::Room { id, .. }) => {
+            Some(Client::Line(LineChannel::Room { id, user_id }))
+        }
+        Client::Slack(_) | Client::Telegram(_) | Client::Web(_) => None,
     }
-    .map(Client::Line);
+}
 
-    client.and_then(|c| {
-        let meals = match body.time.as_str() {
-            "both" => Some(vec![Meal::Lunch, Meal::Dinner]),
-            "lunch" => Some(vec![Meal::Lunch]),
-            "dinner" => Some(vec![Meal::Dinner]),
-            _unknown => None,
-        };
-        meals.map(|m| Action::Add(c, body.place.to_string(), m))
-    })
+fn rejected(error: &LinkError) -> Response {
+    println!("Rejected form link: {error}");
+    warp::reply::with_status(
+        "リンクの有効期限が切れました。もう一度「+ 加」を押してください。",
+        StatusCode::FORBIDDEN,
+    )
+    .into_response()
+}
+
+pub(crate) async fn html_file<F: FnOnce(&str) -> String>(path: &str, render: F) -> Response {
+    match tokio::fs::read_to_string(path).await {
+        Ok(template) => warp::reply::html(render(&template)).into_response(),
+        Err(e) => {
+            println!("Could not read {path}: {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
 }
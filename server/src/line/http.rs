@@ -1,7 +1,11 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
 use async_trait::async_trait;
 use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
 use reqwest::Response;
 
+use crate::app::link::LinkSigner;
 use crate::http::{Empty, HttpClient, HttpResult};
 use crate::line::api::LineApi;
 use crate::line::json::{Message, MessageContent};
@@ -15,11 +19,70 @@ pub enum LineChannel {
     Group { id: String, user_id: Option<String> },
 }
 
+const BASE_LINE_URL: &str = "https://api.line.me";
+const BASE_LINE_DATA_URL: &str = "https://api-data.line.me";
+// One-on-one users whose linked menu is remembered; the others are relinked on their next reply
+const LINKED_MENUS_CAPACITY: usize = 10_000;
+
+// Menu alias last linked to each user, forgetting the users linked first past the capacity
+#[derive(Default)]
+struct LinkedMenus {
+    order: VecDeque<String>,
+    aliases: HashMap<String, &'static str>,
+}
+
+impl LinkedMenus {
+    fn insert(&mut self, user_id: &str, alias: &'static str) {
+        if self.aliases.insert(user_id.to_string(), alias).is_some() {
+            return;
+        }
+        self.order.push_back(user_id.to_string());
+        if self.order.len() > LINKED_MENUS_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.aliases.remove(&oldest);
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
-pub struct LineClient(reqwest::Client);
+pub struct LineClient {
+    client: reqwest::Client,
+    base_url: String,
+    data_url: String,
+    // Signs the add form links of the quick replies; needed to answer the chats
+    link_signer: Option<Arc<LinkSigner>>,
+    // LIFF app the add form links open, instead of the plain web form
+    liff_id: Option<String>,
+    // Menu alias last linked to each user, so that replies keeping the state skip the call
+    linked_menus: Arc<Mutex<LinkedMenus>>,
+}
 
 impl LineClient {
     pub fn new(line_token: &str) -> Self {
+        LineClient::with_urls(line_token, BASE_LINE_URL, BASE_LINE_DATA_URL)
+    }
+
+    /// Client of `LINE_API_URL` when it is set, such as the local simulator, linking the add
+    /// form to the `LIFF_ID` app when there is one
+    pub fn from_env(line_token: &str) -> Self {
+        let client = match std::env::var("LINE_API_URL") {
+            Ok(base_url) => LineClient::with_base_url(line_token, &base_url),
+            Err(_) => LineClient::new(line_token),
+        };
+        LineClient {
+            liff_id: std::env::var("LIFF_ID").ok(),
+            ..client
+        }
+    }
+
+    /// Client of a Messaging API served elsewhere, such as the local simulator, which also
+    /// serves the content uploads of `api-data.line.me`
+    pub fn with_base_url(line_token: &str, base_url: &str) -> Self {
+        LineClient::with_urls(line_token, base_url, base_url)
+    }
+
+    fn with_urls(line_token: &str, base_url: &str, data_url: &str) -> Self {
         let mut header_map = HeaderMap::new();
 
         let authorization_header = &*format!("Bearer {line_token}");
@@ -29,13 +92,52 @@ impl LineClient {
 
         header_map.append(CONTENT_TYPE, HeaderValue::from_static("application/json"));
 
-        LineClient(
-            reqwest::Client::builder()
+        LineClient {
+            client: reqwest::Client::builder()
                 .default_headers(header_map)
                 .connection_verbose(true)
                 .build()
                 .unwrap(),
-        )
+            base_url: base_url.trim_end_matches('/').to_string(),
+            data_url: data_url.trim_end_matches('/').to_string(),
+            link_signer: None,
+            liff_id: None,
+            linked_menus: Arc::default(),
+        }
+    }
+
+    pub fn with_link_signer(mut self, signer: Arc<LinkSigner>) -> Self {
+        self.link_signer = Some(signer);
+        self
+    }
+
+    pub(crate) fn link_signer(&self) -> Option<&LinkSigner> {
+        self.link_signer.as_deref()
+    }
+
+    pub(crate) fn liff_id(&self) -> Option<&str> {
+        self.liff_id.as_deref()
+    }
+
+    pub(crate) fn linked_menu(&self, user_id: &str) -> Option<&'static str> {
+        self.linked_menus
+            .lock()
+            .unwrap()
+            .aliases
+            .get(user_id)
+            .copied()
+    }
+
+    pub(crate) fn set_linked_menu(&self, user_id: &str, alias: &'static str) {
+        self.linked_menus.lock().unwrap().insert(user_id, alias);
+    }
+
+    pub(crate) fn api_url(&self, path: &str) -> String {
+        format!("{}/v2/bot/{path}", self.base_url)
+    }
+
+    pub(crate) fn data_url(&self, path: &str) -> String {
+        format!("{}/v2/bot/{path}", self.data_url)
     }
 
     pub async fn send_to(&self, id: &str, message: MessageContent) -> HttpResult<Empty> {
@@ -59,6 +161,28 @@ impl HttpClient for LineClient {
     where
         O: Send,
     {
-        self.0.make_request(to_request).await
+        self.client.make_request(to_request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::line::http::{LinkedMenus, LINKED_MENUS_CAPACITY};
+
+    #[test]
+    fn it_forgets_the_users_linked_first() {
+        let mut menus = LinkedMenus::default();
+        for user in 0..LINKED_MENUS_CAPACITY {
+            menus.insert(&format!("U{user}"), "idle");
+        }
+        // Relinking a known user keeps its place
+        menus.insert("U0", "active");
+        assert_eq!(menus.aliases.len(), LINKED_MENUS_CAPACITY);
+
+        menus.insert("U_new", "idle");
+        assert_eq!(menus.aliases.len(), LINKED_MENUS_CAPACITY);
+        assert!(!menus.aliases.contains_key("U0"));
+        assert_eq!(menus.aliases.get("U1"), Some(&"idle"));
+        assert_eq!(menus.aliases.get("U_new"), Some(&"idle"));
     }
 }
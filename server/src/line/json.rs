@@ -1,8 +1,9 @@
 use std::fmt::Debug;
 
 use crate::app;
-use crate::app::coordinates::Coordinates;
 use crate::app::core::Meal;
+use crate::app::link::LinkSigner;
+use crate::app::response::Choices;
 use crate::app::user_action::UserAction;
 use serde::{Deserialize, Serialize};
 
@@ -28,6 +29,40 @@ pub struct Event {
     pub(crate) source: EventSource,
     pub(crate) postback: Option<Postback>,
     pub(crate) message: Option<MessageContent>,
+    pub(crate) joined: Option<Members>,
+    pub(crate) left: Option<Members>,
+    #[serde(rename(deserialize = "webhookEventId"))]
+    pub(crate) webhook_event_id: Option<String>,
+    #[serde(rename(deserialize = "deliveryContext"))]
+    pub(crate) delivery_context: Option<DeliveryContext>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DeliveryContext {
+    #[serde(rename(deserialize = "isRedelivery"))]
+    pub(crate) is_redelivery: bool,
+}
+
+impl Event {
+    pub(crate) fn is_redelivery(&self) -> bool {
+        self.delivery_context
+            .as_ref()
+            .is_some_and(|context| context.is_redelivery)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Members {
+    pub(crate) members: Vec<EventSource>,
+}
+
+impl Members {
+    pub(crate) fn user_ids(&self) -> Vec<String> {
+        self.members
+            .iter()
+            .filter_map(|member| member.user_id())
+            .collect()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -35,6 +70,10 @@ pub struct MessageContent {
     #[serde(rename(deserialize = "type", serialize = "type"))]
     pub(crate) message_type: String,
     pub(crate) text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) address: Option<String>,
     pub(crate) latitude: Option<f32>,
     pub(crate) longitude: Option<f32>,
     #[serde(rename(serialize = "quickReply"))]
@@ -65,14 +104,6 @@ pub struct QuickReplyAction {
     pub(crate) uri: Option<String>,
 }
 
-#[derive(Debug, Clone)]
-pub enum QuickReplyState {
-    Idle(Option<Coordinates>),
-    ActiveDraw(Option<Coordinates>),
-    NoShops(Meal),
-    NoShopsClosedBy(Meal, Coordinates),
-}
-
 const LOCATION_ICON_URL: &str = "https://cdn.iconscout.com/icon/free/png-256/pin-191-119557.png";
 
 impl MessageContent {
@@ -130,22 +161,57 @@ impl MessageContent {
         MessageContent {
             message_type: "text".to_string(),
             text: Some(message.to_string()),
+            title: None,
+            address: None,
             quick_replies: None,
             latitude: None,
             longitude: None,
         }
     }
 
+    pub(crate) fn location(title: &str, address: &str, latitude: f32, longitude: f32) -> Self {
+        MessageContent {
+            message_type: "location".to_string(),
+            text: None,
+            title: Some(title.to_string()),
+            address: Some(address.to_string()),
+            quick_replies: None,
+            latitude: Some(latitude),
+            longitude: Some(longitude),
+        }
+    }
+
     pub fn with_quick_replies(
         &mut self,
         client: &app::core::Client,
         host: &str,
-        quick_reply_state: QuickReplyState,
+        signer: &LinkSigner,
+        liff_id: Option<&str>,
+        choices: &Choices,
     ) -> MessageContent {
-        let replies = match quick_reply_state {
-            QuickReplyState::Idle(coordinates) => {
+        let items = MessageContent::quick_replies(client, host, signer, liff_id, choices);
+        self.quick_replies = Some(QuickReplyItems { items });
+        self.clone()
+    }
+
+    fn quick_replies(
+        client: &app::core::Client,
+        host: &str,
+        signer: &LinkSigner,
+        liff_id: Option<&str>,
+        choices: &Choices,
+    ) -> Vec<QuickReply> {
+        match choices.clone() {
+            Choices::Welcome => vec![
+                client.add_place_quick_reply(host, signer, liff_id),
+                MessageContent::postback_quick_reply(&UserAction::Seed, None),
+                MessageContent::postback_quick_reply(&UserAction::Draw(Meal::Lunch, None), None),
+                MessageContent::postback_quick_reply(&UserAction::Draw(Meal::Dinner, None), None),
+                MessageContent::location_quick_reply(),
+            ],
+            Choices::Idle(coordinates) => {
                 let mut base = vec![
-                    client.add_place_quick_reply(host),
+                    client.add_place_quick_reply(host, signer, liff_id),
                     MessageContent::postback_quick_reply(
                         &UserAction::Draw(Meal::Lunch, coordinates.clone()),
                         None,
@@ -161,8 +227,8 @@ impl MessageContent {
                 }
                 base
             }
-            QuickReplyState::ActiveDraw(coordinates) => vec![
-                client.add_place_quick_reply(host),
+            Choices::ActiveDraw(coordinates) => vec![
+                client.add_place_quick_reply(host, signer, liff_id),
                 // MessageContent::location_quick_reply("location", None),
                 MessageContent::postback_quick_reply(
                     &UserAction::ArchiveCurrent(coordinates.clone()),
@@ -174,57 +240,101 @@ impl MessageContent {
                 ),
                 MessageContent::postback_quick_reply(&UserAction::DeleteCurrent(coordinates), None),
             ],
-            QuickReplyState::NoShops(_) => vec![client.add_place_quick_reply(host)],
-            QuickReplyState::NoShopsClosedBy(_, _) => vec![
-                client.add_place_quick_reply(host),
+            Choices::NoShops(_) => vec![client.add_place_quick_reply(host, signer, liff_id)],
+            Choices::NoShopsClosedBy(_, _) => vec![
+                client.add_place_quick_reply(host, signer, liff_id),
                 MessageContent::location_quick_reply(),
                 MessageContent::clear_location_quick_reply(),
             ],
-        };
-        self.quick_replies = Some(QuickReplyItems { items: replies });
-        self.clone()
+            Choices::Candidates(_) => choices
+                .buttons()
+                .iter()
+                .map(|action| MessageContent::postback_quick_reply(action, None))
+                .collect(),
+            Choices::AwaitingLocation => vec![MessageContent::location_quick_reply()],
+            Choices::SharedPlace(name, coordinates) => {
+                let mut replies = vec![MessageContent::postback_quick_reply(
+                    &UserAction::AddAt(name, coordinates.clone()),
+                    None,
+                )];
+                replies.extend(MessageContent::quick_replies(
+                    client,
+                    host,
+                    signer,
+                    liff_id,
+                    &Choices::Idle(Some(coordinates)),
+                ));
+                replies
+            }
+            Choices::SavedLocations(names) => {
+                let mut replies = MessageContent::quick_replies(
+                    client,
+                    host,
+                    signer,
+                    liff_id,
+                    &Choices::Idle(None),
+                );
+                for name in names {
+                    for meal in [Meal::Lunch, Meal::Dinner] {
+                        replies.push(MessageContent::postback_quick_reply(
+                            &UserAction::DrawFrom(meal, name.clone()),
+                            None,
+                        ));
+                    }
+                }
+                replies
+            }
+        }
     }
 
-    pub(crate) fn error_message<E: Debug>(error: &E) -> MessageContent {
-        MessageContent {
-            message_type: "text".to_string(),
-            text: Some(format!("Error {error:?}")),
-            quick_replies: None,
-            latitude: None,
-            longitude: None,
-        }
+    pub(crate) fn error_message(error: &str) -> MessageContent {
+        MessageContent::text(&format!("Error {error}"))
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RichMenu {
-    #[allow(dead_code)]
     #[serde(skip_serializing)]
     #[serde(rename(deserialize = "richMenuId"))]
     id: Option<String>,
-    size: Size,
-    selected: bool,
-    name: String,
+    pub(crate) size: Size,
+    pub(crate) selected: bool,
+    pub(crate) name: String,
     #[serde(rename(serialize = "chatBarText", deserialize = "chatBarText"))]
-    chat_bar_text: String,
-    areas: Vec<Area>,
+    pub(crate) chat_bar_text: String,
+    pub(crate) areas: Vec<Area>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct Size {
-    width: i32,
-    height: i32,
+impl RichMenu {
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    // Compare the menu definitions, ignoring the server assigned id
+    pub(crate) fn same_layout(&self, other: &RichMenu) -> bool {
+        self.size == other.size
+            && self.selected == other.selected
+            && self.name == other.name
+            && self.chat_bar_text == other.chat_bar_text
+            && self.areas == other.areas
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct Bound {
-    x: i32,
-    y: i32,
-    width: i32,
-    height: i32,
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub(crate) struct Size {
+    pub(crate) width: i32,
+    pub(crate) height: i32,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub(crate) struct Bound {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) width: i32,
+    pub(crate) height: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[serde(tag = "type")]
 enum Action {
     #[serde(rename(serialize = "postback", deserialize = "postback"))]
@@ -233,9 +343,9 @@ enum Action {
     Uri { uri: String },
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct Area {
-    bounds: Bound,
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub(crate) struct Area {
+    pub(crate) bounds: Bound,
     action: Action,
 }
 
@@ -251,6 +361,19 @@ pub struct RichMenuId {
     pub rich_menu_id: String,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct RichMenuAlias {
+    #[serde(rename(deserialize = "richMenuAliasId"))]
+    pub alias: String,
+    #[serde(rename(deserialize = "richMenuId"))]
+    pub rich_menu_id: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RichMenuAliases {
+    pub aliases: Vec<RichMenuAlias>,
+}
+
 #[derive(Serialize)]
 pub struct WebHookPayload {
     pub endpoint: String,
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::signature;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::app::session;
+use crate::http::{ApiError, HttpClient};
+
+const LINE_JWKS_URL: &str = "https://api.line.me/oauth2/v2.1/certs";
+const LINE_ISSUER: &str = "https://access.line.me";
+// Line rotates its signing keys rarely; an unknown key id triggers a refresh anyway
+const JWKS_TTL_SECONDS: u64 = 24 * 60 * 60;
+// Unknown key ids come from any request, so they may not fetch the keys more often than this
+const JWKS_MIN_REFRESH_SECONDS: u64 = 60;
+
+#[derive(Debug)]
+pub enum IdTokenError {
+    Malformed,
+    UnsupportedAlgorithm(String),
+    UnknownKey,
+    InvalidSignature,
+    InvalidIssuer,
+    InvalidAudience,
+    Expired,
+    Jwks(ApiError),
+}
+
+impl Display for IdTokenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// Claims of a LIFF ID token once its signature is verified
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: u64,
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kid: String,
+    kty: String,
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+impl Jwk {
+    // Uncompressed P-256 point, as expected by ring
+    fn public_key(&self) -> Option<Vec<u8>> {
+        if self.kty != "EC" || self.crv.as_deref() != Some("P-256") {
+            return None;
+        }
+        let x = URL_SAFE_NO_PAD.decode(self.x.as_ref()?).ok()?;
+        let y = URL_SAFE_NO_PAD.decode(self.y.as_ref()?).ok()?;
+        Some([vec![0x04], x, y].concat())
+    }
+}
+
+type PublicKeys = HashMap<String, Vec<u8>>;
+
+struct KeyCache {
+    keys: PublicKeys,
+    fetched_at: u64,
+    // Last fetch, even a failed one
+    attempted_at: Option<u64>,
+}
+
+impl KeyCache {
+    fn needs_refresh(&self, kid: Option<&String>, now: u64) -> bool {
+        let throttled = self
+            .attempted_at
+            .is_some_and(|at| now < at + JWKS_MIN_REFRESH_SECONDS);
+        let stale = self.fetched_at + JWKS_TTL_SECONDS < now
+            || kid.is_some_and(|kid| !self.keys.contains_key(kid));
+        stale && !throttled
+    }
+}
+
+fn decode_part<T: DeserializeOwned>(part: &str) -> Result<T, IdTokenError> {
+    let json = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| IdTokenError::Malformed)?;
+    serde_json::from_slice(&json).map_err(|_| IdTokenError::Malformed)
+}
+
+fn key_id(token: &str) -> Result<Option<String>, IdTokenError> {
+    let header = token.split('.').next().ok_or(IdTokenError::Malformed)?;
+    Ok(decode_part::<Header>(header)?.kid)
+}
+
+/// Check an ES256 ID token against the Line public keys
+pub fn verify_id_token(
+    token: &str,
+    keys: &PublicKeys,
+    channel_id: &str,
+    now: u64,
+) -> Result<IdTokenClaims, IdTokenError> {
+    let parts: Vec<&str> = token.split('.').collect();
+    let [header, claims, signature] = parts[..] else {
+        return Err(IdTokenError::Malformed);
+    };
+    let header: Header = decode_part(header)?;
+    if header.alg != "ES256" {
+        return Err(IdTokenError::UnsupportedAlgorithm(header.alg));
+    }
+    let key = header
+        .kid
+        .and_then(|kid| keys.get(&kid))
+        .ok_or(IdTokenError::UnknownKey)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| IdTokenError::Malformed)?;
+    let signed = &token[..token.rfind('.').unwrap()];
+    signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, key)
+        .verify(signed.as_bytes(), &signature)
+        .map_err(|_| IdTokenError::InvalidSignature)?;
+
+    let claims: IdTokenClaims = decode_part(claims)?;
+    if claims.iss != LINE_ISSUER {
+        return Err(IdTokenError::InvalidIssuer);
+    }
+    if claims.aud != channel_id {
+        return Err(IdTokenError::InvalidAudience);
+    }
+    if claims.exp < now {
+        return Err(IdTokenError::Expired);
+    }
+    Ok(claims)
+}
+
+/// Verify LIFF ID tokens locally with a cached copy of the Line signing keys
+pub struct IdTokenVerifier {
+    channel_id: String,
+    jwks_url: String,
+    client: reqwest::Client,
+    keys: RwLock<KeyCache>,
+}
+
+impl IdTokenVerifier {
+    pub fn new(channel_id: &str) -> Self {
+        IdTokenVerifier::with_jwks_url(channel_id, LINE_JWKS_URL)
+    }
+
+    fn with_jwks_url(channel_id: &str, jwks_url: &str) -> Self {
+        IdTokenVerifier {
+            channel_id: channel_id.to_string(),
+            jwks_url: jwks_url.to_string(),
+            client: reqwest::Client::new(),
+            keys: RwLock::new(KeyCache {
+                keys: HashMap::new(),
+                fetched_at: 0,
+                attempted_at: None,
+            }),
+        }
+    }
+
+    async fn refresh_keys(&self, kid: Option<&String>, now: u64) -> Result<(), IdTokenError> {
+        let mut cache = self.keys.write().await;
+        // Another request may have refreshed them while this one waited for the lock
+        if !cache.needs_refresh(kid, now) {
+            return Ok(());
+        }
+        cache.attempted_at = Some(now);
+        let jwks: Jwks = self
+            .client
+            .make_json_request(|client| client.get(&self.jwks_url))
+            .await
+            .map_err(IdTokenError::Jwks)?;
+        cache.keys = jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| jwk.public_key().map(|key| (jwk.kid.clone(), key)))
+            .collect();
+        cache.fetched_at = now;
+        Ok(())
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<IdTokenClaims, IdTokenError> {
+        self.verify_at(token, session::now()).await
+    }
+
+    async fn verify_at(&self, token: &str, now: u64) -> Result<IdTokenClaims, IdTokenError> {
+        let kid = key_id(token)?;
+        let needs_refresh = self.keys.read().await.needs_refresh(kid.as_ref(), now);
+        if needs_refresh {
+            self.refresh_keys(kid.as_ref(), now).await?;
+        }
+        let cache = self.keys.read().await;
+        verify_id_token(token, &cache.keys, &self.channel_id, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use serde_json::json;
+    use warp::Filter;
+
+    use crate::line::liff::{verify_id_token, IdTokenError, IdTokenVerifier, PublicKeys};
+
+    fn key_pair() -> EcdsaKeyPair {
+        let pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+                .unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap()
+    }
+
+    fn id_token(key_pair: &EcdsaKeyPair, claims: serde_json::Value) -> String {
+        let header = json!({"typ": "JWT", "alg": "ES256", "kid": "k1"});
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let signature = key_pair
+            .sign(&SystemRandom::new(), signed.as_bytes())
+            .unwrap();
+        format!("{signed}.{}", URL_SAFE_NO_PAD.encode(signature.as_ref()))
+    }
+
+    fn claims(aud: &str, exp: u64) -> serde_json::Value {
+        json!({"iss": "https://access.line.me", "sub": "U1", "aud": aud, "exp": exp, "iat": 0})
+    }
+
+    fn keys(key_pair: &EcdsaKeyPair) -> PublicKeys {
+        HashMap::from([("k1".to_string(), key_pair.public_key().as_ref().to_vec())])
+    }
+
+    #[test]
+    fn it_verifies_id_tokens() {
+        let key_pair = key_pair();
+        let token = id_token(&key_pair, claims("1234", 100));
+        let claims = verify_id_token(&token, &keys(&key_pair), "1234", 50).unwrap();
+        assert_eq!(claims.sub, "U1");
+    }
+
+    #[test]
+    fn it_rejects_invalid_id_tokens() {
+        let key_pair = key_pair();
+        let keys = keys(&key_pair);
+        let token = id_token(&key_pair, claims("1234", 100));
+        assert!(matches!(
+            verify_id_token(&token, &keys, "5678", 50),
+            Err(IdTokenError::InvalidAudience)
+        ));
+        assert!(matches!(
+            verify_id_token(&token, &keys, "1234", 150),
+            Err(IdTokenError::Expired)
+        ));
+        let forged = id_token(&self::key_pair(), claims("1234", 100));
+        assert!(matches!(
+            verify_id_token(&forged, &keys, "1234", 50),
+            Err(IdTokenError::InvalidSignature)
+        ));
+        assert!(matches!(
+            verify_id_token(&token, &HashMap::new(), "1234", 50),
+            Err(IdTokenError::UnknownKey)
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_limits_the_key_refreshes_of_unknown_key_ids() {
+        let key_pair = key_pair();
+        let public_key = key_pair.public_key().as_ref();
+        let jwks = json!({"keys": [{
+            "kid": "k0",
+            "kty": "EC",
+            "crv": "P-256",
+            "x": URL_SAFE_NO_PAD.encode(&public_key[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&public_key[33..]),
+        }]});
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = fetches.clone();
+        let route = warp::path("certs").map(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            warp::reply::json(&jwks)
+        });
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let verifier = IdTokenVerifier::with_jwks_url("1234", &format!("http://{address}/certs"));
+        // Signed with the served key, but under a key id Line does not publish
+        let token = id_token(&key_pair, claims("1234", 2000));
+
+        for now in [1000, 1001, 1059] {
+            assert!(matches!(
+                verifier.verify_at(&token, now).await,
+                Err(IdTokenError::UnknownKey)
+            ));
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert!(verifier.verify_at(&token, 1060).await.is_err());
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,467 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use crate::app::address::Address;
+use crate::app::core::{Client, Meal};
+use crate::app::jar::Jar;
+use crate::app::link::{LinkPurpose, LinkSigner};
+use crate::app::manage;
+use crate::app::manage::{BulkAction, PlaceDetails, PlaceSort};
+use crate::app::session;
+use crate::gcp::api::FirebaseApi;
+use crate::line::form::{escape, TIME_SLOTS};
+use crate::line::html::html_file;
+
+// Dates are shown in Japan time
+const DISPLAY_UTC_OFFSET_SECONDS: u64 = 9 * 60 * 60;
+
+#[derive(Deserialize, Debug)]
+struct ListQuery {
+    token: String,
+    #[serde(default)]
+    q: String,
+    sort: Option<String>,
+    order: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EditQuery {
+    token: String,
+    key: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenQuery {
+    token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct EditEntry {
+    csrf: String,
+    key: String,
+    name: String,
+    time: String,
+    #[serde(default)]
+    tags: String,
+}
+
+/// Management pages of the jar a signed link was issued for
+#[allow(opaque_hidden_inferred_bound)]
+pub fn route<T>(
+    firebase_client: Arc<T>,
+    signer: Arc<LinkSigner>,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Sync + Send
+where
+    T: FirebaseApi + Send + Sync + 'static,
+{
+    let with_signer = warp::any().map(move || signer.clone());
+    let with_firebase = warp::any().map(move || firebase_client.clone());
+
+    let list = warp::path!("line" / "manage")
+        .and(warp::get())
+        .and(warp::query::<ListQuery>())
+        .and(with_signer.clone())
+        .and(with_firebase.clone())
+        .then(
+            |query: ListQuery, signer: Arc<LinkSigner>, firebase_client: Arc<T>| async move {
+                let Some(client) = verified_client(&signer, &query.token) else {
+                    return expired();
+                };
+                list_page(&client, firebase_client.as_ref(), &signer, &query, None).await
+            },
+        );
+    let bulk = warp::path!("line" / "manage")
+        .and(warp::post())
+        .and(warp::query::<TokenQuery>())
+        .and(warp::body::form::<Vec<(String, String)>>())
+        .and(with_signer.clone())
+        .and(with_firebase.clone())
+        .then(
+            |query: TokenQuery,
+             fields: Vec<(String, String)>,
+             signer: Arc<LinkSigner>,
+             firebase_client: Arc<T>| async move {
+                let Some(client) = verified_client(&signer, &query.token) else {
+                    return expired();
+                };
+                let field = |name: &str| {
+                    fields
+                        .iter()
+                        .find(|(key, _)| key == name)
+                        .map(|(_, value)| value.as_str())
+                };
+                if signer
+                    .verify_csrf(&query.token, field("csrf").unwrap_or_default())
+                    .is_err()
+                {
+                    return StatusCode::FORBIDDEN.into_response();
+                }
+                let selected: Vec<String> = fields
+                    .iter()
+                    .filter(|(key, _)| key == "selected")
+                    .map(|(_, value)| value.clone())
+                    .collect();
+                let notice = match bulk_action(field("action"), field("tag")) {
+                    None => "操作を選んでください".to_string(),
+                    Some(_) if selected.is_empty() => "店を選んでください".to_string(),
+                    Some(action) => {
+                        let jar: Jar = (&client).into();
+                        let updated = match manage::get_places(&jar, firebase_client.as_ref()).await
+                        {
+                            Ok(places) => {
+                                manage::apply_bulk_action(
+                                    &jar,
+                                    firebase_client.as_ref(),
+                                    &places,
+                                    &selected,
+                                    &action,
+                                )
+                                .await
+                            }
+                            Err(e) => Err(e),
+                        };
+                        match updated {
+                            Ok(updated) => format!("{updated}件の店を更新しました"),
+                            Err(e) => {
+                                println!("Could not apply {action:?} to {jar:?}: {e:?}");
+                                "更新できませんでした".to_string()
+                            }
+                        }
+                    }
+                };
+                let list_query = ListQuery {
+                    token: query.token,
+                    q: String::new(),
+                    sort: None,
+                    order: None,
+                };
+                list_page(
+                    &client,
+                    firebase_client.as_ref(),
+                    &signer,
+                    &list_query,
+                    Some(&notice),
+                )
+                .await
+            },
+        );
+    let edit = warp::path!("line" / "manage" / "edit")
+        .and(warp::get())
+        .and(warp::query::<EditQuery>())
+        .and(with_signer.clone())
+        .and(with_firebase.clone())
+        .then(
+            |query: EditQuery, signer: Arc<LinkSigner>, firebase_client: Arc<T>| async move {
+                let Some(client) = verified_client(&signer, &query.token) else {
+                    return expired();
+                };
+                let jar: Jar = (&client).into();
+                let places = manage::get_places(&jar, firebase_client.as_ref())
+                    .await
+                    .unwrap_or_default();
+                let Some(place) = places.iter().find(|place| place.key == query.key) else {
+                    return StatusCode::NOT_FOUND.into_response();
+                };
+                let csrf = signer.csrf_token(&query.token);
+                html_file("./resources/line/edit_place.html", |template| {
+                    render_edit(template, place, &query.token, &csrf)
+                })
+                .await
+            },
+        );
+    let save = warp::path!("line" / "manage" / "edit")
+        .and(warp::post())
+        .and(warp::query::<TokenQuery>())
+        .and(warp::body::form::<EditEntry>())
+        .and(with_signer)
+        .and(with_firebase)
+        .then(
+            |query: TokenQuery,
+             entry: EditEntry,
+             signer: Arc<LinkSigner>,
+             firebase_client: Arc<T>| async move {
+                let Some(client) = verified_client(&signer, &query.token) else {
+                    return expired();
+                };
+                if signer.verify_csrf(&query.token, &entry.csrf).is_err() {
+                    return StatusCode::FORBIDDEN.into_response();
+                }
+                let jar: Jar = (&client).into();
+                let places = manage::get_places(&jar, firebase_client.as_ref())
+                    .await
+                    .unwrap_or_default();
+                let Some(place) = places.iter().find(|place| place.key == entry.key) else {
+                    return StatusCode::NOT_FOUND.into_response();
+                };
+                let notice = match edited(place, &entry) {
+                    None => "名前といつ行くかを入力してください".to_string(),
+                    Some(place) => match firebase_client.update_place(&jar, &place).await {
+                        Ok(_) => format!("「{}」を更新しました", place.name),
+                        Err(e) => {
+                            println!("Could not update {place:?} in {jar:?}: {e:?}");
+                            "更新できませんでした".to_string()
+                        }
+                    },
+                };
+                let list_query = ListQuery {
+                    token: query.token,
+                    q: String::new(),
+                    sort: None,
+                    order: None,
+                };
+                list_page(
+                    &client,
+                    firebase_client.as_ref(),
+                    &signer,
+                    &list_query,
+                    Some(&notice),
+                )
+                .await
+            },
+        );
+
+    list.or(bulk).or(edit).or(save)
+}
+
+fn verified_client(signer: &LinkSigner, token: &str) -> Option<Client> {
+    match signer.verify(token, LinkPurpose::Manage, session::now()) {
+        Ok(link) => link.client(),
+        Err(e) => {
+            println!("Rejected management link: {e}");
+            None
+        }
+    }
+}
+
+fn expired() -> Response {
+    warp::reply::with_status(
+        "リンクの有効期限が切れました。もう一度「管理」と送ってください。",
+        StatusCode::FORBIDDEN,
+    )
+    .into_response()
+}
+
+fn bulk_action(action: Option<&str>, tag: Option<&str>) -> Option<BulkAction> {
+    let tag = tag.map(str::trim).filter(|tag| !tag.is_empty());
+    match action? {
+        "delete" => Some(BulkAction::Delete),
+        "add_tag" => tag.map(|tag| BulkAction::AddTag(tag.to_string())),
+        "remove_tag" => tag.map(|tag| BulkAction::RemoveTag(tag.to_string())),
+        slot => meals(slot).map(BulkAction::SetMeals),
+    }
+}
+
+fn meals(time: &str) -> Option<Vec<Meal>> {
+    TIME_SLOTS
+        .iter()
+        .find(|slot| slot.value == time)
+        .map(|slot| slot.meals.to_vec())
+}
+
+fn edited(place: &PlaceDetails, entry: &EditEntry) -> Option<PlaceDetails> {
+    let name = entry.name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    let mut tags: Vec<String> = vec![];
+    for tag in entry.tags.split([',', '、']).map(str::trim) {
+        if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    Some(PlaceDetails {
+        name: name.to_string(),
+        meals: meals(&entry.time)?,
+        tags,
+        ..place.clone()
+    })
+}
+
+async fn list_page<T: FirebaseApi + Sync>(
+    client: &Client,
+    firebase_client: &T,
+    signer: &LinkSigner,
+    query: &ListQuery,
+    notice: Option<&str>,
+) -> Response {
+    let jar: Jar = client.into();
+    let label = firebase_client.get_label(&jar).await.ok().flatten();
+    let places = match manage::get_places(&jar, firebase_client).await {
+        Ok(places) => places,
+        Err(e) => {
+            println!("Could not get the places of {jar:?}: {e:?}");
+            return StatusCode::BAD_GATEWAY.into_response();
+        }
+    };
+    let total = places.len();
+    let sort = query
+        .sort
+        .as_deref()
+        .and_then(PlaceSort::parse)
+        .unwrap_or(PlaceSort::Name);
+    let descending = query.order.as_deref() == Some("desc");
+    let places = manage::search(places, &query.q, sort, descending);
+    let csrf = signer.csrf_token(&query.token);
+    html_file("./resources/line/manage.html", |template| {
+        let sort_link = |column: PlaceSort, value: &str, title: &str| {
+            let order = if column == sort && !descending {
+                "desc"
+            } else {
+                "asc"
+            };
+            let link = page_link(
+                "/line/manage",
+                &[
+                    ("token", &query.token),
+                    ("q", &query.q),
+                    ("sort", value),
+                    ("order", order),
+                ],
+            );
+            format!("<a href=\"{}\">{title}</a>", escape(&link))
+        };
+        let rows: String = places
+            .iter()
+            .map(|place| render_row(place, &query.token))
+            .collect();
+        template
+            .replace("{{label}}", &escape(label.as_deref().unwrap_or("お店")))
+            .replace("{{notice}}", &escape(notice.unwrap_or_default()))
+            .replace("{{token}}", &escape(&query.token))
+            .replace("{{csrf}}", &csrf)
+            .replace("{{q}}", &escape(&query.q))
+            .replace("{{count}}", &format!("{} / {total}", places.len()))
+            .replace("{{sort_name}}", &sort_link(PlaceSort::Name, "name", "名前"))
+            .replace(
+                "{{sort_meals}}",
+                &sort_link(PlaceSort::Meals, "meals", "いつ"),
+            )
+            .replace(
+                "{{sort_last_visit}}",
+                &sort_link(PlaceSort::LastVisit, "last_visit", "最後に行った日"),
+            )
+            .replace("{{rows}}", &rows)
+    })
+    .await
+}
+
+fn render_row(place: &PlaceDetails, token: &str) -> String {
+    let meals: Vec<&str> = place.meals.iter().map(Meal::serialized).collect();
+    // The address, when known, links to the map instead of a bare 地図
+    let address = place
+        .address
+        .as_ref()
+        .and_then(Address::short)
+        .map(|address| escape(&address));
+    let coordinates = match (&place.coordinates, address) {
+        (Some(c), address) => format!(
+            "<a href=\"https://www.google.com/maps/search/?api=1&amp;query={},{}\">{}</a>",
+            c.latitude,
+            c.longitude,
+            address.unwrap_or("地図".to_string())
+        ),
+        (None, address) => address.unwrap_or("-".to_string()),
+    };
+    let edit = page_link(
+        "/line/manage/edit",
+        &[("token", token), ("key", place.key.as_str())],
+    );
+    format!(
+        "<tr><td><input type=\"checkbox\" name=\"selected\" value=\"{}\"/></td>\
+         <td><a href=\"{}\">{}</a></td><td>{}</td><td>{coordinates}</td><td>{}</td><td>{}</td></tr>\n",
+        escape(&place.key),
+        escape(&edit),
+        escape(&place.name),
+        meals.join(" "),
+        escape(&place.tags.join(", ")),
+        place.last_visit.map(format_date).unwrap_or("-".to_string()),
+    )
+}
+
+fn render_edit(template: &str, place: &PlaceDetails, token: &str, csrf: &str) -> String {
+    let time_slots: String = TIME_SLOTS
+        .iter()
+        .map(|slot| {
+            let checked = if slot.meals == place.meals.as_slice() {
+                " checked=\"checked\""
+            } else {
+                ""
+            };
+            format!(
+                "<label><input type=\"radio\" name=\"time\" value=\"{}\"{checked}/>{}</label>\n",
+                slot.value, slot.label
+            )
+        })
+        .collect();
+    template
+        .replace("{{token}}", &escape(token))
+        .replace("{{csrf}}", csrf)
+        .replace("{{key}}", &escape(&place.key))
+        .replace("{{name}}", &escape(&place.name))
+        .replace("{{tags}}", &escape(&place.tags.join(", ")))
+        .replace("{{time_slots}}", &time_slots)
+}
+
+// Relative link with url encoded query parameters
+fn page_link(path: &str, params: &[(&str, &str)]) -> String {
+    let url = reqwest::Url::parse_with_params("https://localhost", params).unwrap();
+    format!("{path}?{}", url.query().unwrap_or_default())
+}
+
+// Civil date from a unix timestamp, see http://howardhinnant.github.io/date_algorithms.html
+fn format_date(timestamp: u64) -> String {
+    let days = ((timestamp + DISPLAY_UTC_OFFSET_SECONDS) / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::core::Meal;
+    use crate::app::manage::BulkAction;
+    use crate::line::manage::{bulk_action, format_date, page_link};
+
+    #[test]
+    fn it_formats_dates_in_japan_time() {
+        assert_eq!(format_date(0), "1970-01-01");
+        // 2024-02-29T20:00:00Z
+        assert_eq!(format_date(1709236800), "2024-03-01");
+    }
+
+    #[test]
+    fn it_parses_bulk_actions() {
+        assert_eq!(
+            bulk_action(Some("lunch"), None),
+            Some(BulkAction::SetMeals(vec![Meal::Lunch]))
+        );
+        assert_eq!(
+            bulk_action(Some("add_tag"), Some(" ramen ")),
+            Some(BulkAction::AddTag("ramen".to_string()))
+        );
+        assert_eq!(bulk_action(Some("add_tag"), Some(" ")), None);
+    }
+
+    #[test]
+    fn it_encodes_page_links() {
+        assert_eq!(
+            page_link("/line/manage", &[("token", "a.b"), ("q", "ラ メン&")]),
+            "/line/manage?token=a.b&q=%E3%83%A9+%E3%83%A1%E3%83%B3%26"
+        );
+    }
+}
@@ -0,0 +1,678 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::digest;
+use serde::Deserialize;
+
+use crate::app::response::Choices;
+use crate::http::HttpResult;
+use crate::line::api::LineApi;
+use crate::line::json::{RichMenu, RichMenuAlias};
+
+// https://developers.line.biz/en/reference/messaging-api/#upload-rich-menu-image-requirements
+const MIN_IMAGE_WIDTH: i32 = 800;
+const MAX_IMAGE_WIDTH: i32 = 2500;
+const MIN_IMAGE_HEIGHT: i32 = 250;
+const MIN_ASPECT_RATIO: f32 = 1.45;
+const MAX_IMAGE_BYTES: usize = 1024 * 1024;
+const MAX_AREAS: usize = 20;
+const MAX_CHAT_BAR_TEXT_LENGTH: usize = 14;
+const MAX_NAME_LENGTH: usize = 300;
+// Images cannot be read back from Line, so the published menus carry a hash of theirs in their
+// name; changing only the image then creates a new menu
+const IMAGE_HASH_SEPARATOR: &str = " #";
+const IMAGE_HASH_BYTES: usize = 12;
+
+// Aliases of the menus declared in the manifest which follow the chat state
+pub(crate) const IDLE_MENU_ALIAS: &str = "idle";
+pub(crate) const ACTIVE_DRAW_MENU_ALIAS: &str = "active_draw";
+pub(crate) const NO_SHOPS_MENU_ALIAS: &str = "no_shops";
+pub(crate) const LOCATION_MENU_ALIAS: &str = "location";
+
+impl Choices {
+    pub(crate) fn menu_alias(&self) -> &'static str {
+        match self {
+            Choices::Welcome => IDLE_MENU_ALIAS,
+            Choices::Idle(None) | Choices::SavedLocations(_) => IDLE_MENU_ALIAS,
+            Choices::Idle(Some(_)) | Choices::SharedPlace(_, _) => LOCATION_MENU_ALIAS,
+            Choices::ActiveDraw(_) => ACTIVE_DRAW_MENU_ALIAS,
+            Choices::NoShops(_) => NO_SHOPS_MENU_ALIAS,
+            Choices::NoShopsClosedBy(_, _) => NO_SHOPS_MENU_ALIAS,
+            Choices::Candidates(_) | Choices::AwaitingLocation => IDLE_MENU_ALIAS,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MenuManifest {
+    menus: Vec<MenuEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MenuEntry {
+    alias: String,
+    layout: PathBuf,
+    image: PathBuf,
+    #[serde(default)]
+    default: bool,
+}
+
+#[derive(Debug)]
+pub struct LocalMenu {
+    pub alias: String,
+    pub menu: RichMenu,
+    pub image: Vec<u8>,
+    pub default: bool,
+}
+
+#[derive(Debug)]
+pub struct RemoteMenus {
+    pub menus: Vec<RichMenu>,
+    pub aliases: Vec<RichMenuAlias>,
+    pub default_menu: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PlanStep {
+    Keep { alias: String, menu_id: String },
+    Create { alias: String },
+    CreateAlias { alias: String },
+    RelinkAlias { alias: String, from: String },
+    DeleteAlias { alias: String },
+    SetDefault { alias: String },
+    Delete { menu_id: String, name: String },
+}
+
+#[derive(Debug)]
+pub enum MenuError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Json {
+        path: PathBuf,
+        error: serde_json::Error,
+    },
+    Invalid {
+        alias: String,
+        message: String,
+    },
+}
+
+impl Display for MenuError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MenuError::Io { path, error } => write!(f, "Could not read {path:?}: {error}"),
+            MenuError::Json { path, error } => write!(f, "Could not parse {path:?}: {error}"),
+            MenuError::Invalid { alias, message } => write!(f, "Invalid menu {alias}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for MenuError {}
+
+impl Display for PlanStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlanStep::Keep { alias, menu_id } => write!(f, "  keep          {alias} ({menu_id})"),
+            PlanStep::Create { alias } => write!(f, "+ create        {alias}"),
+            PlanStep::CreateAlias { alias } => write!(f, "+ create alias  {alias}"),
+            PlanStep::RelinkAlias { alias, from } => {
+                write!(f, "~ relink alias  {alias} (was {from})")
+            }
+            PlanStep::DeleteAlias { alias } => write!(f, "- delete alias  {alias}"),
+            PlanStep::SetDefault { alias } => write!(f, "~ set default   {alias}"),
+            PlanStep::Delete { menu_id, name } => write!(f, "- delete        {name} ({menu_id})"),
+        }
+    }
+}
+
+/// Load the menus listed in a manifest file; layout and image paths are relative to the manifest
+pub fn load_manifest(path: &Path) -> Result<Vec<LocalMenu>, MenuError> {
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+    let manifest: MenuManifest = read_json(path)?;
+    let menus = manifest
+        .menus
+        .into_iter()
+        .map(|entry| {
+            let layout_path = base.join(&entry.layout);
+            let image_path = base.join(&entry.image);
+            let image = std::fs::read(&image_path).map_err(|error| MenuError::Io {
+                path: image_path,
+                error,
+            })?;
+            Ok(LocalMenu {
+                alias: entry.alias,
+                menu: read_json(&layout_path)?,
+                image,
+                default: entry.default,
+            })
+        })
+        .collect::<Result<Vec<_>, MenuError>>()?;
+
+    if menus.iter().filter(|m| m.default).count() > 1 {
+        return Err(MenuError::Invalid {
+            alias: "*".to_string(),
+            message: "Only one menu can be the default one".to_string(),
+        });
+    }
+    for menu in menus.iter() {
+        menu.validate()?;
+    }
+    Ok(menus)
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, MenuError> {
+    let content = std::fs::read_to_string(path).map_err(|error| MenuError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    serde_json::from_str(&content).map_err(|error| MenuError::Json {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+impl LocalMenu {
+    pub fn validate(&self) -> Result<(), MenuError> {
+        let invalid = |message: String| MenuError::Invalid {
+            alias: self.alias.clone(),
+            message,
+        };
+        let size = &self.menu.size;
+        if !(MIN_IMAGE_WIDTH..=MAX_IMAGE_WIDTH).contains(&size.width) {
+            return Err(invalid(format!(
+                "Width {} should be between {MIN_IMAGE_WIDTH} and {MAX_IMAGE_WIDTH}",
+                size.width
+            )));
+        }
+        if size.height < MIN_IMAGE_HEIGHT {
+            return Err(invalid(format!(
+                "Height {} should be at least {MIN_IMAGE_HEIGHT}",
+                size.height
+            )));
+        }
+        if (size.width as f32 / size.height as f32) < MIN_ASPECT_RATIO {
+            return Err(invalid(format!(
+                "Aspect ratio of {}x{} should be at least {MIN_ASPECT_RATIO}",
+                size.width, size.height
+            )));
+        }
+        if self.menu.chat_bar_text.chars().count() > MAX_CHAT_BAR_TEXT_LENGTH {
+            return Err(invalid(format!(
+                "Chat bar text should be at most {MAX_CHAT_BAR_TEXT_LENGTH} characters"
+            )));
+        }
+        if self.published().name.chars().count() > MAX_NAME_LENGTH {
+            return Err(invalid(format!(
+                "Name with its image hash should be at most {MAX_NAME_LENGTH} characters"
+            )));
+        }
+        if self.menu.areas.len() > MAX_AREAS {
+            return Err(invalid(format!("At most {MAX_AREAS} areas are allowed")));
+        }
+        for (index, area) in self.menu.areas.iter().enumerate() {
+            let b = &area.bounds;
+            if b.x < 0
+                || b.y < 0
+                || b.width <= 0
+                || b.height <= 0
+                || b.x + b.width > size.width
+                || b.y + b.height > size.height
+            {
+                return Err(invalid(format!(
+                    "Area {index} ({},{} {}x{}) is out of the {}x{} menu",
+                    b.x, b.y, b.width, b.height, size.width, size.height
+                )));
+            }
+        }
+
+        if self.image.len() > MAX_IMAGE_BYTES {
+            return Err(invalid(format!(
+                "Image is {} bytes, max is {MAX_IMAGE_BYTES}",
+                self.image.len()
+            )));
+        }
+        let (width, height) = image_size(&self.image)
+            .ok_or_else(|| invalid("Image should be a JPEG or PNG file".to_string()))?;
+        if (width, height) != (size.width, size.height) {
+            return Err(invalid(format!(
+                "Image is {width}x{height} but the menu is {}x{}",
+                size.width, size.height
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl LocalMenu {
+    /// Layout sent to Line, named after its image
+    pub fn published(&self) -> RichMenu {
+        let hash = digest::digest(&digest::SHA256, &self.image);
+        let mut menu = self.menu.clone();
+        menu.name = format!(
+            "{}{IMAGE_HASH_SEPARATOR}{}",
+            menu.name,
+            URL_SAFE_NO_PAD.encode(&hash.as_ref()[..IMAGE_HASH_BYTES])
+        );
+        menu
+    }
+}
+
+pub(crate) fn image_content_type(image: &[u8]) -> &'static str {
+    if image.starts_with(PNG_SIGNATURE) {
+        "image/png"
+    } else {
+        "image/jpeg"
+    }
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// Read the image dimensions from the PNG IHDR chunk or the JPEG start of frame segment
+fn image_size(image: &[u8]) -> Option<(i32, i32)> {
+    let read_u16 = |at: usize| -> Option<i32> {
+        image
+            .get(at..at + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as i32)
+    };
+    if image.starts_with(PNG_SIGNATURE) {
+        let read_u32 = |at: usize| -> Option<i32> {
+            image
+                .get(at..at + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as i32)
+        };
+        return Some((read_u32(16)?, read_u32(20)?));
+    }
+    if !image.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut offset = 2;
+    while offset + 4 <= image.len() {
+        if image[offset] != 0xFF {
+            return None;
+        }
+        let marker = image[offset + 1];
+        let length = read_u16(offset + 2)? as usize;
+        let is_start_of_frame =
+            (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker);
+        if is_start_of_frame {
+            return Some((read_u16(offset + 7)?, read_u16(offset + 5)?));
+        }
+        offset += 2 + length;
+    }
+    None
+}
+
+pub async fn get_remote_menus<T: LineApi + Sync>(client: &T) -> HttpResult<RemoteMenus> {
+    let menus = client.get_rich_menus().await?;
+    let aliases = client.get_rich_menu_aliases().await?;
+    // The API answers 404 when no default menu is set
+    let default_menu = client.get_default_menu(None).await.ok();
+    Ok(RemoteMenus {
+        menus,
+        aliases,
+        default_menu,
+    })
+}
+
+/// Compute the steps converging the remote menus toward the local ones.
+/// Menus are matched through their alias; any remote menu not targeted by a local alias is deleted.
+pub fn plan(local: &[LocalMenu], remote: &RemoteMenus) -> Vec<PlanStep> {
+    let remote_aliases: HashMap<&str, &str> = remote
+        .aliases
+        .iter()
+        .map(|a| (a.alias.as_str(), a.rich_menu_id.as_str()))
+        .collect();
+    let mut kept_ids: Vec<&str> = vec![];
+    let mut steps = vec![];
+
+    for menu in local.iter() {
+        let alias = menu.alias.clone();
+        let existing = remote_aliases.get(menu.alias.as_str()).and_then(|id| {
+            remote
+                .menus
+                .iter()
+                .find(|m| m.id() == Some(*id) && m.same_layout(&menu.published()))
+        });
+        match existing {
+            Some(existing) => {
+                let menu_id = existing.id().unwrap_or_default();
+                kept_ids.push(menu_id);
+                steps.push(PlanStep::Keep {
+                    alias: alias.clone(),
+                    menu_id: menu_id.to_string(),
+                });
+                if menu.default && remote.default_menu.as_deref() != Some(menu_id) {
+                    steps.push(PlanStep::SetDefault { alias });
+                }
+            }
+            None => {
+                steps.push(PlanStep::Create {
+                    alias: alias.clone(),
+                });
+                match remote_aliases.get(menu.alias.as_str()) {
+                    Some(from) => steps.push(PlanStep::RelinkAlias {
+                        alias: alias.clone(),
+                        from: from.to_string(),
+                    }),
+                    None => steps.push(PlanStep::CreateAlias {
+                        alias: alias.clone(),
+                    }),
+                }
+                if menu.default {
+                    steps.push(PlanStep::SetDefault { alias });
+                }
+            }
+        }
+    }
+
+    for alias in remote.aliases.iter() {
+        if !local.iter().any(|m| m.alias == alias.alias) {
+            steps.push(PlanStep::DeleteAlias {
+                alias: alias.alias.clone(),
+            });
+        }
+    }
+
+    for menu in remote.menus.iter() {
+        if let Some(id) = menu.id() {
+            if !kept_ids.contains(&id) {
+                steps.push(PlanStep::Delete {
+                    menu_id: id.to_string(),
+                    name: menu.name.clone(),
+                });
+            }
+        }
+    }
+    steps
+}
+
+/// Run the plan steps in order; menus are created before aliases are (re)linked so that
+/// users are never left without a menu.
+pub async fn apply<T: LineApi + Sync>(
+    client: &T,
+    local: &[LocalMenu],
+    steps: &[PlanStep],
+) -> HttpResult<()> {
+    let mut ids: HashMap<&str, String> = HashMap::new();
+    for step in steps.iter() {
+        match step {
+            PlanStep::Keep { alias, menu_id } => {
+                ids.insert(alias, menu_id.to_string());
+            }
+            PlanStep::Create { alias } => {
+                let menu = local_menu(local, alias);
+                let menu_id = client
+                    .create_rich_menu(&menu.published(), menu.image.clone())
+                    .await?;
+                ids.insert(alias, menu_id);
+            }
+            PlanStep::CreateAlias { alias } => {
+                client
+                    .set_rich_menu_alias(&ids[alias.as_str()], alias)
+                    .await?;
+            }
+            PlanStep::RelinkAlias { alias, .. } => {
+                client
+                    .update_rich_menu_alias(&ids[alias.as_str()], alias)
+                    .await?;
+            }
+            PlanStep::DeleteAlias { alias } => {
+                client.delete_rich_menu_alias(alias).await?;
+            }
+            PlanStep::SetDefault { alias } => {
+                client.set_rich_menu(&ids[alias.as_str()], None).await?;
+            }
+            PlanStep::Delete { menu_id, .. } => {
+                client.delete_rich_menu(menu_id).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn local_menu<'a>(local: &'a [LocalMenu], alias: &str) -> &'a LocalMenu {
+    local
+        .iter()
+        .find(|m| m.alias == alias)
+        .expect("Plan steps should only reference local menus")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::coordinates::Coordinates;
+    use crate::app::core::Meal;
+    use crate::app::response::Choices;
+    use crate::line::json::fixtures::rich_menu_fixture;
+    use crate::line::json::RichMenuAlias;
+    use crate::line::menu::{
+        load_manifest, plan, LocalMenu, PlanStep, RemoteMenus, ACTIVE_DRAW_MENU_ALIAS,
+        IDLE_MENU_ALIAS, LOCATION_MENU_ALIAS, NO_SHOPS_MENU_ALIAS,
+    };
+    use std::path::Path;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut image = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        image.extend_from_slice(&width.to_be_bytes());
+        image.extend_from_slice(&height.to_be_bytes());
+        image
+    }
+
+    fn local(alias: &str, default: bool) -> LocalMenu {
+        LocalMenu {
+            alias: alias.to_string(),
+            menu: rich_menu_fixture(),
+            image: png(2500, 843),
+            default,
+        }
+    }
+
+    fn remote(id: &str, name: &str) -> crate::line::json::RichMenu {
+        let mut json = serde_json::to_value(rich_menu_fixture()).unwrap();
+        json["richMenuId"] = serde_json::Value::from(id);
+        json["name"] = serde_json::Value::from(name);
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn it_loads_the_bundled_menus() {
+        let menus = load_manifest(Path::new("./src/menus/menus.json")).unwrap();
+        assert_eq!(menus.iter().filter(|m| m.default).count(), 1);
+        for alias in [
+            IDLE_MENU_ALIAS,
+            ACTIVE_DRAW_MENU_ALIAS,
+            NO_SHOPS_MENU_ALIAS,
+            LOCATION_MENU_ALIAS,
+        ] {
+            assert!(menus.iter().any(|m| m.alias == alias), "Missing {alias}");
+        }
+    }
+
+    #[test]
+    fn it_validates_image_size_against_the_menu() {
+        assert!(local("idle", false).validate().is_ok());
+
+        let mut menu = local("idle", false);
+        menu.image = png(800, 270);
+        assert!(menu.validate().is_err());
+
+        menu.image = b"GIF89a".to_vec();
+        assert!(menu.validate().is_err());
+    }
+
+    #[test]
+    fn it_validates_area_bounds() {
+        let mut menu = local("idle", false);
+        menu.menu = serde_json::from_value(serde_json::json!({
+            "size": {"width": 2500, "height": 843},
+            "selected": false,
+            "name": "test_menu",
+            "chatBarText": "chat bar text",
+            "areas": [{
+                "bounds": {"x": 1666, "y": 0, "width": 835, "height": 843},
+                "action": {"type": "postback", "data": "dinner_action"}
+            }]
+        }))
+        .unwrap();
+        assert!(menu.validate().is_err());
+    }
+
+    #[test]
+    fn it_plans_creation_from_scratch() {
+        let remote = RemoteMenus {
+            menus: vec![],
+            aliases: vec![],
+            default_menu: None,
+        };
+        assert_eq!(
+            plan(&[local("idle", true)], &remote),
+            vec![
+                PlanStep::Create {
+                    alias: "idle".to_string()
+                },
+                PlanStep::CreateAlias {
+                    alias: "idle".to_string()
+                },
+                PlanStep::SetDefault {
+                    alias: "idle".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_keeps_unchanged_menus_and_removes_stale_ones() {
+        let published = local("idle", true).published().name;
+        assert!(published.starts_with("test_menu #"));
+        let remote = RemoteMenus {
+            menus: vec![remote("menu-1", &published), remote("menu-2", "old")],
+            aliases: vec![
+                RichMenuAlias {
+                    alias: "idle".to_string(),
+                    rich_menu_id: "menu-1".to_string(),
+                },
+                RichMenuAlias {
+                    alias: "legacy".to_string(),
+                    rich_menu_id: "menu-2".to_string(),
+                },
+            ],
+            default_menu: Some("menu-1".to_string()),
+        };
+        assert_eq!(
+            plan(&[local("idle", true)], &remote),
+            vec![
+                PlanStep::Keep {
+                    alias: "idle".to_string(),
+                    menu_id: "menu-1".to_string()
+                },
+                PlanStep::DeleteAlias {
+                    alias: "legacy".to_string()
+                },
+                PlanStep::Delete {
+                    menu_id: "menu-2".to_string(),
+                    name: "old".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_relinks_aliases_of_changed_menus() {
+        let remote = RemoteMenus {
+            menus: vec![remote("menu-1", "previous layout")],
+            aliases: vec![RichMenuAlias {
+                alias: "idle".to_string(),
+                rich_menu_id: "menu-1".to_string(),
+            }],
+            default_menu: None,
+        };
+        assert_eq!(
+            plan(&[local("idle", false)], &remote),
+            vec![
+                PlanStep::Create {
+                    alias: "idle".to_string()
+                },
+                PlanStep::RelinkAlias {
+                    alias: "idle".to_string(),
+                    from: "menu-1".to_string()
+                },
+                PlanStep::Delete {
+                    menu_id: "menu-1".to_string(),
+                    name: "previous layout".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_recreates_menus_whose_image_changed() {
+        let published = local("idle", false).published().name;
+        let remote = RemoteMenus {
+            menus: vec![remote("menu-1", &published)],
+            aliases: vec![RichMenuAlias {
+                alias: "idle".to_string(),
+                rich_menu_id: "menu-1".to_string(),
+            }],
+            default_menu: None,
+        };
+        // Same layout and size, other pixels
+        let mut menu = local("idle", false);
+        menu.image.extend_from_slice(b"\x00\x00\x00\x00IDAT");
+        assert!(menu.validate().is_ok());
+        assert_eq!(
+            plan(&[menu], &remote),
+            vec![
+                PlanStep::Create {
+                    alias: "idle".to_string()
+                },
+                PlanStep::RelinkAlias {
+                    alias: "idle".to_string(),
+                    from: "menu-1".to_string()
+                },
+                PlanStep::Delete {
+                    menu_id: "menu-1".to_string(),
+                    name: published
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_maps_choices_to_menu_aliases() {
+        let origin = Coordinates {
+            latitude: 35.5,
+            longitude: 139.5,
+        };
+        for (choices, alias) in [
+            (Choices::Welcome, IDLE_MENU_ALIAS),
+            (Choices::Idle(None), IDLE_MENU_ALIAS),
+            (
+                Choices::SavedLocations(vec!["オフィス".to_string()]),
+                IDLE_MENU_ALIAS,
+            ),
+            (Choices::Idle(Some(origin.clone())), LOCATION_MENU_ALIAS),
+            (
+                Choices::SharedPlace("一蘭".to_string(), origin.clone()),
+                LOCATION_MENU_ALIAS,
+            ),
+            (Choices::ActiveDraw(None), ACTIVE_DRAW_MENU_ALIAS),
+            (
+                Choices::ActiveDraw(Some(origin.clone())),
+                ACTIVE_DRAW_MENU_ALIAS,
+            ),
+            (Choices::NoShops(Meal::Lunch), NO_SHOPS_MENU_ALIAS),
+            (
+                Choices::NoShopsClosedBy(Meal::Dinner, origin),
+                NO_SHOPS_MENU_ALIAS,
+            ),
+            (Choices::Candidates(2), IDLE_MENU_ALIAS),
+            (Choices::AwaitingLocation, IDLE_MENU_ALIAS),
+        ] {
+            assert_eq!(choices.menu_alias(), alias, "{choices:?}");
+        }
+    }
+}
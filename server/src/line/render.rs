@@ -0,0 +1,239 @@
+use async_trait::async_trait;
+
+use crate::app::core::Client;
+use crate::app::link::{page_url, LinkPurpose, LinkSigner};
+use crate::app::response::{Audience, Choices, Messenger, Reply, Response};
+use crate::http::{Empty, HttpResult};
+use crate::line::api::LineApi;
+use crate::line::http::{LineChannel, LineClient};
+use crate::line::json::{MessageContent, QuickReply};
+
+impl Client {
+    pub(crate) fn add_place_quick_reply(
+        &self,
+        host: &str,
+        signer: &LinkSigner,
+        liff_id: Option<&str>,
+    ) -> QuickReply {
+        let token = self.signed_link(signer, LinkPurpose::AddPlace);
+        // The LIFF page identifies who adds the place; the plain form is kept as a fallback
+        let uri = match liff_id {
+            Some(liff_id) => format!("https://liff.line.me/{liff_id}?token={token}"),
+            None => page_url(host, &format!("/line/draw?source=line&token={token}")),
+        };
+        MessageContent::uri_quick_reply("+ 加", &uri, None)
+    }
+}
+
+pub(crate) fn render(
+    client: &Client,
+    host: &str,
+    signer: &LinkSigner,
+    liff_id: Option<&str>,
+    reply: &Reply,
+) -> MessageContent {
+    match reply {
+        Reply::Text(text) => MessageContent::text(text),
+        Reply::Choices(text, choices) => {
+            MessageContent::text(text).with_quick_replies(client, host, signer, liff_id, choices)
+        }
+        Reply::Location { title, coordinates } => MessageContent::location(
+            title,
+            &format!("{},{}", coordinates.latitude, coordinates.longitude),
+            coordinates.latitude,
+            coordinates.longitude,
+        ),
+        Reply::Error(error) => MessageContent::error_message(error),
+    }
+}
+
+impl LineClient {
+    // Rich menus are only displayed in one-on-one chats; keep the core actions reachable there
+    // even when Line drops the quick replies
+    async fn switch_rich_menu(&self, client: &Client, choices: &Choices) {
+        let Client::Line(LineChannel::User(user_id)) = client else {
+            return;
+        };
+        let alias = choices.menu_alias();
+        // Most replies keep the state of the chat, and so its menu
+        if self.linked_menu(user_id) == Some(alias) {
+            return;
+        }
+        match self.set_rich_menu_from_alias(alias, Some(user_id)).await {
+            Ok(_) => self.set_linked_menu(user_id, alias),
+            Err(e) => println!("Could not switch rich menu for {user_id}: {e:?}"),
+        }
+    }
+
+    async fn send_to_single_user(
+        &self,
+        line: &Client,
+        message: MessageContent,
+    ) -> HttpResult<Empty> {
+        let to = line.user_id();
+
+        match to {
+            None => {
+                println!("Could not send to a single user for {line:?}");
+                Ok(Empty {})
+            }
+            Some(user_id) => self.send_to(user_id, message).await,
+        }
+    }
+
+    async fn send_to_all_users(
+        &self,
+        channel: &LineChannel,
+        message: MessageContent,
+    ) -> HttpResult<Empty> {
+        let to = match channel {
+            LineChannel::User(id) => id,
+            LineChannel::Room { id, .. } => id,
+            LineChannel::Group { id, .. } => id,
+        };
+        self.send_to(to, message).await
+    }
+}
+
+#[async_trait]
+impl Messenger for LineClient {
+    async fn respond(&self, client: &Client, host: &str, response: Response) {
+        let Client::Line(channel) = client else {
+            println!("Cannot answer {client:?} on Line");
+            return;
+        };
+        let Some(signer) = self.link_signer() else {
+            println!("Cannot answer {client:?} without a link signer");
+            return;
+        };
+        for reply in &response.replies {
+            if let Reply::Choices(_, choices) = reply {
+                self.switch_rich_menu(client, choices).await;
+            }
+            let message = render(client, host, signer, self.liff_id(), reply);
+            let sent = match response.audience {
+                Audience::Chat => self.send_to_all_users(channel, message).await,
+                Audience::Requester => self.send_to_single_user(client, message).await,
+            };
+            if let Err(e) = sent {
+                println!("Could not send {reply:?} to {client:?}: {e:?}");
+            }
+        }
+    }
+
+    async fn chat_name(&self, client: &Client) -> HttpResult<String> {
+        self.get_jar_info(&client.into()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+    use warp::Filter;
+
+    use crate::app::coordinates::Coordinates;
+    use crate::app::core::Client;
+    use crate::app::link::LinkSigner;
+    use crate::app::response::{Choices, Messenger, Reply, Response};
+    use crate::line::http::{LineChannel, LineClient};
+    use crate::line::render::render;
+
+    #[test]
+    fn it_renders_replies_as_line_messages() {
+        let signer = LinkSigner::new(b"secret");
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        let message = render(
+            &client,
+            "example.com",
+            &signer,
+            None,
+            &Reply::Choices("「一蘭」が出ました".to_string(), Choices::ActiveDraw(None)),
+        );
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["type"], "text");
+        assert_eq!(json["quickReply"]["items"].as_array().unwrap().len(), 4);
+        let uri = json["quickReply"]["items"][0]["action"]["uri"]
+            .as_str()
+            .unwrap();
+        assert!(uri.starts_with("https://example.com/line/draw?source=line&token="));
+
+        let message = render(
+            &client,
+            "example.com",
+            &signer,
+            Some("1234-abcd"),
+            &Reply::Choices("「一蘭」が出ました".to_string(), Choices::ActiveDraw(None)),
+        );
+        let json = serde_json::to_value(&message).unwrap();
+        let uri = json["quickReply"]["items"][0]["action"]["uri"]
+            .as_str()
+            .unwrap();
+        assert!(uri.starts_with("https://liff.line.me/1234-abcd?token="));
+
+        let message = render(
+            &client,
+            "example.com",
+            &signer,
+            None,
+            &Reply::Location {
+                title: "一蘭".to_string(),
+                coordinates: Coordinates {
+                    latitude: 35.5,
+                    longitude: 139.5,
+                },
+            },
+        );
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["type"], "location");
+        assert_eq!(json["title"], "一蘭");
+        assert_eq!(json["address"], "35.5,139.5");
+
+        let message = render(
+            &client,
+            "example.com",
+            &signer,
+            None,
+            &Reply::Error("Http".to_string()),
+        );
+        assert_eq!(message.text.as_deref(), Some("Error Http"));
+    }
+
+    #[tokio::test]
+    async fn it_switches_the_rich_menu_only_when_its_alias_changes() {
+        let links = Arc::new(Mutex::new(vec![]));
+        let recorded = links.clone();
+        let link = warp::post()
+            .and(warp::path!(
+                "v2" / "bot" / "user" / String / "richmenu" / String
+            ))
+            .map(move |_user: String, menu: String| {
+                recorded.lock().unwrap().push(menu);
+                warp::reply::json(&json!({}))
+            });
+        let alias = warp::get()
+            .and(warp::path!("v2" / "bot" / "richmenu" / "alias" / String))
+            .map(|alias: String| {
+                warp::reply::json(&json!({ "richMenuAliasId": alias, "richMenuId": alias }))
+            });
+        let others = warp::any().map(|| warp::reply::json(&json!({})));
+        let (address, server) =
+            warp::serve(link.or(alias).or(others)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let line = LineClient::with_base_url("token", &format!("http://{address}"))
+            .with_link_signer(Arc::new(LinkSigner::new(b"secret")));
+
+        let client = Client::Line(LineChannel::User("U1".to_string()));
+        for choices in [
+            Choices::ActiveDraw(None),
+            Choices::ActiveDraw(None),
+            Choices::Idle(None),
+            Choices::Candidates(2),
+        ] {
+            line.respond(&client, "example.com", Response::choices("ok", choices))
+                .await;
+        }
+        assert_eq!(*links.lock().unwrap(), vec!["active_draw", "idle"]);
+    }
+}
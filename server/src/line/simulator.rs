@@ -0,0 +1,219 @@
+use std::sync::{Arc, Mutex};
+
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use ring::hmac;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use crate::http::{ApiError, HttpClient, HttpResult};
+use crate::line::html::html_file;
+use crate::line::webhook::signature;
+
+/// Plays the Line platform for a local server: it signs the webhook events sent from its page
+/// and records the messages the server sends to its fake Messaging API
+#[derive(Clone)]
+pub struct Simulator {
+    server_url: String,
+    key: hmac::Key,
+    client: reqwest::Client,
+    messages: Arc<Mutex<Vec<Value>>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MessagesQuery {
+    #[serde(default)]
+    after: usize,
+}
+
+impl Simulator {
+    pub fn new(server_url: &str, channel_secret: &str) -> Self {
+        Simulator {
+            server_url: server_url.trim_end_matches('/').to_string(),
+            key: hmac::Key::new(hmac::HMAC_SHA256, channel_secret.as_bytes()),
+            client: reqwest::Client::new(),
+            messages: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Signature and body of the webhook call carrying an event built by the simulator page
+    pub fn webhook_request(&self, mut event: Value) -> (String, String) {
+        event["mode"] = json!("active");
+        event["timestamp"] = json!(crate::app::session::now() * 1000);
+        event["webhookEventId"] = json!(format!("{:016x}", rand::random::<u64>()));
+        event["deliveryContext"] = json!({ "isRedelivery": false });
+        event["replyToken"] = json!(format!("{:016x}", rand::random::<u64>()));
+        let body = json!({ "destination": "simulator", "events": [event] }).to_string();
+        (signature(&self.key, body.as_bytes()), body)
+    }
+
+    async fn deliver(&self, event: Value) -> HttpResult<()> {
+        let (signature, body) = self.webhook_request(event);
+        let url = format!("{}/line/webhook", self.server_url);
+        self.client
+            .make_request(|client| {
+                client
+                    .post(url)
+                    .header("x-line-signature", signature)
+                    .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+                    .body(body)
+            })
+            .await
+            .map(|_| ())
+    }
+
+    fn record(&self, to: &str, messages: &[Value]) {
+        let mut recorded = self.messages.lock().unwrap();
+        for message in messages {
+            println!("Line message to {to}: {message}");
+            recorded.push(json!({ "to": to, "message": message }));
+        }
+    }
+
+    /// Messages sent by the server, from the given index
+    pub fn messages_after(&self, after: usize) -> Vec<Value> {
+        let messages = self.messages.lock().unwrap();
+        messages.iter().skip(after).cloned().collect()
+    }
+}
+
+#[allow(opaque_hidden_inferred_bound)]
+fn messaging_api(
+    simulator: Simulator,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone + Sync + Send {
+    let with_simulator = warp::any().map(move || simulator.clone());
+    let send = warp::post()
+        .and(warp::path!("v2" / "bot" / "message" / String))
+        .and(warp::body::json::<Value>())
+        .and(with_simulator)
+        .map(|kind: String, body: Value, simulator: Simulator| {
+            // Replies carry a token instead of a recipient
+            let to = body["to"].as_str().unwrap_or(&kind).to_string();
+            let messages = body["messages"].as_array().cloned().unwrap_or_default();
+            simulator.record(&to, &messages);
+            warp::reply::json(&json!({}))
+        });
+    let profile = warp::get()
+        .and(warp::path!("v2" / "bot" / "profile" / String))
+        .map(|id: String| warp::reply::json(&json!({ "userId": id, "displayName": id })));
+    let group = warp::get()
+        .and(warp::path!("v2" / "bot" / "group" / String / "summary"))
+        .map(|id: String| warp::reply::json(&json!({ "groupId": id, "groupName": id })));
+    // Every alias links to a menu of the same id
+    let menu_alias = warp::get()
+        .and(warp::path!("v2" / "bot" / "richmenu" / "alias" / String))
+        .map(|alias: String| {
+            warp::reply::json(&json!({ "richMenuAliasId": alias, "richMenuId": alias }))
+        });
+    let others = warp::path("v2")
+        .and(warp::path("bot"))
+        .map(|| warp::reply::json(&json!({})));
+    send.or(profile).or(group).or(menu_alias).or(others)
+}
+
+/// Fake Messaging API along the simulator page and the endpoints it polls
+#[allow(opaque_hidden_inferred_bound)]
+pub fn route(
+    simulator: Simulator,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone + Sync + Send {
+    let page = warp::get()
+        .and(warp::path::end())
+        .then(|| html_file("./resources/line/simulator.html", str::to_string));
+    let events_simulator = simulator.clone();
+    let events = warp::post()
+        .and(warp::path!("simulator" / "events"))
+        .and(warp::body::json::<Value>())
+        .and(warp::any().map(move || events_simulator.clone()))
+        .then(|event: Value, simulator: Simulator| async move {
+            match simulator.deliver(event).await {
+                Ok(_) => StatusCode::ACCEPTED,
+                Err(ApiError::Http { code, message }) => {
+                    println!("The server answered {code}: {message}");
+                    StatusCode::BAD_GATEWAY
+                }
+                Err(e) => {
+                    println!("Could not reach the server: {e:?}");
+                    StatusCode::BAD_GATEWAY
+                }
+            }
+        });
+    let messages_simulator = simulator.clone();
+    let messages = warp::get()
+        .and(warp::path!("simulator" / "messages"))
+        .and(warp::query::<MessagesQuery>())
+        .map(move |query: MessagesQuery| {
+            warp::reply::json(&messages_simulator.messages_after(query.after))
+        });
+    page.or(events).or(messages).or(messaging_api(simulator))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use tokio::sync::mpsc;
+    use warp::http::StatusCode;
+
+    use crate::app::core::Action;
+    use crate::app::jar::Jar;
+    use crate::line::api::LineApi;
+    use crate::line::dedup::fixtures::MemoryEventStore;
+    use crate::line::dedup::Deduplicator;
+    use crate::line::http::LineClient;
+    use crate::line::json::MessageContent;
+    use crate::line::simulator::{route, Simulator};
+    use crate::line::webhook;
+
+    #[tokio::test]
+    async fn it_records_messages_sent_to_the_fake_api() {
+        let simulator = Simulator::new("http://localhost:4001", "secret");
+        let (address, server) =
+            warp::serve(route(simulator.clone())).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let line = LineClient::with_base_url("token", &format!("http://{address}"));
+
+        line.send_to("U1", MessageContent::text("こんにちは"))
+            .await
+            .unwrap();
+        line.set_rich_menu_from_alias("idle", Some("U1"))
+            .await
+            .unwrap();
+        assert_eq!(line.get_jar_info(&Jar::new("user_U1")).await.unwrap(), "U1");
+
+        let messages = simulator.messages_after(0);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["to"], "U1");
+        assert_eq!(messages[0]["message"]["text"], "こんにちは");
+        assert!(simulator.messages_after(1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_signs_events_the_webhook_accepts() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let filter = webhook::route(
+            tx,
+            Arc::new(Deduplicator::new(MemoryEventStore::default())),
+            "secret",
+        );
+        let simulator = Simulator::new("http://localhost:4001", "secret");
+        let (signature, body) = simulator.webhook_request(json!({
+            "type": "message",
+            "source": { "type": "user", "userId": "U1" },
+            "message": { "type": "text", "text": "whoami" }
+        }));
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/line/webhook")
+            .header("host", "localhost:4001")
+            .header("x-line-signature", signature)
+            .body(body)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let (_, action) = rx.recv().await.unwrap();
+        assert!(matches!(action, Action::WhoAmI(_)));
+    }
+}
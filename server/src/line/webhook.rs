@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use base64::Engine;
 use ring::hmac;
 use tokio::sync::mpsc::Sender;
@@ -5,29 +7,26 @@ use warp::http::{HeaderMap, StatusCode};
 use warp::hyper::body::Bytes;
 use warp::{Filter, Rejection, Reply};
 
-use crate::app::core::Action;
+use crate::app::core::{parse_area_draw, parse_region, parse_saved_location, Action};
 use crate::app::user_action::UserAction;
-use crate::line::http::LineClient;
+use crate::line::dedup::{Deduplicator, EventStore};
 use crate::line::json::{Event, Payload};
 
-use super::bot;
-
 #[derive(Debug)]
 struct InvalidWebhookError;
 
 impl warp::reject::Reject for InvalidWebhookError {}
 
 #[allow(opaque_hidden_inferred_bound)]
-pub fn route(
-    line_client: LineClient,
+pub fn route<S>(
     tx: Sender<(String, Action)>,
-) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone + Sync + Send {
-    let key = hmac::Key::new(
-        hmac::HMAC_SHA256,
-        std::env::var("LINE_CHANNEL_SECRET")
-            .expect("Needs to have a LINE_CHANNEL_SECRET env variables to verify incoming webhook")
-            .as_bytes(),
-    );
+    deduplicator: Arc<Deduplicator<S>>,
+    channel_secret: &str,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone + Sync + Send
+where
+    S: EventStore + Send + Sync + 'static,
+{
+    let key = hmac::Key::new(hmac::HMAC_SHA256, channel_secret.as_bytes());
     warp::path!("line" / "webhook")
         .and(warp::filters::path::full())
         .and(warp::header::headers_cloned())
@@ -49,10 +48,7 @@ pub fn route(
                 std::str::from_utf8(&payload)
                     .map_err(|_| warp::reject::custom(InvalidWebhookError))
                     .and_then(|text| {
-                        let signature = hmac::sign(&key, text.as_bytes());
-                        let encoded =
-                            base64::engine::general_purpose::STANDARD.encode(signature.as_ref());
-                        if header == encoded {
+                        if header == signature(&key, text.as_bytes()) {
                             serde_json::from_str::<Payload>(text)
                                 .map_err(|_| warp::reject::custom(InvalidWebhookError))
                         } else {
@@ -62,10 +58,13 @@ pub fn route(
             },
         )
         .and(warp::header::<String>("host"))
-        .and(warp::any().map(move || line_client.clone()))
         .and(warp::any().map(move || tx.clone()))
+        .and(warp::any().map(move || deduplicator.clone()))
         .map(
-            |json: Payload, host: String, client: LineClient, tx: Sender<(String, Action)>| {
+            |json: Payload,
+             host: String,
+             tx: Sender<(String, Action)>,
+             deduplicator: Arc<Deduplicator<S>>| {
                 println!(
                     "Got {} webhook event(s) from bot {} @ {}",
                     json.events.len(),
@@ -73,7 +72,7 @@ pub fn route(
                     host
                 );
                 tokio::spawn(async move {
-                    let actions = parse_webhook_events(client, json).await;
+                    let actions = parse_webhook_events(json, &deduplicator).await;
                     for action in actions {
                         println!("Send {action:?}");
                         let _ = tx.send((host.clone(), action)).await;
@@ -84,16 +83,28 @@ pub fn route(
         )
 }
 
-async fn parse_webhook_events(line_client: LineClient, payload: Payload) -> Vec<Action> {
+/// `X-Line-Signature` of a webhook body
+pub fn signature(key: &hmac::Key, body: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(hmac::sign(key, body).as_ref())
+}
+
+async fn parse_webhook_events<S: EventStore>(
+    payload: Payload,
+    deduplicator: &Deduplicator<S>,
+) -> Vec<Action> {
     let mut vec: Vec<Action> = Vec::new();
     for event in payload.events {
+        if deduplicator.is_duplicate(&event).await {
+            println!("Skip duplicate webhook event {:?}", event.webhook_event_id);
+            continue;
+        }
         let mode = event.clone().mode;
         println!("{mode:?}");
         let action = match mode.as_str() {
             "active" => {
                 let event_type = event.event_type.as_str();
                 println!("{event_type:?}");
-                action(&line_client, &event, event_type).await
+                action(&event, event_type)
             }
             _ => {
                 println!("Unknown event mode {mode:?}");
@@ -109,38 +120,24 @@ async fn parse_webhook_events(line_client: LineClient, payload: Payload) -> Vec<
     return vec;
 }
 
-async fn action(line_client: &LineClient, event: &Event, event_type: &str) -> Option<Action> {
+fn action(event: &Event, event_type: &str) -> Option<Action> {
     match event_type {
-        "join" => {
-            let _ = bot::setup(line_client, &event.source).await;
+        "join" | "follow" => return event.source.to_client().map(Action::Join),
+        "leave" | "unfollow" => return event.source.to_client().map(Action::Leave),
+        "memberJoined" => {
+            let user_ids = event.joined.as_ref()?.user_ids();
+            return Some(Action::MembersJoined(event.source.to_client()?, user_ids));
         }
-        "follow" => {
-            let _ = bot::setup(line_client, &event.source).await;
+        "memberLeft" => {
+            let user_ids = event.left.as_ref()?.user_ids();
+            return Some(Action::MembersLeft(event.source.to_client()?, user_ids));
         }
         "message" => return message_to_action(event),
         "postback" => {
             if let (Some(client), Some(postback)) = (event.source.to_client(), &event.postback) {
-                if let Ok(user_action) = serde_json::from_str(postback.data.as_str()) {
-                    return match user_action {
-                        UserAction::Draw(meal, coordinates) => {
-                            Some(Action::Draw(client, meal, coordinates))
-                        }
-                        UserAction::Postpone(coordinates) => {
-                            Some(Action::PostponeCurrent(client, coordinates))
-                        }
-                        UserAction::DeleteCurrent(coordinates) => {
-                            Some(Action::RemoveCurrent(client, coordinates))
-                        }
-                        UserAction::ArchiveCurrent(coordinates) => {
-                            Some(Action::ArchiveCurrent(client, coordinates))
-                        }
-                        UserAction::ClearLocation => Some(Action::ClearLocation(client)),
-                        UserAction::Add => {
-                            println!("Unhandled postback event: {:?}", &event);
-                            None
-                        }
-                        UserAction::Refresh => Some(Action::Refresh(client)),
-                    };
+                if let Ok(user_action) = serde_json::from_str::<UserAction>(postback.data.as_str())
+                {
+                    return Some(user_action.into_action(client));
                 }
             }
         }
@@ -156,15 +153,35 @@ fn message_to_action(event: &Event) -> Option<Action> {
     let message = event.message.as_ref()?;
     let client = event.source.to_client()?;
     match message.message_type.as_str() {
-        "text" => match message.text.as_ref()?.to_lowercase().trim() {
-            "refresh" => Some(Action::Refresh(client)),
-            "更新" => Some(Action::Refresh(client)),
-            "whoami" => Some(Action::WhoAmI(client)),
-            _ => None,
-        },
+        "text" => {
+            let text = message.text.as_ref()?;
+            match text.to_lowercase().trim() {
+                "refresh" => Some(Action::Refresh(client)),
+                "更新" => Some(Action::Refresh(client)),
+                "whoami" => Some(Action::WhoAmI(client)),
+                "manage" | "管理" => Some(Action::Manage(client)),
+                "api" => Some(Action::IssueApiToken(client)),
+                "api revoke" => Some(Action::RevokeApiTokens(client)),
+                _ => match (
+                    parse_area_draw(text),
+                    parse_region(text),
+                    parse_saved_location(text),
+                ) {
+                    (Some((meal, area)), _, _) => Some(Action::DrawIn(client, meal, area)),
+                    (None, Some(argument), _) => Some(Action::Region(client, argument)),
+                    (None, None, Some(argument)) => Some(Action::SavedLocation(client, argument)),
+                    (None, None, None) => Some(Action::Input(client, text.to_string())),
+                },
+            }
+        }
         "location" => {
             if let (Some(lat), Some(long)) = (message.latitude, message.longitude) {
-                Some(Action::Location(client, lat, long))
+                // Locations picked on the map carry the name of the spot
+                let title = message
+                    .title
+                    .clone()
+                    .filter(|title| !title.trim().is_empty());
+                Some(Action::Location(client, lat, long, title))
             } else {
                 None
             }
@@ -172,3 +189,100 @@ fn message_to_action(event: &Event) -> Option<Action> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ring::hmac;
+    use tokio::sync::mpsc;
+    use warp::http::StatusCode;
+
+    use crate::app::core::Action;
+    use crate::app::jar::Jar;
+    use crate::line::dedup::fixtures::MemoryEventStore;
+    use crate::line::dedup::Deduplicator;
+    use crate::line::json::Payload;
+    use crate::line::webhook::{parse_webhook_events, route, signature};
+
+    #[tokio::test]
+    async fn it_maps_membership_events() {
+        let deduplicator = Deduplicator::new(MemoryEventStore::default());
+        let group = serde_json::json!({ "type": "group", "groupId": "G1" });
+        let members = serde_json::json!({ "members": [
+            { "type": "user", "userId": "U1" },
+            { "type": "user", "userId": "U2" }
+        ]});
+        let payload: Payload = serde_json::from_value(serde_json::json!({
+            "destination": "bot",
+            "events": [
+                { "type": "leave", "mode": "active", "source": group },
+                {
+                    "type": "unfollow",
+                    "mode": "active",
+                    "source": { "type": "user", "userId": "U1" }
+                },
+                { "type": "memberJoined", "mode": "active", "source": group, "joined": members },
+                { "type": "memberLeft", "mode": "active", "source": group, "left": members }
+            ]
+        }))
+        .unwrap();
+
+        let actions = parse_webhook_events(payload, &deduplicator).await;
+        let jars: Vec<String> = actions
+            .iter()
+            .map(|action| match action {
+                Action::Leave(client)
+                | Action::MembersJoined(client, _)
+                | Action::MembersLeft(client, _) => Jar::from(client).to_string(),
+                action => panic!("Unexpected {action:?}"),
+            })
+            .collect();
+        assert_eq!(jars, vec!["group_G1", "user_U1", "group_G1", "group_G1"]);
+        assert!(matches!(&actions[2], Action::MembersJoined(_, ids) if ids == &["U1", "U2"]));
+        assert!(matches!(&actions[3], Action::MembersLeft(_, ids) if ids == &["U1", "U2"]));
+    }
+
+    #[tokio::test]
+    async fn it_sends_a_redelivered_event_only_once() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let filter = route(
+            tx,
+            Arc::new(Deduplicator::new(MemoryEventStore::default())),
+            "secret",
+        );
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        let event = |is_redelivery: bool| {
+            serde_json::json!({
+                "type": "message",
+                "mode": "active",
+                "webhookEventId": "01H1",
+                "deliveryContext": { "isRedelivery": is_redelivery },
+                "source": { "type": "user", "userId": "U1" },
+                "message": { "type": "text", "text": "whoami" }
+            })
+        };
+        let payloads = [
+            serde_json::json!({ "destination": "bot", "events": [event(false), event(false)] }),
+            serde_json::json!({ "destination": "bot", "events": [event(true)] }),
+        ];
+
+        for payload in payloads {
+            let body = payload.to_string();
+            let response = warp::test::request()
+                .method("POST")
+                .path("/line/webhook")
+                .header("host", "localhost:4001")
+                .header("x-line-signature", signature(&key, body.as_bytes()))
+                .body(body)
+                .reply(&filter)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        // The channel closes once the route and its spawned tasks are gone
+        drop(filter);
+        let (_, action) = rx.recv().await.unwrap();
+        assert!(matches!(action, Action::WhoAmI(_)));
+        assert!(rx.recv().await.is_none());
+    }
+}
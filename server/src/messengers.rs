@@ -0,0 +1,48 @@
+use async_trait::async_trait;
+
+use crate::app::core::Client;
+use crate::app::response::{Messenger, Response};
+use crate::http::{ApiError, HttpResult};
+use crate::line::http::LineClient;
+use crate::slack::http::SlackClient;
+use crate::telegram::http::TelegramClient;
+use crate::web::hub::WebHub;
+
+/// Answers each action on the chat platform it came from
+pub struct Messengers {
+    pub line: LineClient,
+    pub slack: Option<SlackClient>,
+    pub telegram: Option<TelegramClient>,
+    pub web: WebHub,
+}
+
+fn configured<'a, C>(messenger: &'a Option<C>, name: &str) -> HttpResult<&'a C> {
+    messenger.as_ref().ok_or(ApiError::Unknown {
+        message: format!("{name} is not configured"),
+    })
+}
+
+impl Messengers {
+    fn platform(&self, client: &Client) -> HttpResult<&(dyn Messenger + Sync)> {
+        match client {
+            Client::Line(_) => Ok(&self.line),
+            Client::Slack(_) => Ok(configured(&self.slack, "Slack")?),
+            Client::Telegram(_) => Ok(configured(&self.telegram, "Telegram")?),
+            Client::Web(_) => Ok(&self.web),
+        }
+    }
+}
+
+#[async_trait]
+impl Messenger for Messengers {
+    async fn respond(&self, client: &Client, host: &str, response: Response) {
+        match self.platform(client) {
+            Ok(messenger) => messenger.respond(client, host, response).await,
+            Err(e) => println!("Could not answer {client:?}: {e:?}"),
+        }
+    }
+
+    async fn chat_name(&self, client: &Client) -> HttpResult<String> {
+        self.platform(client)?.chat_name(client).await
+    }
+}
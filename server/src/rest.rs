@@ -0,0 +1,415 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use crate::app::address::Address;
+use crate::app::api_token;
+use crate::app::coordinates::Coordinates;
+use crate::app::core::{Action, DrawResult, Meal, Place};
+use crate::app::history::HistoryEntry;
+use crate::app::jar::Jar;
+use crate::app::manage;
+use crate::app::manage::PlaceDetails;
+use crate::gcp::api::FirebaseApi;
+
+// A draw also announces the place in the chat; past this delay the API only accepts it
+const DRAW_RESULT_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Serialize, Debug, PartialEq)]
+struct PlaceView {
+    key: String,
+    name: String,
+    meals: Vec<&'static str>,
+    coordinates: Option<Coordinates>,
+    address: Option<Address>,
+    tags: Vec<String>,
+    last_visit: Option<u64>,
+}
+
+impl From<PlaceDetails> for PlaceView {
+    fn from(place: PlaceDetails) -> Self {
+        PlaceView {
+            meals: place.meals.iter().map(meal_name).collect(),
+            key: place.key,
+            name: place.name,
+            coordinates: place.coordinates,
+            address: place.address,
+            tags: place.tags,
+            last_visit: place.last_visit,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct DrawRequest {
+    meal: String,
+    latitude: Option<f32>,
+    longitude: Option<f32>,
+}
+
+#[derive(Serialize, Debug)]
+struct DrawView {
+    place: Option<Place>,
+    already_drawn: bool,
+}
+
+fn meal_name(meal: &Meal) -> &'static str {
+    match meal {
+        Meal::Lunch => "lunch",
+        Meal::Dinner => "dinner",
+    }
+}
+
+fn parse_meal(name: &str) -> Option<Meal> {
+    [Meal::Lunch, Meal::Dinner]
+        .into_iter()
+        .find(|meal| meal_name(meal) == name)
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "error": message })),
+        status,
+    )
+    .into_response()
+}
+
+/// Read only views of a jar, and draws, for clients holding one of its API tokens
+#[allow(opaque_hidden_inferred_bound)]
+pub fn route<T>(
+    sender: Sender<(String, Action)>,
+    firebase_client: Arc<T>,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Sync + Send
+where
+    T: FirebaseApi + Send + Sync + 'static,
+{
+    let with_firebase = warp::any().map(move || firebase_client.clone());
+    let with_sender = warp::any().map(move || sender.clone());
+    let authorization = warp::header::optional::<String>("authorization");
+
+    let places = warp::path!("api" / "jars" / String / "places")
+        .and(warp::get())
+        .and(authorization)
+        .and(with_firebase.clone())
+        .then(
+            |jar: String, authorization: Option<String>, firebase_client: Arc<T>| async move {
+                let jar = match authorize(&jar, authorization, firebase_client.as_ref()).await {
+                    Ok(jar) => jar,
+                    Err(status) => {
+                        return error(status, status.canonical_reason().unwrap_or_default())
+                    }
+                };
+                match manage::get_places(&jar, firebase_client.as_ref()).await {
+                    Ok(places) => {
+                        let places: Vec<PlaceView> = places.into_iter().map(Into::into).collect();
+                        warp::reply::json(&places).into_response()
+                    }
+                    Err(e) => {
+                        println!("Could not get the places of {jar:?}: {e:?}");
+                        error(StatusCode::BAD_GATEWAY, "Could not get the places")
+                    }
+                }
+            },
+        );
+    let current_draw = warp::path!("api" / "jars" / String / "current_draw")
+        .and(warp::get())
+        .and(authorization)
+        .and(with_firebase.clone())
+        .then(
+            |jar: String, authorization: Option<String>, firebase_client: Arc<T>| async move {
+                let jar = match authorize(&jar, authorization, firebase_client.as_ref()).await {
+                    Ok(jar) => jar,
+                    Err(status) => {
+                        return error(status, status.canonical_reason().unwrap_or_default())
+                    }
+                };
+                match firebase_client.get_current_draw(&jar).await {
+                    Ok(place) => warp::reply::json(&place).into_response(),
+                    Err(e) => {
+                        println!("Could not get the current draw of {jar:?}: {e:?}");
+                        error(StatusCode::BAD_GATEWAY, "Could not get the current draw")
+                    }
+                }
+            },
+        );
+    let history = warp::path!("api" / "jars" / String / "history")
+        .and(warp::get())
+        .and(authorization)
+        .and(with_firebase.clone())
+        .then(
+            |jar: String, authorization: Option<String>, firebase_client: Arc<T>| async move {
+                let jar = match authorize(&jar, authorization, firebase_client.as_ref()).await {
+                    Ok(jar) => jar,
+                    Err(status) => {
+                        return error(status, status.canonical_reason().unwrap_or_default())
+                    }
+                };
+                match firebase_client.get_history(&jar).await {
+                    Ok(entries) => warp::reply::json::<Vec<HistoryEntry>>(&entries).into_response(),
+                    Err(e) => {
+                        println!("Could not get the history of {jar:?}: {e:?}");
+                        error(StatusCode::BAD_GATEWAY, "Could not get the history")
+                    }
+                }
+            },
+        );
+    let draw = warp::path!("api" / "jars" / String / "draw")
+        .and(warp::post())
+        .and(authorization)
+        .and(warp::body::json::<DrawRequest>())
+        .and(warp::header::<String>("host"))
+        .and(with_firebase)
+        .and(with_sender)
+        .then(
+            |jar: String,
+             authorization: Option<String>,
+             body: DrawRequest,
+             host: String,
+             firebase_client: Arc<T>,
+             sender: Sender<(String, Action)>| async move {
+                let jar = match authorize(&jar, authorization, firebase_client.as_ref()).await {
+                    Ok(jar) => jar,
+                    Err(status) => {
+                        return error(status, status.canonical_reason().unwrap_or_default())
+                    }
+                };
+                let Some(meal) = parse_meal(&body.meal) else {
+                    return error(StatusCode::BAD_REQUEST, "meal must be lunch or dinner");
+                };
+                let coordinates = match (body.latitude, body.longitude) {
+                    (Some(latitude), Some(longitude)) => Some(Coordinates {
+                        latitude,
+                        longitude,
+                    }),
+                    (None, None) => None,
+                    _ => {
+                        return error(
+                            StatusCode::BAD_REQUEST,
+                            "latitude and longitude go together",
+                        )
+                    }
+                };
+                let Ok(client) = jar.client() else {
+                    return error(StatusCode::NOT_FOUND, "Unknown jar");
+                };
+                let (responder, result) = oneshot::channel();
+                let action = Action::Draw(client, meal, coordinates, Some(responder));
+                let _ = sender.send((host, action)).await;
+                let result = tokio::time::timeout(DRAW_RESULT_TIMEOUT, result)
+                    .await
+                    .ok()
+                    .and_then(|result| result.ok());
+                draw_response(result)
+            },
+        );
+
+    places.or(current_draw).or(history).or(draw)
+}
+
+fn draw_response(result: Option<DrawResult>) -> Response {
+    match result {
+        Some(DrawResult::Drawn(place)) => warp::reply::json(&DrawView {
+            place: Some(place),
+            already_drawn: false,
+        })
+        .into_response(),
+        Some(DrawResult::AlreadyDrawn(place)) => warp::reply::with_status(
+            warp::reply::json(&DrawView {
+                place: Some(place),
+                already_drawn: true,
+            }),
+            StatusCode::CONFLICT,
+        )
+        .into_response(),
+        Some(DrawResult::NothingToDraw) => error(StatusCode::NOT_FOUND, "No place to draw"),
+        Some(DrawResult::Failed(e)) => error(StatusCode::BAD_GATEWAY, &e),
+        None => StatusCode::ACCEPTED.into_response(),
+    }
+}
+
+// The jar of the path, when the bearer token is one of its API tokens
+async fn authorize<T: FirebaseApi + Sync>(
+    jar: &str,
+    authorization: Option<String>,
+    firebase_client: &T,
+) -> Result<Jar, StatusCode> {
+    let jar = Jar::new(jar);
+    let token = authorization
+        .as_deref()
+        .and_then(api_token::from_authorization)
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    if jar.client().is_err() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    match firebase_client
+        .has_api_token(&jar, &api_token::hash(token))
+        .await
+    {
+        Ok(true) => Ok(jar),
+        Ok(false) => Err(StatusCode::UNAUTHORIZED),
+        Err(e) => {
+            println!("Could not check the API tokens of {jar:?}: {e:?}");
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use crate::app::address::Address;
+    use crate::app::api_token;
+    use crate::app::api_token::ApiToken;
+    use crate::app::core::{Action, DrawResult, Meal, Place};
+    use crate::app::jar::Jar;
+    use crate::app::manage::PlaceDetails;
+    use crate::gcp::api::fixtures::MemoryFirebase;
+    use crate::gcp::api::FirebaseApi;
+    use crate::rest::{parse_meal, route, PlaceView};
+
+    // A jar holding a place, and one of its API tokens
+    async fn jar_with_token() -> (Arc<MemoryFirebase>, String) {
+        let firebase_client = MemoryFirebase::default();
+        let jar = Jar::new("user_U1");
+        firebase_client
+            .add_place(&jar, "一蘭", &[Meal::Lunch], None)
+            .await
+            .unwrap();
+        let token = api_token::generate();
+        let created = ApiToken {
+            created_at: 0,
+            created_by: None,
+        };
+        firebase_client
+            .add_api_token(&jar, &api_token::hash(&token), &created)
+            .await
+            .unwrap();
+        (Arc::new(firebase_client), token)
+    }
+
+    #[tokio::test]
+    async fn it_rejects_missing_and_wrong_tokens() {
+        let (firebase_client, token) = jar_with_token().await;
+        let (tx, _rx) = mpsc::channel(1);
+        let filter = route(tx, firebase_client);
+
+        for authorization in [None, Some("Bearer wrong".to_string()), Some(token.clone())] {
+            let mut request = warp::test::request().path("/api/jars/user_U1/places");
+            if let Some(authorization) = authorization {
+                request = request.header("authorization", authorization);
+            }
+            assert_eq!(request.reply(&filter).await.status(), 401);
+        }
+        let response = warp::test::request()
+            .path("/api/jars/user_U2/places")
+            .header("authorization", format!("Bearer {token}"))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn it_serves_the_jar_of_the_token() {
+        let (firebase_client, token) = jar_with_token().await;
+        let (tx, _rx) = mpsc::channel(1);
+        let filter = route(tx, firebase_client);
+
+        let response = warp::test::request()
+            .path("/api/jars/user_U1/places")
+            .header("authorization", format!("Bearer {token}"))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        let places: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(places[0]["name"], "一蘭");
+    }
+
+    #[tokio::test]
+    async fn it_answers_draws_with_their_result() {
+        let (firebase_client, token) = jar_with_token().await;
+        let (tx, mut rx) = mpsc::channel(1);
+        let filter = route(tx, firebase_client);
+        tokio::spawn(async move {
+            if let Some((_, Action::Draw(_, Meal::Lunch, None, Some(responder)))) = rx.recv().await
+            {
+                let _ = responder.send(DrawResult::Drawn(Place {
+                    key: "k".to_string(),
+                    name: "一蘭".to_string(),
+                }));
+            }
+        });
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/jars/user_U1/draw")
+            .header("authorization", format!("Bearer {token}"))
+            .header("host", "example.com")
+            .json(&serde_json::json!({ "meal": "lunch" }))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        let draw: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(draw["place"]["name"], "一蘭");
+        assert_eq!(draw["already_drawn"], false);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_accepts_draws_answered_too_late() {
+        let (firebase_client, token) = jar_with_token().await;
+        // The action is queued but never handled
+        let (tx, _rx) = mpsc::channel(1);
+        let filter = route(tx, firebase_client);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/jars/user_U1/draw")
+            .header("authorization", format!("Bearer {token}"))
+            .header("host", "example.com")
+            .json(&serde_json::json!({ "meal": "lunch" }))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 202);
+    }
+
+    #[test]
+    fn it_uses_english_meal_names() {
+        let view: PlaceView = PlaceDetails {
+            key: "k".to_string(),
+            name: "一蘭".to_string(),
+            meals: vec![Meal::Lunch, Meal::Dinner],
+            coordinates: None,
+            address: Some(Address {
+                district: Some("渋谷区".to_string()),
+                ..Address::default()
+            }),
+            tags: vec![],
+            added_by: Some("U1".to_string()),
+            link: None,
+            last_visit: Some(3),
+        }
+        .into();
+        assert_eq!(
+            serde_json::to_value(&view).unwrap(),
+            serde_json::json!({
+                "key": "k",
+                "name": "一蘭",
+                "meals": ["lunch", "dinner"],
+                "coordinates": null,
+                "address": {"district": "渋谷区"},
+                "tags": [],
+                "last_visit": 3,
+            })
+        );
+        assert_eq!(parse_meal("dinner"), Some(Meal::Dinner));
+        assert_eq!(parse_meal("夜"), None);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod http;
+pub mod render;
+pub mod signature;
+pub mod webhook;
@@ -0,0 +1,226 @@
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::app::core::Client;
+use crate::app::response::{Audience, Messenger, Response};
+use crate::http::{ApiError, HttpClient, HttpResult};
+use crate::slack::render;
+
+const BASE_SLACK_URL: &str = "https://slack.com/api";
+
+#[derive(Debug)]
+pub struct SlackChannel {
+    pub team_id: String,
+    pub channel_id: String,
+    pub user_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SlackResponse {
+    ok: bool,
+    error: Option<String>,
+    #[serde(flatten)]
+    content: Value,
+}
+
+#[derive(Clone)]
+pub struct SlackClient {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl SlackClient {
+    pub fn new(bot_token: &str) -> Self {
+        SlackClient::with_base_url(bot_token, BASE_SLACK_URL)
+    }
+
+    /// Client of a Web API served elsewhere, such as a local fake
+    pub fn with_base_url(bot_token: &str, base_url: &str) -> Self {
+        let mut header_map = HeaderMap::new();
+        let mut auth_value = HeaderValue::from_str(&format!("Bearer {bot_token}")).unwrap();
+        auth_value.set_sensitive(true);
+        header_map.append(AUTHORIZATION, auth_value);
+        SlackClient {
+            client: reqwest::Client::builder()
+                .default_headers(header_map)
+                .build()
+                .unwrap(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn api_url(&self, method: &str) -> String {
+        format!("{}/{method}", self.base_url)
+    }
+
+    // Slack answers errors with a 200 status and `ok` set to false
+    fn content(response: SlackResponse) -> HttpResult<Value> {
+        if response.ok {
+            Ok(response.content)
+        } else {
+            Err(ApiError::Http {
+                code: 200,
+                message: response.error.unwrap_or_default(),
+            })
+        }
+    }
+
+    pub async fn post(&self, method: &str, body: &Value) -> HttpResult<Value> {
+        let response: SlackResponse = self
+            .client
+            .make_json_request(|client| client.post(self.api_url(method)).json(body))
+            .await?;
+        SlackClient::content(response)
+    }
+
+    pub async fn get(&self, method: &str, query: &[(&str, &str)]) -> HttpResult<Value> {
+        let response: SlackResponse = self
+            .client
+            .make_json_request(|client| client.get(self.api_url(method)).query(query))
+            .await?;
+        SlackClient::content(response)
+    }
+}
+
+#[async_trait]
+impl Messenger for SlackClient {
+    async fn respond(&self, client: &Client, _host: &str, response: Response) {
+        let Client::Slack(channel) = client else {
+            println!("Cannot answer {client:?} on Slack");
+            return;
+        };
+        for reply in &response.replies {
+            let mut message = render::message(reply);
+            message["channel"] = Value::from(channel.channel_id.as_str());
+            let sent = match (response.audience, &channel.user_id) {
+                (Audience::Requester, Some(user_id)) => {
+                    message["user"] = Value::from(user_id.as_str());
+                    self.post("chat.postEphemeral", &message).await
+                }
+                (Audience::Requester, None) => {
+                    println!("Could not send to a single user for {client:?}");
+                    continue;
+                }
+                (Audience::Chat, _) => self.post("chat.postMessage", &message).await,
+            };
+            if let Err(e) = sent {
+                println!("Could not send {reply:?} to {client:?}: {e:?}");
+            }
+        }
+    }
+
+    async fn chat_name(&self, client: &Client) -> HttpResult<String> {
+        let Client::Slack(channel) = client else {
+            return Err(ApiError::Unknown {
+                message: format!("{client:?} is not a Slack channel"),
+            });
+        };
+        let info = self
+            .get(
+                "conversations.info",
+                &[("channel", channel.channel_id.as_str())],
+            )
+            .await?;
+        info["channel"]["name"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or(ApiError::Unknown {
+                message: format!("No name for {client:?}"),
+            })
+    }
+}
+
+#[cfg(test)]
+pub mod fixtures {
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::Value;
+    use warp::Filter;
+
+    /// Local stand-in of the Slack Web API recording the calls it receives
+    #[derive(Clone, Default)]
+    pub struct FakeSlack {
+        pub calls: Arc<Mutex<Vec<(String, Value)>>>,
+    }
+
+    impl FakeSlack {
+        pub async fn serve(&self) -> SocketAddr {
+            let calls = self.calls.clone();
+            let post = warp::post()
+                .and(warp::path::param::<String>())
+                .and(warp::body::json::<Value>())
+                .map(move |method: String, body: Value| {
+                    calls.lock().unwrap().push((method, body));
+                    warp::reply::json(&serde_json::json!({ "ok": true }))
+                });
+            let get = warp::get()
+                .and(warp::path!("conversations.info"))
+                .and(warp::query::<std::collections::HashMap<String, String>>())
+                .map(|query: std::collections::HashMap<String, String>| {
+                    let reply = match query.get("channel").map(String::as_str) {
+                        Some("C1") => {
+                            serde_json::json!({ "ok": true, "channel": { "name": "lunch" } })
+                        }
+                        _ => serde_json::json!({ "ok": false, "error": "channel_not_found" }),
+                    };
+                    warp::reply::json(&reply)
+                });
+            let (address, server) = warp::serve(post.or(get)).bind_ephemeral(([127, 0, 0, 1], 0));
+            tokio::spawn(server);
+            address
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::core::Client;
+    use crate::app::response::{Choices, Messenger, Response};
+    use crate::slack::http::fixtures::FakeSlack;
+    use crate::slack::http::{SlackChannel, SlackClient};
+
+    fn channel(channel_id: &str) -> Client {
+        Client::Slack(SlackChannel {
+            team_id: "T1".to_string(),
+            channel_id: channel_id.to_string(),
+            user_id: Some("U1".to_string()),
+        })
+    }
+
+    #[tokio::test]
+    async fn it_posts_responses_to_the_web_api() {
+        let fake = FakeSlack::default();
+        let address = fake.serve().await;
+        let slack = SlackClient::with_base_url("xoxb-test", &format!("http://{address}"));
+
+        let response = Response::choices("「一蘭」が出ました", Choices::ActiveDraw(None));
+        slack.respond(&channel("C1"), "example.com", response).await;
+        slack
+            .respond(
+                &channel("C1"),
+                "example.com",
+                Response::text("U1").private(),
+            )
+            .await;
+
+        let calls = fake.calls.lock().unwrap().clone();
+        assert_eq!(calls.len(), 2);
+        let (method, message) = &calls[0];
+        assert_eq!(method, "chat.postMessage");
+        assert_eq!(message["channel"], "C1");
+        assert_eq!(message["text"], "「一蘭」が出ました");
+        assert_eq!(
+            message["blocks"][1]["elements"].as_array().unwrap().len(),
+            3
+        );
+        let (method, message) = &calls[1];
+        assert_eq!(method, "chat.postEphemeral");
+        assert_eq!(message["user"], "U1");
+
+        assert_eq!(slack.chat_name(&channel("C1")).await.unwrap(), "lunch");
+        assert!(slack.chat_name(&channel("C2")).await.is_err());
+    }
+}
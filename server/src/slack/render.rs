@@ -0,0 +1,48 @@
+use serde_json::{json, Value};
+
+use crate::app::response::Reply;
+use crate::app::user_action::UserAction;
+
+// Control characters of the Slack mrkdwn text
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn button(action: &UserAction) -> Value {
+    let value = serde_json::to_string(action).unwrap();
+    json!({
+        "type": "button",
+        "text": { "type": "plain_text", "text": action.label() },
+        "action_id": value,
+        "value": value,
+    })
+}
+
+/// Body of a `chat.postMessage` call, without its channel
+pub(crate) fn message(reply: &Reply) -> Value {
+    match reply {
+        Reply::Text(text) => json!({ "text": escape(text) }),
+        Reply::Choices(text, choices) => {
+            let buttons: Vec<Value> = choices.buttons().iter().map(button).collect();
+            let mut blocks = vec![json!({
+                "type": "section",
+                "text": { "type": "plain_text", "text": text },
+            })];
+            if !buttons.is_empty() {
+                blocks.push(json!({ "type": "actions", "elements": buttons }));
+            }
+            json!({ "text": escape(text), "blocks": blocks })
+        }
+        Reply::Location { title, coordinates } => json!({
+            "text": format!(
+                "<https://www.google.com/maps/search/?api=1&query={},{}|{}>",
+                coordinates.latitude,
+                coordinates.longitude,
+                escape(title)
+            ),
+        }),
+        Reply::Error(error) => json!({ "text": escape(&format!("Error {error}")) }),
+    }
+}
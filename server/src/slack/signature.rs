@@ -0,0 +1,123 @@
+use std::fmt::{Display, Formatter};
+
+use ring::hmac;
+
+// Requests older than this are rejected to prevent replays
+const MAX_REQUEST_AGE_SECONDS: u64 = 5 * 60;
+const SIGNATURE_VERSION: &str = "v0";
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+    Malformed,
+    Expired,
+    Invalid,
+}
+
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::Malformed => write!(f, "Malformed Slack signature headers"),
+            SignatureError::Expired => write!(f, "Expired Slack request"),
+            SignatureError::Invalid => write!(f, "Invalid Slack signature"),
+        }
+    }
+}
+
+/// Verifies the `X-Slack-Signature` of the requests sent by Slack
+/// https://api.slack.com/authentication/verifying-requests-from-slack
+pub struct SignatureVerifier {
+    key: hmac::Key,
+}
+
+impl SignatureVerifier {
+    pub fn new(signing_secret: &str) -> Self {
+        SignatureVerifier {
+            key: hmac::Key::new(hmac::HMAC_SHA256, signing_secret.as_bytes()),
+        }
+    }
+
+    pub fn verify(
+        &self,
+        timestamp: &str,
+        signature: &str,
+        body: &[u8],
+        now: u64,
+    ) -> Result<(), SignatureError> {
+        let sent_at = timestamp
+            .parse::<u64>()
+            .map_err(|_| SignatureError::Malformed)?;
+        if now.abs_diff(sent_at) > MAX_REQUEST_AGE_SECONDS {
+            return Err(SignatureError::Expired);
+        }
+        let signature = signature
+            .strip_prefix(&format!("{SIGNATURE_VERSION}="))
+            .and_then(decode_hex)
+            .ok_or(SignatureError::Malformed)?;
+        hmac::verify(&self.key, &signed_content(timestamp, body), &signature)
+            .map_err(|_| SignatureError::Invalid)
+    }
+
+    /// Signature Slack would send for the body
+    pub fn sign(&self, timestamp: &str, body: &[u8]) -> String {
+        let signature = hmac::sign(&self.key, &signed_content(timestamp, body));
+        let hex: String = signature
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        format!("{SIGNATURE_VERSION}={hex}")
+    }
+}
+
+fn signed_content(timestamp: &str, body: &[u8]) -> Vec<u8> {
+    [format!("{SIGNATURE_VERSION}:{timestamp}:").as_bytes(), body].concat()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::slack::signature::{SignatureError, SignatureVerifier};
+
+    #[test]
+    fn it_verifies_slack_signatures() {
+        // Example from the Slack documentation
+        let verifier = SignatureVerifier::new("8f742231b10e8888abcd99yyyzzz85a5");
+        let body = b"token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+        let signature = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+        assert_eq!(verifier.sign("1531420618", body), signature);
+        assert_eq!(
+            verifier.verify("1531420618", signature, body, 1531420618 + 60),
+            Ok(())
+        );
+        assert_eq!(
+            verifier.verify("1531420618", signature, b"text=tampered", 1531420618),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            verifier.verify("1531420618", signature, body, 1531420618 + 3600),
+            Err(SignatureError::Expired)
+        );
+        assert_eq!(
+            verifier.verify("1531420618", "v0=zz", body, 1531420618),
+            Err(SignatureError::Malformed)
+        );
+        assert_eq!(
+            verifier.verify(
+                "1531420618",
+                &signature[..signature.len() - 1],
+                body,
+                1531420618
+            ),
+            Err(SignatureError::Malformed)
+        );
+    }
+}
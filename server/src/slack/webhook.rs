@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::app::core::{Action, Client, Meal};
+use crate::app::session;
+use crate::app::user_action::UserAction;
+use crate::slack::http::SlackChannel;
+use crate::slack::signature::SignatureVerifier;
+
+const USAGE: &str = "/taberando draw lunch|dinner [区], add <店名>, archive, postpone, delete, refresh, manage, api, api revoke, region [国 言語]";
+
+#[derive(Deserialize, Debug)]
+struct SlashCommand {
+    team_id: String,
+    channel_id: String,
+    user_id: String,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct InteractionForm {
+    payload: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Id {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Interaction {
+    #[serde(rename = "type")]
+    interaction_type: String,
+    team: Id,
+    channel: Option<Id>,
+    user: Id,
+    #[serde(default)]
+    actions: Vec<ButtonAction>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ButtonAction {
+    value: Option<String>,
+}
+
+fn client(team_id: &str, channel_id: &str, user_id: &str) -> Client {
+    Client::Slack(SlackChannel {
+        team_id: team_id.to_string(),
+        channel_id: channel_id.to_string(),
+        user_id: Some(user_id.to_string()),
+    })
+}
+
+/// Action of a `/taberando` slash command text
+fn command_action(client: Client, text: &str) -> Option<Action> {
+    let text = text.trim();
+    let (command, argument) = text.split_once(' ').unwrap_or((text, ""));
+    let argument = argument.trim();
+    match (command.to_lowercase().as_str(), argument) {
+        ("draw", "lunch" | "昼") => Some(Action::Draw(client, Meal::Lunch, None, None)),
+        ("draw", "dinner" | "夜") => Some(Action::Draw(client, Meal::Dinner, None, None)),
+        ("draw", argument) => {
+            // A third word is the area to draw from, such as 渋谷区
+            let (meal, area) = argument.split_once(' ')?;
+            let meal = match meal {
+                "lunch" | "昼" => Meal::Lunch,
+                "dinner" | "夜" => Meal::Dinner,
+                _ => return None,
+            };
+            Some(Action::DrawIn(client, meal, area.trim().to_string()))
+        }
+        ("add", name) if !name.is_empty() => Some(Action::Add(
+            client,
+            name.to_string(),
+            vec![Meal::Lunch, Meal::Dinner],
+            None,
+        )),
+        ("archive", "") => Some(Action::ArchiveCurrent(client)),
+        ("postpone", "") => Some(Action::PostponeCurrent(client)),
+        ("delete", "") => Some(Action::RemoveCurrent(client)),
+        ("refresh" | "", "") => Some(Action::Refresh(client)),
+        ("seed", "") => Some(Action::Seed(client)),
+        ("region", argument) => Some(Action::Region(client, argument.to_string())),
+        ("origin", argument) => Some(Action::SavedLocation(client, argument.to_string())),
+        ("whoami", "") => Some(Action::WhoAmI(client)),
+        ("manage", "") => Some(Action::Manage(client)),
+        ("api", "") => Some(Action::IssueApiToken(client)),
+        ("api", "revoke") => Some(Action::RevokeApiTokens(client)),
+        _ => None,
+    }
+}
+
+fn interaction_action(interaction: Interaction) -> Option<Action> {
+    if interaction.interaction_type != "block_actions" {
+        return None;
+    }
+    let client = client(
+        &interaction.team.id,
+        &interaction.channel?.id,
+        &interaction.user.id,
+    );
+    let value = interaction.actions.into_iter().next()?.value?;
+    let user_action = serde_json::from_str::<UserAction>(&value).ok()?;
+    Some(user_action.into_action(client))
+}
+
+fn verified(verifier: &SignatureVerifier, timestamp: &str, signature: &str, body: &Bytes) -> bool {
+    match verifier.verify(timestamp, signature, body, session::now()) {
+        Ok(_) => true,
+        Err(e) => {
+            println!("Rejected Slack request: {e}");
+            false
+        }
+    }
+}
+
+#[allow(opaque_hidden_inferred_bound)]
+pub fn route(
+    tx: Sender<(String, Action)>,
+    verifier: Option<Arc<SignatureVerifier>>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone + Sync + Send {
+    // Slack is only served once a signing secret is configured
+    let with_verifier = warp::any().map(move || verifier.clone()).and_then(
+        |verifier: Option<Arc<SignatureVerifier>>| async move {
+            verifier.ok_or_else(warp::reject::not_found)
+        },
+    );
+    let signed = warp::post()
+        .and(with_verifier)
+        .and(warp::header::<String>("x-slack-request-timestamp"))
+        .and(warp::header::<String>("x-slack-signature"))
+        .and(warp::body::bytes())
+        .and(warp::header::<String>("host"))
+        .and(warp::any().map(move || tx.clone()));
+
+    let commands = warp::path!("slack" / "commands").and(signed.clone()).then(
+        |verifier: Arc<SignatureVerifier>,
+         timestamp: String,
+         signature: String,
+         body: Bytes,
+         host: String,
+         tx: Sender<(String, Action)>| async move {
+            if !verified(&verifier, &timestamp, &signature, &body) {
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+            let Ok(command) = serde_urlencoded::from_bytes::<SlashCommand>(&body) else {
+                return StatusCode::BAD_REQUEST.into_response();
+            };
+            let client = client(&command.team_id, &command.channel_id, &command.user_id);
+            match command_action(client, &command.text) {
+                Some(action) => {
+                    let _ = tx.send((host, action)).await;
+                    // The results are posted to the channel through the Web API
+                    StatusCode::OK.into_response()
+                }
+                None => ephemeral(USAGE),
+            }
+        },
+    );
+    let interactions = warp::path!("slack" / "interactions").and(signed).then(
+        |verifier: Arc<SignatureVerifier>,
+         timestamp: String,
+         signature: String,
+         body: Bytes,
+         host: String,
+         tx: Sender<(String, Action)>| async move {
+            if !verified(&verifier, &timestamp, &signature, &body) {
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+            let interaction = serde_urlencoded::from_bytes::<InteractionForm>(&body)
+                .ok()
+                .and_then(|form| serde_json::from_str::<Interaction>(&form.payload).ok());
+            let Some(interaction) = interaction else {
+                return StatusCode::BAD_REQUEST.into_response();
+            };
+            if let Some(action) = interaction_action(interaction) {
+                let _ = tx.send((host, action)).await;
+            }
+            StatusCode::OK.into_response()
+        },
+    );
+    commands.or(interactions)
+}
+
+fn ephemeral(text: &str) -> Response {
+    warp::reply::json(&serde_json::json!({
+        "response_type": "ephemeral",
+        "text": text,
+    }))
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+    use warp::http::StatusCode;
+
+    use crate::app::core::{Action, Client, Meal};
+    use crate::app::jar::Jar;
+    use crate::app::session;
+    use crate::slack::signature::SignatureVerifier;
+    use crate::slack::webhook::route;
+
+    fn signed_request(path: &str, body: &str) -> warp::test::RequestBuilder {
+        let verifier = SignatureVerifier::new("secret");
+        let timestamp = session::now().to_string();
+        warp::test::request()
+            .method("POST")
+            .path(path)
+            .header("host", "example.com")
+            .header("x-slack-request-timestamp", &timestamp)
+            .header(
+                "x-slack-signature",
+                verifier.sign(&timestamp, body.as_bytes()),
+            )
+            .body(body)
+    }
+
+    #[tokio::test]
+    async fn it_turns_commands_and_buttons_into_actions() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let filter = route(tx, Some(Arc::new(SignatureVerifier::new("secret"))));
+
+        let body = "team_id=T1&channel_id=C1&user_id=U1&command=%2Ftaberando&text=draw+lunch";
+        let response = signed_request("/slack/commands", body).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let (host, action) = rx.recv().await.unwrap();
+        assert_eq!(host, "example.com");
+        let Action::Draw(client, Meal::Lunch, None, None) = action else {
+            panic!("Unexpected {action:?}");
+        };
+        assert_eq!(Jar::from(&client).to_string(), "slack_T1_C1");
+
+        let payload = r#"{"type":"block_actions","team":{"id":"T1"},"channel":{"id":"C1"},"user":{"id":"U1"},"actions":[{"value":"\"archive_action\""}]}"#;
+        let body = serde_urlencoded::to_string([("payload", payload)]).unwrap();
+        let response = signed_request("/slack/interactions", &body)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let (_, action) = rx.recv().await.unwrap();
+        assert!(matches!(action, Action::ArchiveCurrent(Client::Slack(_))));
+
+        let response = signed_request(
+            "/slack/commands",
+            "team_id=T1&channel_id=C1&user_id=U1&text=dance",
+        )
+        .reply(&filter)
+        .await;
+        assert!(String::from_utf8_lossy(response.body()).contains("ephemeral"));
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/slack/commands")
+            .header("host", "example.com")
+            .header("x-slack-request-timestamp", session::now().to_string())
+            .header("x-slack-signature", "v0=00")
+            .body(body)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::app::core::Client;
+use crate::app::response::{Audience, Messenger, Response};
+use crate::http::{ApiError, HttpClient, HttpResult};
+use crate::telegram::render;
+
+const BASE_TELEGRAM_URL: &str = "https://api.telegram.org";
+
+/// Telegram chat, identified by its numeric id kept as text like the other platform ids
+#[derive(Debug)]
+pub struct TelegramChat {
+    pub chat_id: String,
+    pub user_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TelegramResponse {
+    ok: bool,
+    description: Option<String>,
+    #[serde(default)]
+    result: Value,
+}
+
+#[derive(Clone)]
+pub struct TelegramClient {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl TelegramClient {
+    pub fn new(bot_token: &str) -> Self {
+        TelegramClient::with_base_url(bot_token, BASE_TELEGRAM_URL)
+    }
+
+    /// Client of a Bot API served elsewhere, such as a local fake
+    pub fn with_base_url(bot_token: &str, base_url: &str) -> Self {
+        TelegramClient {
+            client: reqwest::Client::new(),
+            // The Bot API takes the token in the path of each method
+            base_url: format!("{}/bot{bot_token}", base_url.trim_end_matches('/')),
+        }
+    }
+
+    pub async fn call(&self, method: &str, body: &Value) -> HttpResult<Value> {
+        let url = format!("{}/{method}", self.base_url);
+        let response: TelegramResponse = self
+            .client
+            .make_json_request(|client| client.post(url).json(body))
+            .await?;
+        if response.ok {
+            Ok(response.result)
+        } else {
+            Err(ApiError::Http {
+                code: 200,
+                message: response.description.unwrap_or_default(),
+            })
+        }
+    }
+}
+
+#[async_trait]
+impl Messenger for TelegramClient {
+    async fn respond(&self, client: &Client, _host: &str, response: Response) {
+        let Client::Telegram(chat) = client else {
+            println!("Cannot answer {client:?} on Telegram");
+            return;
+        };
+        // Bots cannot whisper in groups; private replies go to the chat of the user with the bot,
+        // which only exists once the user started the bot
+        let chat_id = match (response.audience, &chat.user_id) {
+            (Audience::Requester, Some(user_id)) => user_id,
+            (Audience::Requester, None) => {
+                println!("Could not send to a single user for {client:?}");
+                return;
+            }
+            (Audience::Chat, _) => &chat.chat_id,
+        };
+        for reply in &response.replies {
+            let (method, mut message) = render::message(reply);
+            message["chat_id"] = Value::from(chat_id.as_str());
+            if let Err(e) = self.call(method, &message).await {
+                println!("Could not send {reply:?} to {client:?}: {e:?}");
+            }
+        }
+    }
+
+    async fn chat_name(&self, client: &Client) -> HttpResult<String> {
+        let Client::Telegram(chat) = client else {
+            return Err(ApiError::Unknown {
+                message: format!("{client:?} is not a Telegram chat"),
+            });
+        };
+        let info = self
+            .call("getChat", &serde_json::json!({ "chat_id": chat.chat_id }))
+            .await?;
+        // Private chats have no title
+        info["title"]
+            .as_str()
+            .or(info["first_name"].as_str())
+            .map(str::to_string)
+            .ok_or(ApiError::Unknown {
+                message: format!("No name for {client:?}"),
+            })
+    }
+}
+
+#[cfg(test)]
+pub mod fixtures {
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::Value;
+    use warp::Filter;
+
+    /// Local stand-in of the Telegram Bot API recording the calls it receives
+    #[derive(Clone, Default)]
+    pub struct FakeTelegram {
+        pub calls: Arc<Mutex<Vec<(String, Value)>>>,
+    }
+
+    impl FakeTelegram {
+        pub async fn serve(&self) -> SocketAddr {
+            let calls = self.calls.clone();
+            let api = warp::post()
+                .and(warp::path!(String / String))
+                .and(warp::body::json::<Value>())
+                .map(move |_bot: String, method: String, body: Value| {
+                    let reply = match (method.as_str(), body["chat_id"].as_str()) {
+                        ("getChat", Some("-100")) => {
+                            serde_json::json!({ "ok": true, "result": { "title": "lunch" } })
+                        }
+                        ("getChat", _) => {
+                            serde_json::json!({ "ok": false, "description": "chat not found" })
+                        }
+                        _ => serde_json::json!({ "ok": true, "result": {} }),
+                    };
+                    calls.lock().unwrap().push((method, body));
+                    warp::reply::json(&reply)
+                });
+            let (address, server) = warp::serve(api).bind_ephemeral(([127, 0, 0, 1], 0));
+            tokio::spawn(server);
+            address
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::coordinates::Coordinates;
+    use crate::app::core::Client;
+    use crate::app::response::{Choices, Messenger, Reply, Response};
+    use crate::telegram::http::fixtures::FakeTelegram;
+    use crate::telegram::http::{TelegramChat, TelegramClient};
+
+    fn chat(chat_id: &str) -> Client {
+        Client::Telegram(TelegramChat {
+            chat_id: chat_id.to_string(),
+            user_id: Some("42".to_string()),
+        })
+    }
+
+    #[tokio::test]
+    async fn it_sends_responses_to_the_bot_api() {
+        let fake = FakeTelegram::default();
+        let address = fake.serve().await;
+        let telegram = TelegramClient::with_base_url("123:abc", &format!("http://{address}"));
+
+        let mut response = Response::choices("「一蘭」が出ました", Choices::ActiveDraw(None));
+        response.replies.push(Reply::Location {
+            title: "一蘭".to_string(),
+            coordinates: Coordinates {
+                latitude: 35.5,
+                longitude: 139.5,
+            },
+        });
+        telegram
+            .respond(&chat("-100"), "example.com", response)
+            .await;
+        telegram
+            .respond(&chat("-100"), "example.com", Response::text("42").private())
+            .await;
+
+        let calls = fake.calls.lock().unwrap().clone();
+        assert_eq!(calls.len(), 3);
+        let (method, message) = &calls[0];
+        assert_eq!(method, "sendMessage");
+        assert_eq!(message["chat_id"], "-100");
+        assert_eq!(message["text"], "「一蘭」が出ました");
+        let keyboard = &message["reply_markup"]["inline_keyboard"][0];
+        assert_eq!(keyboard.as_array().unwrap().len(), 3);
+        assert_eq!(keyboard[0]["text"], "✓ 完");
+        assert_eq!(keyboard[0]["callback_data"], "\"archive_action\"");
+        let (method, message) = &calls[1];
+        assert_eq!(method, "sendVenue");
+        assert_eq!(message["title"], "一蘭");
+        let (method, message) = &calls[2];
+        assert_eq!(method, "sendMessage");
+        assert_eq!(message["chat_id"], "42");
+
+        assert_eq!(telegram.chat_name(&chat("-100")).await.unwrap(), "lunch");
+        assert!(telegram.chat_name(&chat("-200")).await.is_err());
+    }
+}
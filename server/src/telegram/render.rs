@@ -0,0 +1,81 @@
+use serde_json::{json, Value};
+
+use crate::app::response::Reply;
+use crate::app::user_action::UserAction;
+
+// Longest callback data Telegram accepts, in bytes
+const MAX_CALLBACK_DATA_LENGTH: usize = 64;
+
+// Telegram rejects the whole message over a single callback too long, so such a button is left out
+fn button(action: &UserAction) -> Option<Value> {
+    let callback_data = serde_json::to_string(action).unwrap();
+    if callback_data.len() > MAX_CALLBACK_DATA_LENGTH {
+        println!("Left out the button {callback_data}, too long for Telegram");
+        return None;
+    }
+    Some(json!({
+        "text": action.label(),
+        "callback_data": callback_data,
+    }))
+}
+
+/// Bot API method and body sending the reply, without its chat id
+pub(crate) fn message(reply: &Reply) -> (&'static str, Value) {
+    match reply {
+        Reply::Text(text) => ("sendMessage", json!({ "text": text })),
+        Reply::Choices(text, choices) => {
+            let buttons: Vec<Value> = choices.buttons().iter().filter_map(button).collect();
+            let mut message = json!({ "text": text });
+            if !buttons.is_empty() {
+                message["reply_markup"] = json!({ "inline_keyboard": [buttons] });
+            }
+            ("sendMessage", message)
+        }
+        Reply::Location { title, coordinates } => (
+            "sendVenue",
+            json!({
+                "latitude": coordinates.latitude,
+                "longitude": coordinates.longitude,
+                "title": title,
+                "address": format!("{}, {}", coordinates.latitude, coordinates.longitude),
+            }),
+        ),
+        Reply::Error(error) => ("sendMessage", json!({ "text": format!("Error {error}") })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::coordinates::Coordinates;
+    use crate::app::core::Meal;
+    use crate::app::response::Choices;
+    use crate::telegram::render::{button, MAX_CALLBACK_DATA_LENGTH};
+
+    #[test]
+    fn it_keeps_every_callback_within_the_telegram_limit() {
+        let coordinates = Coordinates {
+            latitude: -89.12345,
+            longitude: -179.1234,
+        };
+        // Saved location names are at most 12 characters, of 3 bytes each in Japanese
+        let names: Vec<String> = (0..4).map(|i| format!("{}{i}", "東".repeat(11))).collect();
+        // Shared places come from the Line map only
+        let choices = [
+            Choices::Welcome,
+            Choices::Idle(Some(coordinates.clone())),
+            Choices::ActiveDraw(Some(coordinates.clone())),
+            Choices::NoShops(Meal::Dinner),
+            Choices::NoShopsClosedBy(Meal::Dinner, coordinates),
+            Choices::Candidates(4),
+            Choices::AwaitingLocation,
+            Choices::SavedLocations(names),
+        ];
+        for choices in choices {
+            for action in choices.buttons() {
+                let button = button(&action).unwrap_or_else(|| panic!("{choices:?} lost a button"));
+                let callback_data = button["callback_data"].as_str().unwrap();
+                assert!(callback_data.len() <= MAX_CALLBACK_DATA_LENGTH);
+            }
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::sync::Arc;
+
+use ring::constant_time::verify_slices_are_equal;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::mpsc::Sender;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use crate::app::core::{Action, Client, Meal};
+use crate::app::user_action::UserAction;
+use crate::telegram::http::TelegramChat;
+
+const USAGE: &str = "/draw lunch|dinner [区], /add <店名>, /archive, /postpone, /delete, /refresh, /manage, /api, /api revoke, /region [国 言語]";
+
+#[derive(Deserialize, Debug)]
+struct Id {
+    id: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Location {
+    latitude: f32,
+    longitude: f32,
+}
+
+#[derive(Deserialize, Debug)]
+struct Message {
+    chat: Id,
+    from: Option<Id>,
+    text: Option<String>,
+    location: Option<Location>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CallbackQuery {
+    id: String,
+    from: Id,
+    message: Option<Message>,
+    data: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatMember {
+    status: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatMemberUpdated {
+    chat: Id,
+    new_chat_member: ChatMember,
+}
+
+#[derive(Deserialize, Debug)]
+struct Update {
+    message: Option<Message>,
+    callback_query: Option<CallbackQuery>,
+    my_chat_member: Option<ChatMemberUpdated>,
+}
+
+fn client(chat: &Id, user: Option<&Id>) -> Client {
+    Client::Telegram(TelegramChat {
+        chat_id: chat.id.to_string(),
+        user_id: user.map(|user| user.id.to_string()),
+    })
+}
+
+/// Action of a bot command such as `/draw lunch` or `/archive@taberando_bot`
+fn command_action(client: Client, text: &str) -> Option<Action> {
+    let text = text.trim();
+    let (command, argument) = text.split_once(' ').unwrap_or((text, ""));
+    // Commands sent in groups are suffixed with the name of the bot
+    let command = command.split('@').next().unwrap_or_default();
+    match (command.to_lowercase().as_str(), argument.trim()) {
+        ("/start", "") => Some(Action::Join(client)),
+        ("/draw", "lunch" | "昼") => Some(Action::Draw(client, Meal::Lunch, None, None)),
+        ("/draw", "dinner" | "夜") => Some(Action::Draw(client, Meal::Dinner, None, None)),
+        ("/draw", argument) => {
+            // A third word is the area to draw from, such as 渋谷区
+            let (meal, area) = argument.split_once(' ')?;
+            let meal = match meal {
+                "lunch" | "昼" => Meal::Lunch,
+                "dinner" | "夜" => Meal::Dinner,
+                _ => return None,
+            };
+            Some(Action::DrawIn(client, meal, area.trim().to_string()))
+        }
+        ("/add", "") => Some(Action::RequestPlaceName(client)),
+        ("/add", name) => Some(Action::Add(
+            client,
+            name.to_string(),
+            vec![Meal::Lunch, Meal::Dinner],
+            None,
+        )),
+        ("/archive", "") => Some(Action::ArchiveCurrent(client)),
+        ("/postpone", "") => Some(Action::PostponeCurrent(client)),
+        ("/delete", "") => Some(Action::RemoveCurrent(client)),
+        ("/refresh", "") => Some(Action::Refresh(client)),
+        ("/seed", "") => Some(Action::Seed(client)),
+        ("/region", argument) => Some(Action::Region(client, argument.to_string())),
+        ("/origin", argument) => Some(Action::SavedLocation(client, argument.to_string())),
+        ("/whoami", "") => Some(Action::WhoAmI(client)),
+        ("/manage", "") => Some(Action::Manage(client)),
+        ("/api", "") => Some(Action::IssueApiToken(client)),
+        ("/api", "revoke") => Some(Action::RevokeApiTokens(client)),
+        _ => None,
+    }
+}
+
+/// Action of an update, along the method call answering the webhook if any
+fn update_action(update: Update) -> (Option<Action>, Option<serde_json::Value>) {
+    if let Some(member) = update.my_chat_member {
+        let client = client(&member.chat, None);
+        let action = match member.new_chat_member.status.as_str() {
+            "member" | "administrator" => Some(Action::Join(client)),
+            "left" | "kicked" => Some(Action::Leave(client)),
+            _ => None,
+        };
+        return (action, None);
+    }
+    if let Some(query) = update.callback_query {
+        // Answering the query stops the progress indicator of the button
+        let answer = json!({ "method": "answerCallbackQuery", "callback_query_id": query.id });
+        let action = query.message.zip(query.data).and_then(|(message, data)| {
+            let user_action = serde_json::from_str::<UserAction>(&data).ok()?;
+            Some(user_action.into_action(client(&message.chat, Some(&query.from))))
+        });
+        return (action, Some(answer));
+    }
+    let Some(message) = update.message else {
+        return (None, None);
+    };
+    let client = client(&message.chat, message.from.as_ref());
+    if let Some(location) = message.location {
+        let action = Action::Location(client, location.latitude, location.longitude, None);
+        return (Some(action), None);
+    }
+    match message.text {
+        Some(text) if text.starts_with('/') => match command_action(client, &text) {
+            Some(action) => (Some(action), None),
+            None => {
+                let usage =
+                    json!({ "method": "sendMessage", "chat_id": message.chat.id, "text": USAGE });
+                (None, Some(usage))
+            }
+        },
+        Some(text) => (Some(Action::Input(client, text)), None),
+        None => (None, None),
+    }
+}
+
+fn verified(secret_token: &str, header: Option<String>) -> bool {
+    let header = header.unwrap_or_default();
+    let verified = verify_slices_are_equal(secret_token.as_bytes(), header.as_bytes()).is_ok();
+    if !verified {
+        println!("Rejected Telegram update with a wrong secret token");
+    }
+    verified
+}
+
+#[allow(opaque_hidden_inferred_bound)]
+pub fn route(
+    tx: Sender<(String, Action)>,
+    secret_token: Option<Arc<String>>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone + Sync + Send {
+    // Telegram is only served once a webhook secret token is configured
+    let with_secret = warp::any().map(move || secret_token.clone()).and_then(
+        |secret_token: Option<Arc<String>>| async move {
+            secret_token.ok_or_else(warp::reject::not_found)
+        },
+    );
+    warp::post()
+        .and(warp::path!("telegram" / "webhook"))
+        .and(with_secret)
+        .and(warp::header::optional::<String>(
+            "x-telegram-bot-api-secret-token",
+        ))
+        .and(warp::body::json::<Update>())
+        .and(warp::header::<String>("host"))
+        .and(warp::any().map(move || tx.clone()))
+        .then(
+            |secret_token: Arc<String>,
+             header: Option<String>,
+             update: Update,
+             host: String,
+             tx: Sender<(String, Action)>| async move {
+                if !verified(&secret_token, header) {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                let (action, answer) = update_action(update);
+                if let Some(action) = action {
+                    let _ = tx.send((host, action)).await;
+                }
+                match answer {
+                    Some(answer) => warp::reply::json(&answer).into_response(),
+                    None => StatusCode::OK.into_response(),
+                }
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+    use warp::http::StatusCode;
+
+    use crate::app::core::{Action, Client, Meal};
+    use crate::app::jar::Jar;
+    use crate::telegram::webhook::route;
+
+    fn update(body: &str) -> warp::test::RequestBuilder {
+        warp::test::request()
+            .method("POST")
+            .path("/telegram/webhook")
+            .header("host", "example.com")
+            .header("x-telegram-bot-api-secret-token", "secret")
+            .body(body)
+    }
+
+    #[tokio::test]
+    async fn it_turns_updates_into_actions() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let filter = route(tx, Some(Arc::new("secret".to_string())));
+
+        let body = r#"{"update_id":1,"message":{"chat":{"id":-100},"from":{"id":42},"text":"/draw@taberando_bot lunch"}}"#;
+        let response = update(body).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let (host, action) = rx.recv().await.unwrap();
+        assert_eq!(host, "example.com");
+        let Action::Draw(client, Meal::Lunch, None, None) = action else {
+            panic!("Unexpected {action:?}");
+        };
+        assert_eq!(Jar::from(&client).to_string(), "telegram_-100");
+
+        let body = r#"{"update_id":2,"message":{"chat":{"id":-100},"from":{"id":42},"location":{"latitude":35.5,"longitude":139.5}}}"#;
+        update(body).reply(&filter).await;
+        let (_, action) = rx.recv().await.unwrap();
+        assert!(matches!(
+            action,
+            Action::Location(Client::Telegram(_), _, _, None)
+        ));
+
+        let body = r#"{"update_id":3,"callback_query":{"id":"q1","from":{"id":42},"message":{"chat":{"id":-100}},"data":"\"archive_action\""}}"#;
+        let response = update(body).reply(&filter).await;
+        assert!(String::from_utf8_lossy(response.body()).contains("answerCallbackQuery"));
+        let (_, action) = rx.recv().await.unwrap();
+        assert!(matches!(
+            action,
+            Action::ArchiveCurrent(Client::Telegram(_))
+        ));
+
+        let body = r#"{"update_id":4,"message":{"chat":{"id":-100},"from":{"id":42},"text":"/draw 夜 渋谷区"}}"#;
+        update(body).reply(&filter).await;
+        let (_, action) = rx.recv().await.unwrap();
+        assert!(matches!(
+            action,
+            Action::DrawIn(_, Meal::Dinner, area) if area == "渋谷区"
+        ));
+
+        let body =
+            r#"{"update_id":5,"message":{"chat":{"id":-100},"from":{"id":42},"text":"/dance"}}"#;
+        let response = update(body).reply(&filter).await;
+        assert!(String::from_utf8_lossy(response.body()).contains("sendMessage"));
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/telegram/webhook")
+            .header("host", "example.com")
+            .header("x-telegram-bot-api-secret-token", "wrong")
+            .body(body)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod hub;
+pub mod render;
+pub mod route;
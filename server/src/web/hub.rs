@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use warp::ws::Message;
+
+use crate::app::core::Client;
+use crate::app::response::{Audience, Messenger, Response};
+use crate::http::{ApiError, HttpResult};
+use crate::web::render;
+
+/// Web chat room, joined through its invite link
+#[derive(Debug)]
+pub struct WebRoom {
+    pub room_id: String,
+    /// Random id of a browser, from its signed session cookie
+    pub user_id: Option<String>,
+}
+
+struct Connection {
+    id: u64,
+    user_id: String,
+    sender: UnboundedSender<Message>,
+}
+
+/// Browsers connected to each room; the replies of a room are pushed to its open sockets
+#[derive(Clone, Default)]
+pub struct WebHub {
+    rooms: Arc<Mutex<HashMap<String, Vec<Connection>>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl WebHub {
+    /// Register a socket of the room; returns its id, its outgoing frames and whether the
+    /// room had no other open socket
+    pub fn connect(&self, room_id: &str, user_id: &str) -> (u64, UnboundedReceiver<Message>, bool) {
+        let (sender, receiver) = unbounded_channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut rooms = self.rooms.lock().unwrap();
+        let connections = rooms.entry(room_id.to_string()).or_default();
+        let first = connections.is_empty();
+        connections.push(Connection {
+            id,
+            user_id: user_id.to_string(),
+            sender,
+        });
+        (id, receiver, first)
+    }
+
+    pub fn disconnect(&self, room_id: &str, id: u64) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(connections) = rooms.get_mut(room_id) {
+            connections.retain(|connection| connection.id != id);
+            if connections.is_empty() {
+                rooms.remove(room_id);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Messenger for WebHub {
+    async fn respond(&self, client: &Client, _host: &str, response: Response) {
+        let Client::Web(room) = client else {
+            println!("Cannot answer {client:?} on the web chat");
+            return;
+        };
+        let rooms = self.rooms.lock().unwrap();
+        let Some(connections) = rooms.get(&room.room_id) else {
+            println!("Nobody is connected to {client:?}");
+            return;
+        };
+        for reply in &response.replies {
+            let frame = render::message(reply).to_string();
+            for connection in connections {
+                let recipient = match response.audience {
+                    Audience::Chat => true,
+                    Audience::Requester => room.user_id.as_ref() == Some(&connection.user_id),
+                };
+                if recipient {
+                    // Closed sockets are removed once their reading loop ends
+                    let _ = connection.sender.send(Message::text(frame.clone()));
+                }
+            }
+        }
+    }
+
+    async fn chat_name(&self, client: &Client) -> HttpResult<String> {
+        match client {
+            Client::Web(room) => Ok(format!("Web {}", room.room_id)),
+            _ => Err(ApiError::Unknown {
+                message: format!("{client:?} is not a web chat room"),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::core::Client;
+    use crate::app::response::{Choices, Messenger, Response};
+    use crate::web::hub::{WebHub, WebRoom};
+
+    fn room(user_id: &str) -> Client {
+        Client::Web(WebRoom {
+            room_id: "R1".to_string(),
+            user_id: Some(user_id.to_string()),
+        })
+    }
+
+    #[tokio::test]
+    async fn it_pushes_replies_to_the_room_sockets() {
+        let hub = WebHub::default();
+        let (_, mut alice, first) = hub.connect("R1", "alice");
+        assert!(first);
+        let (bob_id, mut bob, first) = hub.connect("R1", "bob");
+        assert!(!first);
+
+        let response = Response::choices("「一蘭」が出ました", Choices::ActiveDraw(None));
+        hub.respond(&room("alice"), "example.com", response).await;
+        hub.respond(
+            &room("alice"),
+            "example.com",
+            Response::text("alice").private(),
+        )
+        .await;
+
+        let frame: serde_json::Value =
+            serde_json::from_str(bob.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(frame["type"], "choices");
+        assert_eq!(frame["buttons"].as_array().unwrap().len(), 4);
+        assert!(bob.try_recv().is_err());
+        alice.recv().await.unwrap();
+        let frame = alice.recv().await.unwrap();
+        assert!(frame.to_str().unwrap().contains("alice"));
+
+        hub.disconnect("R1", bob_id);
+        let (_, _, first) = hub.connect("R2", "bob");
+        assert!(first);
+    }
+}
@@ -0,0 +1,52 @@
+use serde_json::{json, Value};
+
+use crate::app::response::{Choices, Reply};
+use crate::app::user_action::UserAction;
+
+// The page asks the browser for its position where Line offers its location picker
+fn offers_location(choices: &Choices) -> bool {
+    matches!(
+        choices,
+        Choices::Welcome
+            | Choices::Idle(_)
+            | Choices::NoShopsClosedBy(_, _)
+            | Choices::AwaitingLocation
+            | Choices::SharedPlace(_, _)
+            | Choices::SavedLocations(_)
+    )
+}
+
+fn button(action: &UserAction) -> Value {
+    json!({
+        "label": action.label(),
+        "action": serde_json::to_string(action).unwrap(),
+    })
+}
+
+/// Frame sent to the browsers of a room
+pub(crate) fn message(reply: &Reply) -> Value {
+    match reply {
+        Reply::Text(text) => json!({ "type": "text", "text": text }),
+        Reply::Choices(text, choices) => {
+            // Places are named in the chat itself, as the page has no add form
+            let mut actions = match choices {
+                Choices::Candidates(_) | Choices::AwaitingLocation => vec![],
+                _ => vec![UserAction::Add],
+            };
+            actions.extend(choices.buttons());
+            json!({
+                "type": "choices",
+                "text": text,
+                "buttons": actions.iter().map(button).collect::<Vec<Value>>(),
+                "location": offers_location(choices),
+            })
+        }
+        Reply::Location { title, coordinates } => json!({
+            "type": "location",
+            "title": title,
+            "latitude": coordinates.latitude,
+            "longitude": coordinates.longitude,
+        }),
+        Reply::Error(error) => json!({ "type": "error", "text": format!("Error {error}") }),
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures::{SinkExt, StreamExt};
+use rand::RngCore;
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+use warp::http::header::SET_COOKIE;
+use warp::http::{HeaderValue, StatusCode, Uri};
+use warp::reply::Response;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::{Filter, Rejection, Reply};
+
+use crate::app::core::{parse_area_draw, parse_region, parse_saved_location, Action, Client};
+use crate::app::link::{LinkError, LinkSigner};
+use crate::app::session;
+use crate::app::user_action::UserAction;
+use crate::line::html::html_file;
+use crate::web::hub::{WebHub, WebRoom};
+
+const SESSION_COOKIE: &str = "taberando_session";
+const SESSION_COOKIE_MAX_AGE_SECONDS: u64 = 365 * 24 * 60 * 60;
+// Rooms a single address may create within the window
+const MAX_ROOMS_PER_ADDRESS: usize = 10;
+const ROOM_QUOTA_WINDOW_SECONDS: u64 = 60 * 60;
+
+#[derive(Deserialize, Debug)]
+struct PageQuery {
+    invite: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SocketQuery {
+    invite: String,
+}
+
+/// Frame sent by the page
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event {
+    Text { text: String },
+    Action { action: String },
+    Location { latitude: f32, longitude: f32 },
+}
+
+/// Rooms recently created from each address, as anybody can create them
+#[derive(Clone, Default)]
+struct RoomQuota(Arc<Mutex<HashMap<String, Vec<u64>>>>);
+
+impl RoomQuota {
+    fn take(&self, address: &str, now: u64) -> bool {
+        let mut created = self.0.lock().unwrap();
+        created.retain(|_, times| {
+            times.retain(|time| time + ROOM_QUOTA_WINDOW_SECONDS > now);
+            !times.is_empty()
+        });
+        let times = created.entry(address.to_string()).or_default();
+        if times.len() >= MAX_ROOMS_PER_ADDRESS {
+            return false;
+        }
+        times.push(now);
+        true
+    }
+}
+
+// Behind a proxy, the address it saw is the last one it appended
+fn client_address(forwarded_for: Option<String>, remote: Option<SocketAddr>) -> String {
+    forwarded_for
+        .as_deref()
+        .and_then(|addresses| addresses.rsplit(',').next())
+        .map(|address| address.trim().to_string())
+        .or_else(|| remote.map(|remote| remote.ip().to_string()))
+        .unwrap_or_default()
+}
+
+fn random_id() -> String {
+    let mut id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    URL_SAFE_NO_PAD.encode(id)
+}
+
+fn client(room_id: &str, user_id: &str) -> Client {
+    Client::Web(WebRoom {
+        room_id: room_id.to_string(),
+        user_id: Some(user_id.to_string()),
+    })
+}
+
+// Same keywords as the Line text messages
+fn event_action(client: Client, event: Event) -> Option<Action> {
+    match event {
+        Event::Text { text } => match text.to_lowercase().trim() {
+            "refresh" | "更新" => Some(Action::Refresh(client)),
+            "whoami" => Some(Action::WhoAmI(client)),
+            "manage" | "管理" => Some(Action::Manage(client)),
+            "api" => Some(Action::IssueApiToken(client)),
+            "api revoke" => Some(Action::RevokeApiTokens(client)),
+            _ => match (
+                parse_area_draw(&text),
+                parse_region(&text),
+                parse_saved_location(&text),
+            ) {
+                (Some((meal, area)), _, _) => Some(Action::DrawIn(client, meal, area)),
+                (None, Some(argument), _) => Some(Action::Region(client, argument)),
+                (None, None, Some(argument)) => Some(Action::SavedLocation(client, argument)),
+                (None, None, None) => Some(Action::Input(client, text)),
+            },
+        },
+        Event::Action { action } => serde_json::from_str::<UserAction>(&action)
+            .ok()
+            .map(|user_action| user_action.into_action(client)),
+        Event::Location {
+            latitude,
+            longitude,
+        } => Some(Action::Location(client, latitude, longitude, None)),
+    }
+}
+
+fn rejected(error: &LinkError) -> Response {
+    println!("Rejected web chat invite: {error}");
+    warp::reply::with_status("招待リンクが無効です。", StatusCode::FORBIDDEN).into_response()
+}
+
+async fn serve_socket(
+    socket: WebSocket,
+    room_id: String,
+    user_id: String,
+    host: String,
+    tx: Sender<(String, Action)>,
+    hub: WebHub,
+    signer: Arc<LinkSigner>,
+) {
+    let (mut outgoing, mut incoming) = socket.split();
+    // Keeps the page url a valid invite for as long as its members come back
+    let invite = serde_json::json!({
+        "type": "invite",
+        "invite": signer.invite(&room_id, session::now()),
+    });
+    if outgoing
+        .send(Message::text(invite.to_string()))
+        .await
+        .is_err()
+    {
+        return;
+    }
+    let (id, mut frames, first) = hub.connect(&room_id, &user_id);
+    if first {
+        // Only the first join of the room greets; later ones merely reactivate its jar
+        let _ = tx
+            .send((host.clone(), Action::Join(client(&room_id, &user_id))))
+            .await;
+    }
+    let forward = tokio::spawn(async move {
+        while let Some(frame) = frames.recv().await {
+            if outgoing.send(frame).await.is_err() {
+                break;
+            }
+        }
+    });
+    while let Some(Ok(message)) = incoming.next().await {
+        // Pings and close frames carry no event
+        let Ok(text) = message.to_str() else {
+            continue;
+        };
+        match serde_json::from_str::<Event>(text) {
+            Ok(event) => {
+                if let Some(action) = event_action(client(&room_id, &user_id), event) {
+                    let _ = tx.send((host.clone(), action)).await;
+                }
+            }
+            Err(e) => println!("Unreadable web chat frame {text:?}: {e:?}"),
+        }
+    }
+    hub.disconnect(&room_id, id);
+    forward.abort();
+}
+
+#[allow(opaque_hidden_inferred_bound)]
+pub fn route(
+    tx: Sender<(String, Action)>,
+    hub: WebHub,
+    signer: Arc<LinkSigner>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone + Sync + Send {
+    let with_signer = warp::any().map(move || signer.clone());
+    let quota = RoomQuota::default();
+    let new_room = warp::get()
+        .and(warp::path!("web"))
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::addr::remote())
+        .and(warp::any().map(move || quota.clone()))
+        .and(with_signer.clone())
+        .map(
+            |forwarded_for: Option<String>,
+             remote: Option<SocketAddr>,
+             quota: RoomQuota,
+             signer: Arc<LinkSigner>| {
+                let address = client_address(forwarded_for, remote);
+                let now = session::now();
+                if !quota.take(&address, now) {
+                    println!("Too many web chat rooms created from {address}");
+                    return StatusCode::TOO_MANY_REQUESTS.into_response();
+                }
+                let invite = signer.invite(&random_id(), now);
+                let uri: Uri = format!("/web/chat?invite={invite}").parse().unwrap();
+                warp::redirect::see_other(uri).into_response()
+            },
+        );
+    let page = warp::get()
+        .and(warp::path!("web" / "chat"))
+        .and(warp::query::<PageQuery>())
+        .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+        .and(with_signer.clone())
+        .then(
+            |query: PageQuery, session: Option<String>, signer: Arc<LinkSigner>| async move {
+                if let Err(e) = signer.verify_invite(&query.invite, session::now()) {
+                    return rejected(&e);
+                }
+                let mut response = html_file("./resources/web/chat.html", str::to_string).await;
+                let signed_in = session.is_some_and(|token| signer.verify_session(&token).is_ok());
+                if !signed_in {
+                    // The user id only tells the members of a room apart
+                    let cookie = format!(
+                        "{SESSION_COOKIE}={}; Path=/web; Max-Age={SESSION_COOKIE_MAX_AGE_SECONDS}; HttpOnly; Secure; SameSite=Lax",
+                        signer.session(&random_id())
+                    );
+                    response
+                        .headers_mut()
+                        .insert(SET_COOKIE, HeaderValue::from_str(&cookie).unwrap());
+                }
+                response
+            },
+        );
+    let socket = warp::path!("web" / "socket")
+        .and(warp::ws())
+        .and(warp::query::<SocketQuery>())
+        .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+        .and(warp::header::<String>("host"))
+        .and(warp::any().map(move || tx.clone()))
+        .and(warp::any().map(move || hub.clone()))
+        .and(with_signer)
+        .map(
+            |ws: Ws,
+             query: SocketQuery,
+             session: Option<String>,
+             host: String,
+             tx: Sender<(String, Action)>,
+             hub: WebHub,
+             signer: Arc<LinkSigner>| {
+                let room_id = match signer.verify_invite(&query.invite, session::now()) {
+                    Ok(room_id) => room_id,
+                    Err(e) => return rejected(&e),
+                };
+                let user_id = match session.map(|token| signer.verify_session(&token)) {
+                    Some(Ok(user_id)) => user_id,
+                    _ => return StatusCode::UNAUTHORIZED.into_response(),
+                };
+                ws.on_upgrade(move |socket| {
+                    serve_socket(socket, room_id, user_id, host, tx, hub, signer)
+                })
+                .into_response()
+            },
+        );
+    new_room.or(page).or(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use crate::app::core::{Action, Client, Meal};
+    use crate::app::jar::Jar;
+    use crate::app::link::LinkSigner;
+    use crate::app::response::{Messenger, Response};
+    use crate::app::session;
+    use crate::web::hub::{WebHub, WebRoom};
+    use crate::web::route::{route, MAX_ROOMS_PER_ADDRESS, SESSION_COOKIE};
+
+    fn signer() -> LinkSigner {
+        LinkSigner::new(b"secret")
+    }
+
+    fn cookie(user_id: &str) -> String {
+        format!("{SESSION_COOKIE}={}", signer().session(user_id))
+    }
+
+    #[tokio::test]
+    async fn it_drives_the_jar_over_a_socket() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let hub = WebHub::default();
+        let filter = route(tx, hub.clone(), Arc::new(signer()));
+        let invite = signer().invite("R1", session::now());
+
+        let mut socket = warp::test::ws()
+            .path(&format!("/web/socket?invite={invite}"))
+            .header("host", "example.com")
+            .header("cookie", cookie("alice"))
+            .handshake(filter.clone())
+            .await
+            .unwrap();
+        let frame = socket.recv().await.unwrap();
+        let frame: serde_json::Value = serde_json::from_str(frame.to_str().unwrap()).unwrap();
+        assert_eq!(frame["type"], "invite");
+        assert_eq!(
+            signer().verify_invite(frame["invite"].as_str().unwrap(), session::now()),
+            Ok("R1".to_string())
+        );
+        let (host, action) = rx.recv().await.unwrap();
+        assert_eq!(host, "example.com");
+        let Action::Join(client) = action else {
+            panic!("Unexpected {action:?}");
+        };
+        assert_eq!(Jar::from(&client).to_string(), "web_R1");
+
+        socket
+            .send_text(r#"{"type":"action","action":"\"lunch_action\""}"#)
+            .await;
+        let (_, action) = rx.recv().await.unwrap();
+        assert!(matches!(
+            action,
+            Action::Draw(Client::Web(_), Meal::Lunch, None, None)
+        ));
+        socket
+            .send_text(r#"{"type":"location","latitude":35.5,"longitude":139.5}"#)
+            .await;
+        let (_, action) = rx.recv().await.unwrap();
+        let Action::Location(Client::Web(room), _, _, _) = &action else {
+            panic!("Unexpected {action:?}");
+        };
+        assert_eq!(room.user_id.as_deref(), Some("alice"));
+        hub.respond(
+            &Client::Web(WebRoom {
+                room_id: "R1".to_string(),
+                user_id: Some("alice".to_string()),
+            }),
+            "example.com",
+            Response::text("ok").private(),
+        )
+        .await;
+        let frame = socket.recv().await.unwrap();
+        let frame: serde_json::Value = serde_json::from_str(frame.to_str().unwrap()).unwrap();
+        assert_eq!(frame["text"], "ok");
+    }
+
+    #[tokio::test]
+    async fn it_rejects_forged_invites_and_sessions() {
+        let (tx, _rx) = mpsc::channel(4);
+        let filter = route(tx, WebHub::default(), Arc::new(signer()));
+        let invite = signer().invite("R1", session::now());
+        let forged = LinkSigner::new(b"other").invite("R1", session::now());
+        let expired = signer().invite("R1", 0);
+
+        for (invite, cookie) in [
+            (forged.as_str(), cookie("alice")),
+            (expired.as_str(), cookie("alice")),
+            (invite.as_str(), format!("{SESSION_COOKIE}=alice")),
+        ] {
+            let rejected = warp::test::ws()
+                .path(&format!("/web/socket?invite={invite}"))
+                .header("host", "example.com")
+                .header("cookie", cookie)
+                .handshake(filter.clone())
+                .await;
+            assert!(rejected.is_err());
+        }
+        let rejected = warp::test::ws()
+            .path(&format!("/web/socket?invite={invite}&user=alice"))
+            .header("host", "example.com")
+            .handshake(filter)
+            .await;
+        assert!(rejected.is_err());
+    }
+
+    #[tokio::test]
+    async fn it_gives_a_session_to_new_browsers() {
+        let (tx, _rx) = mpsc::channel(4);
+        let filter = route(tx, WebHub::default(), Arc::new(signer()));
+        let invite = signer().invite("R1", session::now());
+
+        let response = warp::test::request()
+            .path(&format!("/web/chat?invite={invite}"))
+            .reply(&filter)
+            .await;
+        let set_cookie = response.headers()["set-cookie"].to_str().unwrap();
+        let token = set_cookie
+            .strip_prefix(&format!("{SESSION_COOKIE}="))
+            .and_then(|cookie| cookie.split(';').next())
+            .unwrap();
+        assert!(signer().verify_session(token).is_ok());
+        assert!(set_cookie.contains("HttpOnly"));
+
+        let response = warp::test::request()
+            .path(&format!("/web/chat?invite={invite}"))
+            .header("cookie", cookie("alice"))
+            .reply(&filter)
+            .await;
+        assert!(response.headers().get("set-cookie").is_none());
+    }
+
+    #[tokio::test]
+    async fn it_limits_the_rooms_created_from_an_address() {
+        let (tx, _rx) = mpsc::channel(4);
+        let filter = route(tx, WebHub::default(), Arc::new(signer()));
+        let create = |address: &'static str| {
+            warp::test::request()
+                .path("/web")
+                .header("x-forwarded-for", format!("10.0.0.1, {address}"))
+                .reply(&filter)
+        };
+        for _ in 0..MAX_ROOMS_PER_ADDRESS {
+            assert_eq!(create("192.0.2.1").await.status(), 303);
+        }
+        assert_eq!(create("192.0.2.1").await.status(), 429);
+        assert_eq!(create("192.0.2.2").await.status(), 303);
+    }
+}